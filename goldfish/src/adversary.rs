@@ -1,9 +1,24 @@
+use std::collections::HashMap;
+
+use crate::das;
+use crate::goldfish_blockvote;
+use crate::goldfish_message;
 use crate::goldfish_type;
 use crate::goldfish_validator;
 use crate::network;
 
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Corrupted validators do nothing
+    Crash,
+    /// Corrupted validators follow the protocol but withhold the chunks of their blocks (see --das)
+    Withhold,
+}
+
 pub trait Adversary<V: goldfish_validator::HonestValidator> {
-    fn new() -> Self;
+    fn new() -> Self
+    where
+        Self: Sized;
     fn corrupt(&mut self, validator: V);
     fn step(
         &mut self,
@@ -40,3 +55,81 @@ impl<V: goldfish_validator::HonestValidator> Adversary<V> for CrashFaults<V> {
         inboxes[my_inbox].collect_inbox();
     }
 }
+
+// Corrupted validators run the protocol, but publish fewer than k of the 2k
+// chunks of their blocks, so the payloads cannot be reconstructed. They answer
+// samples of withheld chunks only among themselves.
+pub struct WithholdingAdversary<V: goldfish_validator::HonestValidator> {
+    validators: Vec<V>,
+    withheld: HashMap<goldfish_type::Hash, Vec<das::Sample>>,
+}
+
+struct WithholdingTransport<'a, 'b> {
+    inner: network::SimulationTransport<'a>,
+    msgs: Vec<goldfish_message::Message>,
+    withheld: &'b mut HashMap<goldfish_type::Hash, Vec<das::Sample>>,
+}
+
+impl<'a, 'b> network::Transport for WithholdingTransport<'a, 'b> {
+    fn broadcast(&mut self, msg: &goldfish_message::Message) {
+        self.inner.broadcast(msg);
+    }
+
+    // the corrupted validators share an inbox
+    fn collect_inbox(&mut self) -> Vec<goldfish_message::Message> {
+        self.msgs.clone()
+    }
+
+    fn publish_block(&mut self, b: &goldfish_blockvote::Block) {
+        self.inner.publish_block(b);
+    }
+
+    fn fetch_block(&mut self, h: &goldfish_type::Hash) -> Option<goldfish_blockvote::Block> {
+        self.inner.fetch_block(h)
+    }
+
+    fn publish_chunks(&mut self, b: &goldfish_blockvote::Block, samples: Vec<das::Sample>) {
+        let k = samples.len() / 2;
+        self.inner
+            .publish_chunks(b, samples.iter().take(k - 1).cloned().collect());
+        self.withheld.insert(b.digest(), samples);
+    }
+
+    fn sample_chunk(&mut self, h: &goldfish_type::Hash, index: usize) -> Option<das::Sample> {
+        match self.withheld.get(h) {
+            Some(samples) => samples.get(index).cloned(),
+            None => self.inner.sample_chunk(h, index),
+        }
+    }
+}
+
+impl<V: goldfish_validator::HonestValidator> Adversary<V> for WithholdingAdversary<V> {
+    fn new() -> Self {
+        Self {
+            validators: Vec::new(),
+            withheld: HashMap::new(),
+        }
+    }
+
+    fn corrupt(&mut self, validator: V) {
+        self.validators.push(validator);
+    }
+
+    fn step(
+        &mut self,
+        lotteries: &goldfish_type::Lotteries,
+        r: usize,
+        inboxes: &mut Vec<network::SimulationInbox>,
+        my_inbox: usize,
+    ) {
+        let msgs = inboxes[my_inbox].collect_inbox();
+        for validator in self.validators.iter_mut() {
+            let mut transport = WithholdingTransport {
+                inner: network::SimulationTransport::new(inboxes, my_inbox),
+                msgs: msgs.clone(),
+                withheld: &mut self.withheld,
+            };
+            validator.step(lotteries, r, &mut transport);
+        }
+    }
+}
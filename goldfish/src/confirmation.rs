@@ -0,0 +1,108 @@
+use clap::ValueEnum as _;
+use std::collections::VecDeque;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// (3/4 + eps/2) of the expected votes of all validators in the PKI
+    Pki,
+    /// (3/4 + eps/2) of the expected votes of the awake validators, estimated from recent slots
+    Participation,
+    /// A configurable fraction of the expected votes of all validators in the PKI
+    Fraction,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConfirmationConfig {
+    /// Rule for the quorum of votes needed for fast confirmation
+    #[arg(long, value_enum, default_value_t = RuleKind::Pki)]
+    pub confirm_fast_rule: RuleKind,
+
+    /// Quorum as a fraction of the expected votes (with --confirm-fast-rule fraction)
+    #[arg(long, default_value_t = 0.75)]
+    pub confirm_fast_quorum: f64,
+
+    /// Number of recent slots over which participation is estimated (with --confirm-fast-rule participation)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 8)]
+    pub confirm_fast_window: u64,
+}
+
+impl ConfirmationConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        vec![
+            "--confirm-fast-rule".to_string(),
+            self.confirm_fast_rule
+                .to_possible_value()
+                .unwrap()
+                .get_name()
+                .to_string(),
+            "--confirm-fast-quorum".to_string(),
+            self.confirm_fast_quorum.to_string(),
+            "--confirm-fast-window".to_string(),
+            self.confirm_fast_window.to_string(),
+        ]
+    }
+
+    pub fn build(&self, eps: f64) -> Box<dyn ConfirmationRule> {
+        match self.confirm_fast_rule {
+            RuleKind::Pki => Box::new(PkiRule { eps }),
+            RuleKind::Participation => Box::new(ParticipationRule {
+                eps,
+                window: self.confirm_fast_window as usize,
+                recent_votes: VecDeque::new(),
+            }),
+            RuleKind::Fraction => Box::new(FractionRule {
+                fraction: self.confirm_fast_quorum,
+            }),
+        }
+    }
+}
+
+/// Decides how many votes of a slot a block needs to be fast confirmed
+pub trait ConfirmationRule: std::fmt::Debug + Send + Sync {
+    /// Called once per slot, with the PKI size n, the vote lottery success
+    /// probability, and the number of votes of the slot at hand
+    fn quorum(&mut self, n: usize, p_vote: f64, votes_in_slot: usize) -> usize;
+}
+
+// the rule of the Goldfish paper, assuming all validators are awake
+#[derive(Debug, Clone)]
+pub struct PkiRule {
+    eps: f64,
+}
+
+impl ConfirmationRule for PkiRule {
+    fn quorum(&mut self, n: usize, p_vote: f64, _votes_in_slot: usize) -> usize {
+        ((n as f64) * (0.75 + 0.5 * self.eps) * p_vote).ceil() as usize
+    }
+}
+
+// the expected votes are estimated by the largest number of votes in any of
+// the recent slots, so that a single slot with few votes does not lower the quorum
+#[derive(Debug, Clone)]
+pub struct ParticipationRule {
+    eps: f64,
+    window: usize,
+    recent_votes: VecDeque<usize>,
+}
+
+impl ConfirmationRule for ParticipationRule {
+    fn quorum(&mut self, _n: usize, _p_vote: f64, votes_in_slot: usize) -> usize {
+        self.recent_votes.push_back(votes_in_slot);
+        while self.recent_votes.len() > self.window {
+            self.recent_votes.pop_front();
+        }
+        let expected = self.recent_votes.iter().copied().max().unwrap().max(1);
+        ((expected as f64) * (0.75 + 0.5 * self.eps)).ceil() as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FractionRule {
+    fraction: f64,
+}
+
+impl ConfirmationRule for FractionRule {
+    fn quorum(&mut self, n: usize, p_vote: f64, _votes_in_slot: usize) -> usize {
+        ((n as f64) * self.fraction * p_vote).ceil() as usize
+    }
+}
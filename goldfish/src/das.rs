@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+use crate::ghash;
+use crate::goldfish_blockvote;
+use crate::goldfish_type;
+use crate::merkle;
+use crate::network;
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct DasConfig {
+    /// Erasure-code block payloads and accept blocks only after sampling them; proposals
+    /// are then compact, and payloads are fetched after voting, for execution
+    #[arg(long)]
+    pub das: bool,
+
+    /// Number of data chunks per payload (extended to twice as many)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..=128), default_value_t = 64)]
+    pub das_chunks: u64,
+
+    /// Number of chunks each validator samples per block
+    #[arg(long, default_value_t = 16)]
+    pub das_samples: usize,
+}
+
+impl DasConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--das-chunks".to_string(),
+            self.das_chunks.to_string(),
+            "--das-samples".to_string(),
+            self.das_samples.to_string(),
+        ];
+        if self.das {
+            args.push("--das".to_string());
+        }
+        args
+    }
+
+    pub fn n_chunks(&self) -> usize {
+        2 * self.das_chunks as usize
+    }
+}
+
+// GF(2^8) with the primitive polynomial x^8 + x^4 + x^3 + x^2 + 1
+struct Gf256 {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+fn gf() -> &'static Gf256 {
+    static GF: OnceLock<Gf256> = OnceLock::new();
+    GF.get_or_init(|| {
+        let mut exp = [0u8; 512];
+        let mut log = [0u8; 256];
+        let mut x: u16 = 1;
+        for (i, e) in exp.iter_mut().take(255).enumerate() {
+            *e = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11d;
+            }
+        }
+        for i in 255..512 {
+            exp[i] = exp[i - 255];
+        }
+        Gf256 { exp, log }
+    })
+}
+
+impl Gf256 {
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+    }
+
+    fn div(&self, a: u8, b: u8) -> u8 {
+        assert!(b != 0);
+        if a == 0 {
+            return 0;
+        }
+        self.exp[self.log[a as usize] as usize + 255 - self.log[b as usize] as usize]
+    }
+}
+
+// Evaluates, byte by byte, the polynomials of degree < points.len() through
+// the given (x, chunk) points at each of the targets (Lagrange interpolation).
+fn interpolate(points: &[(u8, &[u8])], targets: &[u8]) -> Vec<Vec<u8>> {
+    let gf = gf();
+    let len = points[0].1.len();
+    targets
+        .iter()
+        .map(|&x| {
+            let mut out = vec![0u8; len];
+            for (i, (x_i, chunk)) in points.iter().enumerate() {
+                let mut coef = 1u8;
+                for (m, (x_m, _)) in points.iter().enumerate() {
+                    if m != i {
+                        coef = gf.mul(coef, gf.div(x ^ x_m, x_i ^ x_m));
+                    }
+                }
+                if coef == 0 {
+                    continue;
+                }
+                for (o, c) in out.iter_mut().zip(chunk.iter()) {
+                    *o ^= gf.mul(coef, *c);
+                }
+            }
+            out
+        })
+        .collect()
+}
+
+// Systematic Reed-Solomon code: data chunk i is the evaluation at x = i, and the
+// n - k parity chunks are the evaluations at x = k..n, so any k chunks suffice.
+fn encode_chunks(data: &[u8], k: usize, n: usize) -> Vec<Vec<u8>> {
+    let chunk_size = ((data.len() + k - 1) / k).max(1);
+    let mut chunks: Vec<Vec<u8>> = (0..k)
+        .map(|i| {
+            let mut chunk = vec![0u8; chunk_size];
+            let lo = (i * chunk_size).min(data.len());
+            let hi = ((i + 1) * chunk_size).min(data.len());
+            chunk[..hi - lo].copy_from_slice(&data[lo..hi]);
+            chunk
+        })
+        .collect();
+    let points: Vec<(u8, &[u8])> = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i as u8, c.as_slice()))
+        .collect();
+    let targets: Vec<u8> = (k..n).map(|x| x as u8).collect();
+    let parity = interpolate(&points, &targets);
+    chunks.extend(parity);
+    chunks
+}
+
+/// Recovers the k data chunks from any k of the n chunks
+#[allow(dead_code)]
+pub fn decode_chunks(chunks: &[(usize, Vec<u8>)], k: usize) -> Option<Vec<Vec<u8>>> {
+    if chunks.len() < k {
+        return None;
+    }
+    let points: Vec<(u8, &[u8])> = chunks[..k]
+        .iter()
+        .map(|(i, c)| (*i as u8, c.as_slice()))
+        .collect();
+    let targets: Vec<u8> = (0..k).map(|x| x as u8).collect();
+    Some(interpolate(&points, &targets))
+}
+
+fn leaf_hash(index: usize, chunk: &[u8]) -> goldfish_type::Hash {
+    let mut hasher = ghash::Ghasher::new();
+    hasher.update(b"chunk");
+    hasher.update(&index.to_le_bytes());
+    hasher.update(chunk);
+    hasher.into()
+}
+
+/// A chunk of an erasure-coded payload with its Merkle proof against the data root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sample {
+    index: usize,
+    #[serde(with = "serde_bytes")]
+    chunk: Vec<u8>,
+    proof: Vec<goldfish_type::Hash>,
+}
+
+impl Sample {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize
+    }
+
+    pub fn verify(&self, data_root: &goldfish_type::Hash, n: usize) -> bool {
+        merkle::verify(
+            data_root,
+            n,
+            self.index,
+            leaf_hash(self.index, &self.chunk),
+            &self.proof,
+        )
+    }
+}
+
+/// Erasure-codes the payload into n = 2k chunks; returns the data root and all samples
+pub fn encode(
+    payload: &goldfish_blockvote::Payload,
+    config: &DasConfig,
+) -> (goldfish_type::Hash, Vec<Sample>) {
+    let k = config.das_chunks as usize;
+    let n = config.n_chunks();
+    let chunks = encode_chunks(&bincode::serialize(payload).unwrap(), k, n);
+    let levels = merkle::levels(
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, c)| leaf_hash(i, c))
+            .collect(),
+    );
+    let data_root = merkle::root(&levels);
+    let samples = chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| Sample {
+            index,
+            chunk,
+            proof: merkle::proof(&levels, index),
+        })
+        .collect();
+    (data_root, samples)
+}
+
+// Per-validator availability checks
+#[derive(Debug)]
+pub struct Sampler {
+    config: DasConfig,
+    // blocks that passed sampling, and the last round in which sampling failed
+    available: HashSet<goldfish_type::Hash>,
+    unavailable: HashMap<goldfish_type::Hash, usize>,
+    // blocks whose payload was checked against their data root
+    coded: HashSet<goldfish_type::Hash>,
+}
+
+impl Sampler {
+    pub fn new(config: DasConfig) -> Self {
+        Self {
+            config,
+            available: HashSet::new(),
+            unavailable: HashMap::new(),
+            coded: HashSet::new(),
+        }
+    }
+
+    pub fn config(&self) -> &DasConfig {
+        &self.config
+    }
+
+    // whether the payload of block h is available, by sampling random chunks (at most once per round)
+    pub fn check(
+        &mut self,
+        h: &goldfish_type::Hash,
+        data_root: &goldfish_type::Hash,
+        r: usize,
+        transport: &mut dyn network::Transport,
+    ) -> bool {
+        if !self.config.das || self.available.contains(h) {
+            return true;
+        }
+        if self.unavailable.get(h) == Some(&r) {
+            return false;
+        }
+
+        let n = self.config.n_chunks();
+        let indices =
+            rand::seq::index::sample(&mut rand::thread_rng(), n, self.config.das_samples.min(n));
+        for i in indices {
+            match transport.sample_chunk(h, i) {
+                Some(sample) if sample.verify(data_root, n) => {}
+                _ => {
+                    log::info!("r={} UNAVAILABLE block {:?} (chunk {})", r, h, i);
+                    self.unavailable.insert(h.clone(), r);
+                    return false;
+                }
+            }
+        }
+
+        self.unavailable.remove(h);
+        self.available.insert(h.clone());
+        true
+    }
+
+    // whether the payload of block h is the one erasure-coded under its data root;
+    // the payload must match the header of h, as the result is cached for h
+    pub fn check_data_root(
+        &mut self,
+        h: &goldfish_type::Hash,
+        data_root: &goldfish_type::Hash,
+        payload: &goldfish_blockvote::Payload,
+    ) -> bool {
+        if !self.config.das || self.coded.contains(h) {
+            return true;
+        }
+        if encode(payload, &self.config).0 != *data_root {
+            return false;
+        }
+        self.coded.insert(h.clone());
+        true
+    }
+    // with DAS, a block only counts once its payload is known to be available, and a
+    // payload at hand must be the one erasure-coded under the data root
+    pub fn check_data(
+        &mut self,
+        h: &goldfish_type::Hash,
+        data_root: &goldfish_type::Hash,
+        payload: Option<&goldfish_blockvote::Payload>,
+        r: usize,
+        transport: &mut dyn network::Transport,
+    ) -> goldfish_type::ValidationResult {
+        if let Some(payload) = payload {
+            if !self.check_data_root(h, data_root, payload) {
+                return goldfish_type::ValidationResult::Invalid(
+                    goldfish_type::ValidationError::BadDataRoot,
+                );
+            }
+        }
+        if !self.check(h, data_root, r, transport) {
+            return goldfish_type::ValidationResult::Unknown;
+        }
+        goldfish_type::ValidationResult::Valid
+    }
+}
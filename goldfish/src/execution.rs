@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ghash;
+use crate::goldfish_blockvote;
+use crate::goldfish_type;
+
+/// Deterministic state transition applied to the blocks of a ledger, in order
+pub trait StateMachine: Default {
+    /// Whatever is needed to revert one applied block
+    type Undo: std::fmt::Debug;
+
+    fn apply(&mut self, b: &goldfish_blockvote::Block) -> Self::Undo;
+    fn rollback(&mut self, undo: Self::Undo);
+    fn state_root(&self) -> goldfish_type::Hash;
+}
+
+// balance of accounts that have not been touched yet
+pub const GENESIS_BALANCE: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Self {
+            balance: GENESIS_BALANCE,
+            nonce: 0,
+        }
+    }
+}
+
+// Account balances keyed by transaction sender. A transaction is skipped (but
+// stays in the ledger) if its nonce was already used or the sender cannot
+// cover the transfer.
+#[derive(Debug, Clone, Default)]
+pub struct AccountState {
+    accounts: BTreeMap<u64, Account>,
+}
+
+impl AccountState {
+    pub fn get(&self, id: u64) -> Account {
+        self.accounts.get(&id).copied().unwrap_or_default()
+    }
+
+    fn set(&mut self, id: u64, account: Account, undo: &mut Vec<(u64, Option<Account>)>) {
+        undo.push((id, self.accounts.insert(id, account)));
+    }
+}
+
+impl StateMachine for AccountState {
+    // previous value of every account write, in order
+    type Undo = Vec<(u64, Option<Account>)>;
+
+    fn apply(&mut self, b: &goldfish_blockvote::Block) -> Self::Undo {
+        let mut undo = Vec::new();
+        for tx in b.txs() {
+            let mut sender = self.get(tx.sender());
+            if tx.nonce() < sender.nonce {
+                continue;
+            }
+            let (recipient, amount) = tx.transfer().unwrap_or((tx.sender(), 0));
+            if amount > sender.balance {
+                continue;
+            }
+
+            sender.nonce = tx.nonce() + 1;
+            sender.balance -= amount;
+            self.set(tx.sender(), sender, &mut undo);
+            let mut recipient_account = self.get(recipient);
+            recipient_account.balance = recipient_account.balance.saturating_add(amount);
+            self.set(recipient, recipient_account, &mut undo);
+        }
+        undo
+    }
+
+    fn rollback(&mut self, undo: Self::Undo) {
+        for (id, account) in undo.into_iter().rev() {
+            match account {
+                Some(account) => self.accounts.insert(id, account),
+                None => self.accounts.remove(&id),
+            };
+        }
+    }
+
+    fn state_root(&self) -> goldfish_type::Hash {
+        let mut hasher = ghash::Ghasher::new();
+        hasher.update(b"state");
+        hasher.update(&self.accounts.len().to_le_bytes());
+        for (id, account) in self.accounts.iter() {
+            hasher.update(&id.to_le_bytes());
+            hasher.update(&account.balance.to_le_bytes());
+            hasher.update(&account.nonce.to_le_bytes());
+        }
+        hasher.into()
+    }
+}
+
+/// Keeps a state machine in sync with a (possibly reorging) ledger tip
+#[derive(Debug)]
+pub struct Executor<S: StateMachine> {
+    state: S,
+    tip: goldfish_type::Hash,
+    // executed chain, oldest first: block, its undo log, and the state root after it
+    applied: Vec<(goldfish_type::Hash, S::Undo, goldfish_type::Hash)>,
+    // height of the last block forgotten from the executed chain, as it was pruned
+    height_pruned: usize,
+    rolled_back: usize,
+}
+
+impl<S: StateMachine> Default for Executor<S> {
+    fn default() -> Self {
+        Self {
+            state: S::default(),
+            tip: goldfish_blockvote::Block::default().digest(),
+            applied: Vec::new(),
+            height_pruned: 0,
+            rolled_back: 0,
+        }
+    }
+}
+
+impl<S: StateMachine> Executor<S> {
+    pub fn advance(&mut self, bvtree: &goldfish_blockvote::BvTree, tip: &goldfish_type::Hash) {
+        if *tip == self.tip {
+            return;
+        }
+
+        // walk back from the new tip until hitting the executed chain,
+        // rolling back executed blocks that are not on the way
+        let mut blocks = Vec::new();
+        let mut h = tip.clone();
+        let mut height = bvtree.get_block_height(tip);
+        loop {
+            if height <= self.height_pruned + self.applied.len() {
+                while self.height_pruned + self.applied.len() > height {
+                    self.rollback_one();
+                }
+                if height <= self.height_pruned
+                    || self.applied[height - self.height_pruned - 1].0 == h
+                {
+                    break;
+                }
+                self.rollback_one();
+            }
+            let b = bvtree.ancestors(&h).next().unwrap();
+            blocks.push(b);
+            h = bvtree.parent(&h);
+            height = bvtree.get_block_height(&h);
+        }
+
+        for b in blocks.into_iter().rev() {
+            let undo = self.state.apply(b);
+            self.applied
+                .push((b.digest(), undo, self.state.state_root()));
+        }
+        self.tip = tip.clone();
+    }
+
+    // forgets the undo logs of the blocks below the anchor, which are never rolled back
+    pub fn prune(&mut self, bvtree: &goldfish_blockvote::BvTree) {
+        let height = bvtree.get_block_height(&bvtree.anchor());
+        if height <= self.height_pruned + 1 {
+            return;
+        }
+        let i = height - self.height_pruned - 1;
+        if self.applied.get(i).map(|(h, _, _)| h) != Some(&bvtree.anchor()) {
+            return;
+        }
+        self.applied.drain(..i);
+        self.height_pruned = height - 1;
+    }
+
+    fn rollback_one(&mut self) {
+        let (_, undo, _) = self.applied.pop().unwrap();
+        self.state.rollback(undo);
+        self.rolled_back += 1;
+    }
+
+    pub fn tip(&self) -> &goldfish_type::Hash {
+        &self.tip
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn state_root(&self) -> goldfish_type::Hash {
+        match self.applied.last() {
+            Some((_, _, root)) => root.clone(),
+            None => self.state.state_root(),
+        }
+    }
+
+    /// Number of blocks reverted because of reorgs so far
+    pub fn rolled_back(&self) -> usize {
+        self.rolled_back
+    }
+
+    /// (block, state root after it) along the executed chain not yet pruned, oldest first
+    pub fn roots(&self) -> impl Iterator<Item = (&goldfish_type::Hash, &goldfish_type::Hash)> {
+        self.applied.iter().map(|(h, _, root)| (h, root))
+    }
+}
+
+/// Compares the state roots that executors report after each block they have
+/// executed. Returns the number of distinct blocks compared and those for
+/// which executors disagree.
+pub fn check_state_roots<'a, S: StateMachine + 'a>(
+    executors: impl IntoIterator<Item = &'a Executor<S>>,
+) -> (usize, Vec<goldfish_type::Hash>) {
+    let mut roots: HashMap<&goldfish_type::Hash, &goldfish_type::Hash> = HashMap::new();
+    let mut mismatches = Vec::new();
+    for executor in executors {
+        for (h, root) in executor.roots() {
+            match roots.get(h) {
+                Some(root_) if *root_ != root => mismatches.push(h.clone()),
+                Some(_) => {}
+                None => {
+                    roots.insert(h, root);
+                }
+            }
+        }
+    }
+    mismatches.sort_by_key(|h| h.as_slice());
+    mismatches.dedup();
+    (roots.len(), mismatches)
+}
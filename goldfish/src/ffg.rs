@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::ghash;
+use crate::goldfish_blockvote;
+use crate::goldfish_type;
+use crate::{sig, sig::HashingBytes as _, sig::Scheme};
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct FfgConfig {
+    /// Run the Casper FFG finality gadget on top of the available ledger
+    #[arg(long)]
+    pub ffg: bool,
+
+    /// Number of slots per FFG epoch
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 8)]
+    pub ffg_epoch_slots: u64,
+}
+
+impl FfgConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--ffg-epoch-slots".to_string(),
+            self.ffg_epoch_slots.to_string(),
+        ];
+        if self.ffg {
+            args.push("--ffg".to_string());
+        }
+        args
+    }
+
+    pub fn epoch(&self, t: goldfish_type::Slot) -> u64 {
+        t / self.ffg_epoch_slots
+    }
+
+    // the block of a checkpoint is the latest one as of the first slot of its epoch
+    fn is_checkpoint_slot(&self, t: goldfish_type::Slot, epoch: u64) -> bool {
+        t <= epoch.saturating_mul(self.ffg_epoch_slots)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub h: goldfish_type::Hash,
+    pub epoch: u64,
+}
+
+impl Checkpoint {
+    pub fn genesis() -> Self {
+        Self {
+            h: goldfish_blockvote::Block::default().digest(),
+            epoch: 0,
+        }
+    }
+
+    fn digest_hasher_update(&self, hasher: &mut ghash::Ghasher) {
+        hasher.update(self.h.as_bytes());
+        hasher.update(&self.epoch.to_ne_bytes());
+    }
+}
+
+// Unlike Goldfish votes, every validator casts an FFG vote once per epoch (no lottery)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FfgVote {
+    ticket: goldfish_type::Ticket,
+    source: Checkpoint,
+    target: Checkpoint,
+    sigma: <goldfish_type::Sigs as sig::Scheme>::Sig,
+}
+
+impl FfgVote {
+    pub fn digest(&self) -> goldfish_type::Hash {
+        let mut hasher = ghash::Ghasher::new();
+        self.digest_hasher_update(&mut hasher);
+        hasher.into()
+    }
+
+    pub fn digest_hasher_update(&self, hasher: &mut ghash::Ghasher) {
+        hasher.update(b"ffgvote");
+        hasher.update(&self.ticket.0.to_ne_bytes());
+        hasher.update(&self.ticket.1.to_ne_bytes());
+        self.inner_digest_hasher_update(hasher);
+        hasher.update(&self.sigma.as_bytes_for_hashing());
+    }
+
+    fn inner_digest(&self) -> goldfish_type::Hash {
+        let mut hasher = ghash::Ghasher::new();
+        self.inner_digest_hasher_update(&mut hasher);
+        hasher.into()
+    }
+
+    fn inner_digest_hasher_update(&self, hasher: &mut ghash::Ghasher) {
+        hasher.update(b"ffgvote");
+        self.source.digest_hasher_update(hasher);
+        self.target.digest_hasher_update(hasher);
+    }
+
+    pub fn create(
+        sk_sig: &<goldfish_type::Sigs as sig::Scheme>::Sk,
+        ticket: goldfish_type::Ticket,
+        source: Checkpoint,
+        target: Checkpoint,
+    ) -> Self {
+        let mut v = Self {
+            ticket,
+            source,
+            target,
+            sigma: <goldfish_type::Sigs as sig::Scheme>::Sig::default(),
+        };
+        v.sigma = goldfish_type::Sigs::sign(sk_sig, &v.inner_digest().as_slice());
+        v
+    }
+
+    pub fn is_valid<C: goldfish_type::ValidationCache>(
+        &self,
+        config: &FfgConfig,
+        cache: &mut C,
+        pki: &goldfish_type::Pki,
+        bvset: &dyn goldfish_blockvote::BvSet,
+    ) -> goldfish_type::ValidationResult {
+        if let Some(ret) = cache.get(&self.digest()) {
+            return *ret;
+        }
+
+        // the target block has to extend the source block
+        let b_source = match bvset.get_block(self.source.h.clone()) {
+            Some(b) => b,
+            None => return goldfish_type::ValidationResult::Unknown,
+        };
+        let mut b = match bvset.get_block(self.target.h.clone()) {
+            Some(b) => b,
+            None => return goldfish_type::ValidationResult::Unknown,
+        };
+        let epochs_ok = self.target.epoch == config.epoch(self.slot())
+            && config.is_checkpoint_slot(b_source.slot(), self.source.epoch)
+            && config.is_checkpoint_slot(b.slot(), self.target.epoch);
+        while b.slot() > b_source.slot() {
+            b = match bvset.get_block(b.parent()) {
+                Some(b) => b,
+                None => return goldfish_type::ValidationResult::Unknown,
+            };
+        }
+
+        let ret = match pki.get(&self.ticket.0) {
+            None => goldfish_type::ValidationResult::Invalid(
+                goldfish_type::ValidationError::UnknownSigner,
+            ),
+            Some((pk_sig, _pk_vrf))
+                if !goldfish_type::Sigs::verify(
+                    pk_sig,
+                    &self.inner_digest().as_slice(),
+                    &self.sigma,
+                ) =>
+            {
+                goldfish_type::ValidationResult::Invalid(
+                    goldfish_type::ValidationError::BadSignature,
+                )
+            }
+            Some(_) if b.digest() != self.source.h || self.target.epoch <= self.source.epoch => {
+                goldfish_type::ValidationResult::Invalid(goldfish_type::ValidationError::BadLink)
+            }
+            Some(_) if !epochs_ok => {
+                goldfish_type::ValidationResult::Invalid(goldfish_type::ValidationError::BadEpoch)
+            }
+            Some(_) => goldfish_type::ValidationResult::Valid,
+        };
+        cache.insert(self.digest(), ret);
+        ret
+    }
+
+    pub fn slot(&self) -> goldfish_type::Slot {
+        self.ticket.1
+    }
+
+    pub fn id(&self) -> goldfish_type::Id {
+        self.ticket.0
+    }
+}
+
+// Justification and finalization from FFG votes, with equal stake per validator:
+// a checkpoint is justified by votes of 2/3 of all validators for a link from a
+// justified source, and a justified checkpoint is finalized once such a link
+// leads from it to a checkpoint of the next epoch.
+#[derive(Debug, Clone)]
+pub struct Ffg {
+    n: usize,
+    links: HashMap<(Checkpoint, Checkpoint), HashSet<goldfish_type::Id>>,
+    // target epochs each validator voted for (a second vote would be slashable)
+    voted: HashSet<(goldfish_type::Id, u64)>,
+    justified: HashSet<Checkpoint>,
+    latest_justified: Checkpoint,
+    finalized: Checkpoint,
+}
+
+impl Ffg {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            links: HashMap::new(),
+            voted: HashSet::new(),
+            justified: HashSet::from([Checkpoint::genesis()]),
+            latest_justified: Checkpoint::genesis(),
+            finalized: Checkpoint::genesis(),
+        }
+    }
+
+    pub fn insert(&mut self, v: &FfgVote) {
+        if !self.voted.insert((v.id(), v.target.epoch)) {
+            log::warn!("Ignoring FFG double vote: {:?}", v);
+            return;
+        }
+        self.links
+            .entry((v.source.clone(), v.target.clone()))
+            .or_default()
+            .insert(v.id());
+    }
+
+    fn is_supermajority(&self, votes: usize) -> bool {
+        3 * votes >= 2 * self.n
+    }
+
+    // only checkpoints whose blocks are in the tree can be justified
+    pub fn update(&mut self, bvtree: &goldfish_blockvote::BvTree) {
+        let mut done = false;
+        while !done {
+            done = true;
+            for ((source, target), voters) in self.links.iter() {
+                if !self.is_supermajority(voters.len())
+                    || !self.justified.contains(source)
+                    || !bvtree.contains_block(&target.h)
+                {
+                    continue;
+                }
+
+                if self.justified.insert(target.clone()) {
+                    log::info!("FFG justified {:?}", target);
+                    if target.epoch > self.latest_justified.epoch {
+                        self.latest_justified = target.clone();
+                    }
+                    done = false;
+                }
+                if target.epoch == source.epoch + 1 && source.epoch > self.finalized.epoch {
+                    log::info!("FFG finalized {:?}", source);
+                    self.finalized = source.clone();
+                }
+            }
+        }
+
+        // links to finalized epochs cannot change anything anymore
+        let epoch = self.finalized.epoch;
+        self.links.retain(|(_, target), _| target.epoch > epoch);
+        self.voted.retain(|(_, epoch_)| *epoch_ > epoch);
+    }
+
+    // forgets the checkpoints up to the epoch of the anchor whose blocks were pruned,
+    // along with the links and votes concerning them
+    pub fn prune(&mut self, bvtree: &goldfish_blockvote::BvTree, epoch_anchor: u64) {
+        let is_pruned = |c: &Checkpoint| c.epoch <= epoch_anchor && !bvtree.contains_block(&c.h);
+        self.justified.retain(|c| !is_pruned(c));
+        self.links
+            .retain(|(source, target), _| !is_pruned(source) && !is_pruned(target));
+        self.voted.retain(|(_, epoch)| *epoch > epoch_anchor);
+    }
+
+    pub fn latest_justified(&self) -> &Checkpoint {
+        &self.latest_justified
+    }
+
+    pub fn finalized(&self) -> &Checkpoint {
+        &self.finalized
+    }
+}
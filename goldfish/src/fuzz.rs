@@ -0,0 +1,236 @@
+use bincode::Options as _;
+use proptest::prelude::*;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::ffg;
+use crate::goldfish_blockvote;
+use crate::goldfish_message;
+use crate::goldfish_type;
+use crate::lottery::Lottery as _;
+use crate::sig;
+use crate::sig::{HashingBytes as _, Scheme as _};
+use crate::testing;
+
+// size of the honest network whose messages seed the mutations
+const N: usize = 4;
+const T_HORIZON: u64 = 8;
+// same bound as for frames from TCP peers, scaled down to single messages
+const MAX_MESSAGE_SIZE: u64 = 1024 * 1024;
+
+// messages of an honest run, and the block tree they end up in
+struct Corpus {
+    lotteries: goldfish_type::Lotteries,
+    ffg_config: ffg::FfgConfig,
+    pki: goldfish_type::Pki,
+    bvtree: goldfish_blockvote::BvTree,
+    msgs: Vec<Vec<u8>>,
+}
+
+fn corpus() -> &'static Corpus {
+    static CORPUS: OnceLock<Corpus> = OnceLock::new();
+    CORPUS.get_or_init(|| {
+        let mut net = testing::Network::always_awake(testing::Configs::new(&[]), N, T_HORIZON);
+        net.run(net.rounds(T_HORIZON));
+        let msgs: Vec<Vec<u8>> = net
+            .observed()
+            .iter()
+            .map(|msg| bincode::serialize(msg).unwrap())
+            .collect();
+        assert!(!msgs.is_empty());
+        Corpus {
+            lotteries: net.lotteries,
+            ffg_config: net.configs.validator.ffg.clone(),
+            pki: net.pki.clone(),
+            bvtree: net.validators[0].validator().bvtree().clone(),
+            msgs,
+        }
+    })
+}
+
+fn decode(bytes: &[u8]) -> Option<goldfish_message::Message> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE)
+        .deserialize(bytes)
+        .ok()
+}
+
+// an honest message with some bytes flipped, possibly cut short
+fn mutated_bytes() -> impl Strategy<Value = Vec<u8>> {
+    (
+        any::<prop::sample::Index>(),
+        prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+        any::<Option<prop::sample::Index>>(),
+    )
+        .prop_map(|(seed, flips, cut)| {
+            let msgs = &corpus().msgs;
+            let mut bytes = msgs[seed.index(msgs.len())].clone();
+            for (i, x) in flips {
+                let i = i.index(bytes.len());
+                bytes[i] ^= x;
+            }
+            if let Some(cut) = cut {
+                bytes.truncate(cut.index(bytes.len()));
+            }
+            bytes
+        })
+}
+
+// random bytes behind a valid variant tag, so that decoding gets past the tag
+fn random_bytes() -> impl Strategy<Value = Vec<u8>> {
+    (
+        0u32..3,
+        0u32..2,
+        prop::collection::vec(any::<u8>(), 0..1024),
+    )
+        .prop_map(|(tag, piece_tag, tail)| {
+            let mut bytes = tag.to_le_bytes().to_vec();
+            if tag == 0 {
+                bytes.extend(piece_tag.to_le_bytes());
+            }
+            bytes.extend(tail);
+            bytes
+        })
+}
+
+fn message_bytes() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![4 => mutated_bytes(), 1 => random_bytes()]
+}
+
+// validation with a cache warmed up by the honest messages agrees with validation
+// without any cache, also when asked again
+fn check_validation(
+    msg: &goldfish_message::Message,
+    bvtree: &goldfish_blockvote::BvTree,
+) -> Result<(), TestCaseError> {
+    let c = corpus();
+    let empty: HashMap<goldfish_type::Hash, goldfish_type::ValidationResult> = HashMap::new();
+    let uncached = msg.is_valid(
+        &c.lotteries,
+        &c.ffg_config,
+        &mut goldfish_type::RoValidationCache::new(&empty),
+        &c.pki,
+        bvtree,
+    );
+
+    let mut cache = HashMap::new();
+    for bytes in c.msgs.iter() {
+        decode(bytes)
+            .unwrap()
+            .is_valid(&c.lotteries, &c.ffg_config, &mut cache, &c.pki, bvtree);
+    }
+    let cached = msg.is_valid(&c.lotteries, &c.ffg_config, &mut cache, &c.pki, bvtree);
+    prop_assert_eq!(cached, uncached);
+    let cached = msg.is_valid(&c.lotteries, &c.ffg_config, &mut cache, &c.pki, bvtree);
+    prop_assert_eq!(cached, uncached);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn is_valid_on_mutated_messages(bytes in message_bytes()) {
+        if let Some(msg) = decode(&bytes) {
+            check_validation(&msg, &corpus().bvtree)?;
+            check_validation(&msg, &goldfish_blockvote::BvTree::default())?;
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    // honest validators get mutated messages injected along with the honest ones
+    #[test]
+    fn step_on_mutated_messages(
+        batches in prop::collection::vec(
+            prop::collection::vec((0..N, message_bytes()), 0..8),
+            T_HORIZON as usize,
+        )
+    ) {
+        let mut net = testing::Network::always_awake(testing::Configs::new(&[]), N, T_HORIZON);
+        let rounds_per_slot = net.configs.validator.slot_schedule.rounds_per_slot();
+        let mut injected = Vec::new();
+        for r in net.rounds(T_HORIZON) {
+            if r % rounds_per_slot == 0 {
+                for (i, bytes) in batches[r / rounds_per_slot - 1].iter() {
+                    if let Some(msg) = decode(bytes) {
+                        net.inject(*i, &msg);
+                        injected.push(msg);
+                    }
+                }
+            }
+            net.step(r);
+        }
+
+        for v in net.validators.iter() {
+            let res = v.validator().bvtree().check_tree();
+            prop_assert!(res.is_ok(), "validator {}: {:?}", v.validator().id(), res);
+        }
+        for msg in injected.iter() {
+            check_validation(msg, net.validators[0].validator().bvtree())?;
+        }
+    }
+}
+
+// tests run with mock signatures, so real BLS signatures are mutated separately:
+// an honest one with some bytes flipped (including its length), or random bytes
+fn bls_signature_bytes() -> impl Strategy<Value = Vec<u8>> {
+    let (sk, _) = sig::MilagroBlsScheme::new().gen();
+    let honest = bincode::serialize(&sig::MilagroBlsScheme::sign(&sk, b"goldfish")).unwrap();
+    prop_oneof![
+        prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..4).prop_map(
+            move |flips| {
+                let mut bytes = honest.clone();
+                for (i, x) in flips {
+                    let i = i.index(bytes.len());
+                    bytes[i] ^= x;
+                }
+                bytes
+            }
+        ),
+        prop::collection::vec(any::<u8>(), 96).prop_map(|tail| {
+            let mut bytes = 96u64.to_le_bytes().to_vec();
+            bytes.extend(tail);
+            bytes
+        }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn decode_malformed_bls_signatures(bytes in bls_signature_bytes()) {
+        // whatever decodes can be hashed, printed and encoded again
+        if let Ok(sigma) = bincode::deserialize::<sig::MilagroBlsSchemeSig>(&bytes) {
+            let _ = sigma.as_bytes_for_hashing();
+            let _ = format!("{:?}", sigma);
+            let encoded = bincode::serialize(&sigma).unwrap();
+            prop_assert_eq!(
+                bincode::deserialize::<sig::MilagroBlsSchemeSig>(&encoded).unwrap(),
+                sigma
+            );
+        }
+    }
+}
+
+// a valid vote from the far future once overflowed the expiry check of limbo
+#[test]
+fn step_on_vote_from_far_future() {
+    let mut net = testing::Network::always_awake(testing::Configs::new(&[]), N, 2);
+    let (_, sks) = crate::generate_keys(N as u64);
+    let (sk_sig, sk_vrf) = &sks[1];
+    let t = goldfish_type::Slot::MAX;
+    let v = goldfish_blockvote::Vote::create(
+        sk_sig,
+        (1, t),
+        net.lotteries.vote.open(sk_vrf, t),
+        goldfish_blockvote::Block::default().digest(),
+    );
+    net.inject(
+        0,
+        &goldfish_message::Message::Piece(goldfish_message::Piece::Vote(v)),
+    );
+    net.run(net.rounds(2));
+    assert_eq!(net.validators[0].validator().bvtree().check_tree(), Ok(()));
+}
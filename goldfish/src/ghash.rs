@@ -31,12 +31,23 @@ impl Ghash {
     pub fn as_bytes(&self) -> &[u8] {
         &self.ghash
     }
+
+    pub fn to_base64(&self) -> String {
+        general_purpose::STANDARD_NO_PAD.encode(self.ghash)
+    }
+
+    pub fn from_base64(s: &str) -> Option<Self> {
+        let ghash = general_purpose::STANDARD_NO_PAD.decode(s).ok()?;
+        Some(Self {
+            ghash: ghash.try_into().ok()?,
+        })
+    }
 }
 
 impl std::fmt::Debug for Ghash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_tuple("H")
-            .field(&general_purpose::STANDARD_NO_PAD.encode(&self.ghash)[..10].to_string())
+            .field(&self.to_base64()[..10].to_string())
             .finish()
     }
 }
@@ -1,58 +1,117 @@
-use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 use crate::ghash;
 use crate::goldfish_message;
 use crate::goldfish_type;
+use crate::merkle;
+use crate::transaction;
 use crate::{lottery, lottery::Lottery};
-use crate::{sig, sig::Scheme};
+use crate::{sig, sig::HashingBytes as _, sig::Scheme};
 
-const BLOCK_SIZE: usize = 80_000;
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct ExpiryConfig {
+    /// Number of recent slots whose votes count in GHOST-Eph (eta-expiry)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1)]
+    pub vote_expiry: u64,
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    /// Count only the latest vote of every validator within the expiry window
+    #[arg(long)]
+    pub vote_expiry_latest: bool,
+}
+
+impl ExpiryConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec!["--vote-expiry".to_string(), self.vote_expiry.to_string()];
+        if self.vote_expiry_latest {
+            args.push("--vote-expiry-latest".to_string());
+        }
+        args
+    }
+}
+
+#[derive(clap::Args, Debug, Clone, Copy, Default)]
+pub struct PruneConfig {
+    /// Prune the block tree this many blocks below the slow-confirmed tip (and never past
+    /// the finalized checkpoint); proposals with tips a validator has pruned are unknown to
+    /// it, so this should exceed how far validators lag behind each other
+    #[arg(long)]
+    pub prune_depth: Option<u64>,
+}
+
+impl PruneConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        match self.prune_depth {
+            Some(depth) => vec!["--prune-depth".to_string(), depth.to_string()],
+            None => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Payload {
     graffiti: String,
-    #[serde(with = "serde_big_array::BigArray")]
-    data: [u8; BLOCK_SIZE],
+    txs: Vec<transaction::Transaction>,
+    // filler up to the sampled block size, for bandwidth experiments
+    #[serde(with = "serde_bytes")]
+    padding: Vec<u8>,
 }
 
-impl Default for Payload {
-    fn default() -> Self {
+impl Payload {
+    pub fn new(graffiti: String, txs: Vec<transaction::Transaction>, padding: usize) -> Self {
         Self {
-            graffiti: String::new(),
-            data: [0; BLOCK_SIZE],
+            graffiti,
+            txs,
+            padding: vec![0; padding],
         }
     }
-}
 
-impl Payload {
+    fn digest(&self) -> goldfish_type::Hash {
+        let mut hasher = ghash::Ghasher::new();
+        self.digest_hasher_update(&mut hasher);
+        hasher.into()
+    }
+
+    fn size(&self) -> u64 {
+        bincode::serialized_size(self).unwrap()
+    }
+
+    // Merkle tree over the transaction digests
+    fn tx_levels(&self) -> Vec<Vec<goldfish_type::Hash>> {
+        merkle::levels(self.txs.iter().map(|tx| tx.digest()).collect())
+    }
+
     fn digest_hasher_update(&self, hasher: &mut ghash::Ghasher) {
         hasher.update(b"payload");
-        hasher.update(&self.data);
+        hasher.update(&self.txs.len().to_ne_bytes());
+        for tx in self.txs.iter() {
+            tx.digest_hasher_update(hasher);
+        }
         hasher.update(&self.graffiti.as_bytes());
-    }
-
-    fn random_with_graffiti(graffiti: String) -> Self {
-        let mut rng = rand::thread_rng();
-        let mut data = [0; BLOCK_SIZE];
-        rng.fill_bytes(&mut data);
-        Self { graffiti, data }
+        hasher.update(&self.padding.len().to_ne_bytes());
+        hasher.update(&self.padding);
     }
 }
 
+// A block with its payload replaced by a commitment to it. Headers and their
+// blocks have the same digest, so a header can stand in for a block that is
+// relayed or fetched separately.
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct Block {
+pub struct BlockHeader {
     ticket: goldfish_type::Ticket,
     rho: <goldfish_type::Lottery as lottery::Lottery>::Opening,
     h: goldfish_type::Hash,
-    payload: Payload,
+    payload_digest: goldfish_type::Hash,
+    payload_size: u64,
+    // root of the Merkle tree over the transactions of the payload
+    tx_root: goldfish_type::Hash,
+    // root of the erasure-coded payload chunks (default if not erasure-coded)
+    data_root: goldfish_type::Hash,
     sigma: <goldfish_type::Sigs as sig::Scheme>::Sig,
 }
 
-impl Block {
+impl BlockHeader {
     pub fn digest(&self) -> goldfish_type::Hash {
-        // ghash::Ghash::new(&bincode::serialize(&self).unwrap())
         let mut hasher = ghash::Ghasher::new();
         self.digest_hasher_update(&mut hasher);
         hasher.into()
@@ -69,9 +128,6 @@ impl Block {
     }
 
     fn inner_digest(&self) -> goldfish_type::Hash {
-        // ghash::Ghash::new(
-        //     &bincode::serialize(&("block", self.h.clone(), self.txs.clone())).unwrap(),
-        // )
         let mut hasher = ghash::Ghasher::new();
         self.inner_digest_hasher_update(&mut hasher);
         hasher.into()
@@ -80,7 +136,85 @@ impl Block {
     fn inner_digest_hasher_update(&self, hasher: &mut ghash::Ghasher) {
         hasher.update(b"block");
         hasher.update(&self.h.as_bytes());
-        self.payload.digest_hasher_update(hasher);
+        hasher.update(&self.payload_digest.as_bytes());
+        hasher.update(&self.payload_size.to_ne_bytes());
+        hasher.update(&self.tx_root.as_bytes());
+        hasher.update(&self.data_root.as_bytes());
+    }
+
+    // whether the header was signed by a winner of the block lottery of its slot
+    // (does not check the parent, nor the payload)
+    pub fn check_signed(
+        &self,
+        lotteries: &goldfish_type::Lotteries,
+        pki: &goldfish_type::Pki,
+    ) -> Result<(), goldfish_type::ValidationError> {
+        let (pk_sig, pk_vrf) = pki
+            .get(&self.ticket.0)
+            .ok_or(goldfish_type::ValidationError::UnknownSigner)?;
+        if !lotteries.block.is_winning(pk_vrf, self.ticket.1, &self.rho) {
+            return Err(goldfish_type::ValidationError::BadLottery);
+        }
+        if !goldfish_type::Sigs::verify(pk_sig, &self.inner_digest().as_slice(), &self.sigma) {
+            return Err(goldfish_type::ValidationError::BadSignature);
+        }
+        Ok(())
+    }
+
+    pub fn is_signed(
+        &self,
+        lotteries: &goldfish_type::Lotteries,
+        pki: &goldfish_type::Pki,
+    ) -> bool {
+        self.check_signed(lotteries, pki).is_ok()
+    }
+
+    pub fn slot(&self) -> goldfish_type::Slot {
+        self.ticket.1
+    }
+
+    pub fn prio(&self) -> u64 {
+        goldfish_type::Lottery::prio(&self.rho)
+    }
+
+    pub fn parent(&self) -> goldfish_type::Hash {
+        self.h.clone()
+    }
+
+    pub fn payload_size(&self) -> u64 {
+        self.payload_size
+    }
+
+    pub fn tx_root(&self) -> goldfish_type::Hash {
+        self.tx_root.clone()
+    }
+
+    pub fn data_root(&self) -> goldfish_type::Hash {
+        self.data_root.clone()
+    }
+}
+
+// The header holds the commitments to the payload, computed once when the block
+// is created, so that digests do not rehash the payload.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Block {
+    header: BlockHeader,
+    // None if only the header is at hand (with DAS, see das::Sampler)
+    payload: Option<Payload>,
+}
+
+impl Block {
+    pub fn digest(&self) -> goldfish_type::Hash {
+        // ghash::Ghash::new(&bincode::serialize(&self).unwrap())
+        self.header.digest()
+    }
+
+    pub fn digest_hasher_update(&self, hasher: &mut ghash::Ghasher) {
+        self.header.digest_hasher_update(hasher);
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        self.header.clone()
     }
 
     pub fn create(
@@ -88,17 +222,48 @@ impl Block {
         ticket: goldfish_type::Ticket,
         rho: <goldfish_type::Lottery as lottery::Lottery>::Opening,
         h: goldfish_type::Hash,
-        txs: String,
+        payload: Payload,
+        data_root: goldfish_type::Hash,
     ) -> Self {
-        let mut b = Self {
+        let mut header = BlockHeader {
             ticket,
             rho,
             h,
-            payload: Payload::random_with_graffiti(txs),
+            payload_digest: payload.digest(),
+            payload_size: payload.size(),
+            tx_root: merkle::root(&payload.tx_levels()),
+            data_root,
             sigma: <goldfish_type::Sigs as sig::Scheme>::Sig::default(),
         };
-        b.sigma = goldfish_type::Sigs::sign(sk_sig, &b.inner_digest().as_slice());
-        b
+        header.sigma = goldfish_type::Sigs::sign(sk_sig, &header.inner_digest().as_slice());
+        Self {
+            header,
+            payload: Some(payload),
+        }
+    }
+
+    // a block whose payload is to be sampled rather than downloaded
+    pub fn from_header(header: BlockHeader) -> Self {
+        Self {
+            header,
+            payload: None,
+        }
+    }
+
+    // whether the payload (if at hand) is the one the (signed) header commits to;
+    // not cached, as blocks with different payloads can have the same digest
+    pub fn check_payload(&self) -> Result<(), goldfish_type::ValidationError> {
+        let payload = match &self.payload {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        if payload.digest() != self.header.payload_digest
+            || payload.size() != self.header.payload_size
+            || merkle::root(&payload.tx_levels()) != self.header.tx_root
+        {
+            return Err(goldfish_type::ValidationError::BadPayload);
+        }
+        Ok(())
     }
 
     pub fn is_valid<C: goldfish_type::ValidationCache>(
@@ -107,6 +272,20 @@ impl Block {
         cache: &mut C,
         pki: &goldfish_type::Pki,
         bvset: &dyn BvSet,
+    ) -> goldfish_type::ValidationResult {
+        match self.check_payload() {
+            Err(e) => goldfish_type::ValidationResult::Invalid(e),
+            Ok(()) => self.is_valid_header(lotteries, cache, pki, bvset),
+        }
+    }
+
+    // validity of the header and its ancestors, assuming the payload matches it
+    pub fn is_valid_header<C: goldfish_type::ValidationCache>(
+        &self,
+        lotteries: &goldfish_type::Lotteries,
+        cache: &mut C,
+        pki: &goldfish_type::Pki,
+        bvset: &dyn BvSet,
     ) -> goldfish_type::ValidationResult {
         if let Some(ret) = cache.get(&self.digest()) {
             return *ret;
@@ -117,42 +296,87 @@ impl Block {
             return goldfish_type::ValidationResult::Valid;
         }
 
-        let b_parent = bvset.get_block(self.h.clone());
-        if b_parent == None {
-            return goldfish_type::ValidationResult::Unknown;
-        }
-        let b_parent = b_parent.unwrap();
-        assert!(
-            b_parent.is_valid(lotteries, cache, pki, bvset)
-                == goldfish_type::ValidationResult::Valid
-        );
-
-        let id = self.ticket.0;
-        let (pk_sig, pk_vrf) = pki.get(&id).unwrap();
-        if lotteries.block.is_winning(pk_vrf, self.ticket.1, &self.rho)
-            && goldfish_type::Sigs::verify(pk_sig, &self.inner_digest().as_slice(), &self.sigma)
-            && b_parent.is_valid(lotteries, cache, pki, bvset)
-                == goldfish_type::ValidationResult::Valid
-            && self.ticket.1 > b_parent.ticket.1
-        {
-            cache.insert(self.digest(), goldfish_type::ValidationResult::Valid);
-            return goldfish_type::ValidationResult::Valid;
-        }
+        let b_parent = match bvset.get_block(self.parent()) {
+            Some(b) => b,
+            None => return goldfish_type::ValidationResult::Unknown,
+        };
+        let ret = match b_parent.is_valid_header(lotteries, cache, pki, bvset) {
+            goldfish_type::ValidationResult::Unknown => {
+                return goldfish_type::ValidationResult::Unknown;
+            }
+            goldfish_type::ValidationResult::Invalid(_) => {
+                goldfish_type::ValidationResult::Invalid(
+                    goldfish_type::ValidationError::InvalidParent,
+                )
+            }
+            goldfish_type::ValidationResult::Valid => {
+                match self.header.check_signed(lotteries, pki) {
+                    Err(e) => goldfish_type::ValidationResult::Invalid(e),
+                    Ok(()) if self.slot() <= b_parent.slot() => {
+                        goldfish_type::ValidationResult::Invalid(
+                            goldfish_type::ValidationError::NonIncreasingSlot,
+                        )
+                    }
+                    Ok(()) => goldfish_type::ValidationResult::Valid,
+                }
+            }
+        };
 
-        cache.insert(self.digest(), goldfish_type::ValidationResult::Invalid);
-        goldfish_type::ValidationResult::Invalid
+        cache.insert(self.digest(), ret);
+        ret
     }
 
     pub fn slot(&self) -> goldfish_type::Slot {
-        self.ticket.1
+        self.header.slot()
     }
 
     pub fn id(&self) -> goldfish_type::Id {
-        self.ticket.0
+        self.header.ticket.0
     }
 
     pub fn prio(&self) -> u64 {
-        goldfish_type::Lottery::prio(&self.rho)
+        self.header.prio()
+    }
+
+    pub fn parent(&self) -> goldfish_type::Hash {
+        self.header.parent()
+    }
+
+    pub fn payload(&self) -> Option<&Payload> {
+        self.payload.as_ref()
+    }
+
+    // empty if the payload is not at hand
+    pub fn graffiti(&self) -> &str {
+        match &self.payload {
+            Some(payload) => &payload.graffiti,
+            None => "",
+        }
+    }
+
+    // empty if the payload is not at hand
+    pub fn txs(&self) -> &[transaction::Transaction] {
+        match &self.payload {
+            Some(payload) => &payload.txs,
+            None => &[],
+        }
+    }
+
+    pub fn data_root(&self) -> goldfish_type::Hash {
+        self.header.data_root()
+    }
+
+    // index and Merkle proof of the transaction with digest h_tx, if it is in this block
+    pub fn tx_proof(
+        &self,
+        h_tx: &goldfish_type::Hash,
+    ) -> Option<(usize, Vec<goldfish_type::Hash>)> {
+        let payload = self.payload.as_ref()?;
+        let levels = payload.tx_levels();
+        let index = levels[0][..payload.txs.len()]
+            .iter()
+            .position(|h| h == h_tx)?;
+        Some((index, merkle::proof(&levels, index)))
     }
 }
 
@@ -222,30 +446,49 @@ impl Vote {
             return *ret;
         }
 
-        let b_target = bvset.get_block(self.h.clone());
-        if b_target == None {
-            return goldfish_type::ValidationResult::Unknown;
-        }
-        let b_target = b_target.unwrap();
-        assert!(
-            b_target.is_valid(lotteries, cache, pki, bvset)
-                == goldfish_type::ValidationResult::Valid
-        );
+        let b_target = match bvset.get_block(self.h.clone()) {
+            Some(b) => b,
+            None => return goldfish_type::ValidationResult::Unknown,
+        };
+        let ret = match b_target.is_valid_header(lotteries, cache, pki, bvset) {
+            goldfish_type::ValidationResult::Unknown => {
+                return goldfish_type::ValidationResult::Unknown;
+            }
+            goldfish_type::ValidationResult::Invalid(_) => {
+                goldfish_type::ValidationResult::Invalid(
+                    goldfish_type::ValidationError::InvalidReference,
+                )
+            }
+            goldfish_type::ValidationResult::Valid => match self.check_signed(lotteries, pki) {
+                Err(e) => goldfish_type::ValidationResult::Invalid(e),
+                Ok(()) if self.ticket.1 < b_target.slot() => {
+                    goldfish_type::ValidationResult::Invalid(
+                        goldfish_type::ValidationError::NonIncreasingSlot,
+                    )
+                }
+                Ok(()) => goldfish_type::ValidationResult::Valid,
+            },
+        };
 
-        let id = self.ticket.0;
-        let (pk_sig, pk_vrf) = pki.get(&id).unwrap();
-        if lotteries.vote.is_winning(pk_vrf, self.ticket.1, &self.rho)
-            && goldfish_type::Sigs::verify(pk_sig, &self.inner_digest().as_slice(), &self.sigma)
-            && b_target.is_valid(lotteries, cache, pki, bvset)
-                == goldfish_type::ValidationResult::Valid
-            && self.ticket.1 >= b_target.ticket.1
-        {
-            cache.insert(self.digest(), goldfish_type::ValidationResult::Valid);
-            return goldfish_type::ValidationResult::Valid;
-        }
+        cache.insert(self.digest(), ret);
+        ret
+    }
 
-        cache.insert(self.digest(), goldfish_type::ValidationResult::Invalid);
-        goldfish_type::ValidationResult::Invalid
+    fn check_signed(
+        &self,
+        lotteries: &goldfish_type::Lotteries,
+        pki: &goldfish_type::Pki,
+    ) -> Result<(), goldfish_type::ValidationError> {
+        let (pk_sig, pk_vrf) = pki
+            .get(&self.ticket.0)
+            .ok_or(goldfish_type::ValidationError::UnknownSigner)?;
+        if !lotteries.vote.is_winning(pk_vrf, self.ticket.1, &self.rho) {
+            return Err(goldfish_type::ValidationError::BadLottery);
+        }
+        if !goldfish_type::Sigs::verify(pk_sig, &self.inner_digest().as_slice(), &self.sigma) {
+            return Err(goldfish_type::ValidationError::BadSignature);
+        }
+        Ok(())
     }
 
     pub fn slot(&self) -> goldfish_type::Slot {
@@ -256,6 +499,10 @@ impl Vote {
     pub fn id(&self) -> goldfish_type::Id {
         self.ticket.0
     }
+
+    pub fn target(&self) -> goldfish_type::Hash {
+        self.h.clone()
+    }
 }
 
 pub trait BvSet {
@@ -263,13 +510,89 @@ pub trait BvSet {
     fn get_vote(&self, h: goldfish_type::Hash) -> Option<Vote>;
 }
 
+// Fork-choice node of a block (proto-array style): blocks are stored in insertion
+// order, so parents come before their children, with their height and the
+// number of tickets voting for them or their descendants, per slot of the votes.
+#[derive(Debug, Clone, PartialEq)]
+struct ProtoNode {
+    parent: Option<usize>,
+    height: usize,
+    weights: BTreeMap<goldfish_type::Slot, usize>,
+    // only the latest vote of every validator
+    weights_latest: BTreeMap<goldfish_type::Slot, usize>,
+}
+
+impl ProtoNode {
+    fn weight(weights: &BTreeMap<goldfish_type::Slot, usize>, t_min: isize, t_max: isize) -> usize {
+        if t_max < 0 || t_max < t_min {
+            return 0;
+        }
+        weights
+            .range((t_min.max(0) as goldfish_type::Slot)..=(t_max as goldfish_type::Slot))
+            .map(|(_, w)| w)
+            .sum()
+    }
+
+    fn add(
+        weights: &mut BTreeMap<goldfish_type::Slot, usize>,
+        delta: &BTreeMap<goldfish_type::Slot, isize>,
+    ) {
+        for (t, d) in delta {
+            let w = weights.entry(*t).or_default();
+            *w = w.checked_add_signed(*d).unwrap();
+            if *w == 0 {
+                weights.remove(t);
+            }
+        }
+    }
+}
+
+// pending changes to the weights of a node, per slot
+#[derive(Debug, Default)]
+struct WeightDelta {
+    weights: BTreeMap<goldfish_type::Slot, isize>,
+    weights_latest: BTreeMap<goldfish_type::Slot, isize>,
+}
+
+impl WeightDelta {
+    fn add(delta: &mut BTreeMap<goldfish_type::Slot, isize>, t: goldfish_type::Slot, d: isize) {
+        let d_ = delta.entry(t).or_default();
+        *d_ += d;
+        if *d_ == 0 {
+            delta.remove(&t);
+        }
+    }
+
+    fn merge(&mut self, other: &WeightDelta) {
+        for (t, d) in other.weights.iter() {
+            Self::add(&mut self.weights, *t, *d);
+        }
+        for (t, d) in other.weights_latest.iter() {
+            Self::add(&mut self.weights_latest, *t, *d);
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct BvTree {
     blocks: HashMap<goldfish_type::Hash, Block>,
     votes: HashMap<goldfish_type::Hash, Vote>,
-    votecount: HashMap<goldfish_type::Hash, HashSet<(goldfish_type::Id, goldfish_type::Slot)>>,
     children: HashMap<goldfish_type::Hash, HashSet<goldfish_type::Hash>>,
     tips: HashSet<goldfish_type::Hash>,
+    nodes: Vec<ProtoNode>,
+    indices: HashMap<goldfish_type::Hash, usize>,
+    // blocks voted for by every ticket, which counts once on each of their branches
+    // (an equivocating ticket counts for every branch it voted for)
+    tickets: HashMap<goldfish_type::Ticket, Vec<goldfish_type::Hash>>,
+    // latest vote of every validator, never expired (for LMD-GHOST)
+    latest_votes: HashMap<goldfish_type::Id, Vote>,
+    // oldest block kept besides genesis, which remains the root with the anchor
+    // as its only child once the tree is pruned
+    anchor: goldfish_type::Hash,
+    // blocks inserted without their payload, until it is inserted as well
+    header_only: HashSet<goldfish_type::Hash>,
+    // blocks and votes inserted since the last take_journal, to persist them
+    journal: Vec<goldfish_message::Piece>,
 }
 
 impl Default for BvTree {
@@ -278,9 +601,20 @@ impl Default for BvTree {
         BvTree {
             blocks: HashMap::from([(b0.digest(), b0.clone())]),
             votes: HashMap::new(),
-            votecount: HashMap::from([(b0.digest(), HashSet::new())]),
             children: HashMap::from([(b0.digest(), HashSet::new())]),
             tips: HashSet::from([b0.digest()]),
+            nodes: vec![ProtoNode {
+                parent: None,
+                height: 0,
+                weights: BTreeMap::new(),
+                weights_latest: BTreeMap::new(),
+            }],
+            indices: HashMap::from([(b0.digest(), 0)]),
+            tickets: HashMap::new(),
+            latest_votes: HashMap::new(),
+            anchor: b0.digest(),
+            header_only: HashSet::new(),
+            journal: Vec::new(),
         }
     }
 }
@@ -300,18 +634,158 @@ impl BvTree {
         self.tips.clone()
     }
 
-    pub fn vote_digests_for_proposal(&self) -> HashSet<goldfish_type::Hash> {
-        self.votes.keys().cloned().collect()
+    // a proposal for slot t can only include votes from the previous slot
+    pub fn vote_digests_for_proposal(
+        &self,
+        t: goldfish_type::Slot,
+    ) -> HashSet<goldfish_type::Hash> {
+        self.votes
+            .iter()
+            .filter(|(_, v)| v.slot() + 1 == t)
+            .map(|(h, _)| h.clone())
+            .collect()
     }
 
     pub fn insert_block(&mut self, b: &Block) {
+        if self.blocks.contains_key(&b.digest()) {
+            // the payload of a block inserted with only its header
+            if b.payload().is_some() && self.header_only.remove(&b.digest()) {
+                self.blocks.insert(b.digest(), b.clone());
+                self.journal.push(goldfish_message::Piece::Block(b.clone()));
+            }
+            return;
+        }
+        debug_assert!(!self.is_discarded(b));
+        if b.payload().is_none() {
+            self.header_only.insert(b.digest());
+        }
         self.blocks.insert(b.digest(), b.clone());
-        self.votecount.insert(b.digest(), HashSet::new());
+        let parent = self.indices[&b.parent()];
+        self.nodes.push(ProtoNode {
+            parent: Some(parent),
+            height: self.nodes[parent].height + 1,
+            weights: BTreeMap::new(),
+            weights_latest: BTreeMap::new(),
+        });
+        self.indices.insert(b.digest(), self.nodes.len() - 1);
         self.children.insert(b.digest(), HashSet::new());
-        let b_parent_children = self.children.get_mut(&b.h).unwrap();
+        let b_parent_children = self.children.get_mut(&b.parent()).unwrap();
         b_parent_children.insert(b.digest());
-        self.tips.remove(&b.h);
+        self.tips.remove(&b.parent());
         self.tips.insert(b.digest());
+        self.journal.push(goldfish_message::Piece::Block(b.clone()));
+    }
+
+    fn insert_votes<I: IntoIterator<Item = Vote>>(&mut self, votes: I) {
+        let mut deltas: BTreeMap<usize, WeightDelta> = BTreeMap::new();
+        for v in votes {
+            if self.votes.insert(v.digest(), v.clone()).is_none() {
+                self.journal.push(goldfish_message::Piece::Vote(v.clone()));
+                self.count_ticket(&mut deltas, &v, 1);
+            }
+
+            let i = self.indices[&v.h];
+            if self
+                .latest_votes
+                .get(&v.id())
+                .map_or(true, |v_latest| v_latest.slot() < v.slot())
+            {
+                if let Some(v_latest) = self.latest_votes.insert(v.id(), v.clone()) {
+                    let delta = deltas.entry(self.indices[&v_latest.h]).or_default();
+                    WeightDelta::add(&mut delta.weights_latest, v_latest.slot(), -1);
+                }
+                WeightDelta::add(
+                    &mut deltas.entry(i).or_default().weights_latest,
+                    v.slot(),
+                    1,
+                );
+            }
+        }
+        self.apply_deltas(deltas);
+    }
+
+    // blocks and votes inserted since the last call
+    pub fn take_journal(&mut self) -> Vec<goldfish_message::Piece> {
+        std::mem::take(&mut self.journal)
+    }
+
+    // re-inserts blocks (parents first) and votes from a store, which were valid
+    // when they were merged; anything building on a missing block is skipped
+    pub fn restore(&mut self, pieces: Vec<goldfish_message::Piece>) {
+        let mut votes = Vec::new();
+        for x in pieces {
+            match x {
+                goldfish_message::Piece::Block(b) => {
+                    if self.blocks.contains_key(&b.parent()) {
+                        self.insert_block(&b);
+                    } else {
+                        log::warn!("Not restoring block with unknown parent: {:?}", b.digest());
+                    }
+                }
+                goldfish_message::Piece::Vote(v) => votes.push(v),
+            }
+        }
+        let (votes, orphans): (Vec<Vote>, Vec<Vote>) = votes
+            .into_iter()
+            .partition(|v| self.blocks.contains_key(&v.h));
+        if !orphans.is_empty() {
+            log::warn!("Not restoring {} votes for unknown blocks", orphans.len());
+        }
+        self.insert_votes(votes);
+        self.journal.clear();
+    }
+
+    // adds (d = 1) or removes (d = -1) the vote v for its ticket, changing the weights
+    // of the blocks from v.h up to those counted for other votes of the ticket already
+    fn count_ticket(&mut self, deltas: &mut BTreeMap<usize, WeightDelta>, v: &Vote, d: isize) {
+        let mut targets = self.tickets.remove(&v.ticket).unwrap_or_default();
+        if d < 0 {
+            if let Some(k) = targets.iter().position(|h| *h == v.h) {
+                targets.swap_remove(k);
+            }
+        }
+
+        let i = self.indices[&v.h];
+        WeightDelta::add(&mut deltas.entry(i).or_default().weights, v.slot(), d);
+        let covered = targets
+            .iter()
+            .map(|h| self.common_ancestor(i, self.indices[h]))
+            .max_by_key(|j| self.nodes[*j].height);
+        if let Some(j) = covered {
+            WeightDelta::add(&mut deltas.entry(j).or_default().weights, v.slot(), -d);
+        }
+
+        if d > 0 {
+            targets.push(v.h.clone());
+        }
+        if !targets.is_empty() {
+            self.tickets.insert(v.ticket, targets);
+        }
+    }
+
+    // deepest common ancestor of the nodes i and j
+    fn common_ancestor(&self, mut i: usize, mut j: usize) -> usize {
+        while i != j {
+            if self.nodes[i].height >= self.nodes[j].height {
+                i = self.nodes[i].parent.unwrap();
+            } else {
+                j = self.nodes[j].parent.unwrap();
+            }
+        }
+        i
+    }
+
+    // adds the weights of each node to it and all its ancestors, in a single
+    // backward pass instead of a walk to genesis for every vote
+    fn apply_deltas(&mut self, mut deltas: BTreeMap<usize, WeightDelta>) {
+        while let Some((i, delta)) = deltas.pop_last() {
+            let node = &mut self.nodes[i];
+            ProtoNode::add(&mut node.weights, &delta.weights);
+            ProtoNode::add(&mut node.weights_latest, &delta.weights_latest);
+            if let Some(parent) = node.parent {
+                deltas.entry(parent).or_default().merge(&delta);
+            }
+        }
     }
 
     pub fn merge<C: goldfish_type::ValidationCache>(
@@ -322,7 +796,20 @@ impl BvTree {
         buffer_blocks: &mut HashMap<goldfish_type::Hash, Block>,
         buffer_votes: &mut HashMap<goldfish_type::Hash, Vote>,
         proposal: Option<&goldfish_message::Proposal>,
-    ) {
+    ) -> Vec<goldfish_type::ValidationError> {
+        // buffered blocks and votes were valid when buffered, so this should stay empty
+        let mut dropped = Vec::new();
+
+        let payloads: Vec<goldfish_type::Hash> = buffer_blocks
+            .iter()
+            .filter(|(h, b)| b.payload().is_some() && self.header_only.contains(h))
+            .map(|(h, _)| h.clone())
+            .collect();
+        for h in payloads {
+            let b = buffer_blocks.remove(&h).unwrap();
+            self.insert_block(&b);
+        }
+
         let mut try_to_merge = VecDeque::new();
         match proposal {
             None => {
@@ -333,10 +820,17 @@ impl BvTree {
                 let bvset = BufferAugmentedBvTree::new(self, buffer_blocks, buffer_votes);
                 for h in p.tips() {
                     let mut h_ = h;
-                    while !try_to_merge.contains(&h_) && h_ != Block::default().digest() {
-                        try_to_merge.push_back(h_.clone());
-                        let b = bvset.get_block(h_).unwrap();
-                        h_ = b.h.clone();
+                    while !try_to_merge.contains(&h_) && !self.blocks.contains_key(&h_) {
+                        match bvset.get_block(h_.clone()) {
+                            Some(b) => {
+                                try_to_merge.push_back(h_);
+                                h_ = b.parent();
+                            }
+                            None => {
+                                log::warn!("Proposal tip not at hand: {:?}", h_);
+                                break;
+                            }
+                        }
                     }
                 }
             }
@@ -353,20 +847,31 @@ impl BvTree {
                 continue;
             }
 
-            let b = buffer_blocks.remove(&k).unwrap();
-
-            let b_is_valid = b.is_valid(lotteries, cache, pki, self);
-            assert!(b_is_valid != goldfish_type::ValidationResult::Invalid);
+            let b = match buffer_blocks.remove(&k) {
+                Some(b) => b,
+                None => continue,
+            };
+            if self.is_discarded(&b) {
+                log::warn!(
+                    "Dropping buffered block building on pruned history: {:?}",
+                    k
+                );
+                continue;
+            }
 
-            match b_is_valid {
+            match b.is_valid(lotteries, cache, pki, self) {
                 goldfish_type::ValidationResult::Valid => {
                     self.insert_block(&b);
                 }
-                goldfish_type::ValidationResult::Invalid => {
-                    assert!(b_is_valid != goldfish_type::ValidationResult::Invalid);
+                goldfish_type::ValidationResult::Invalid(e) => {
+                    log::warn!("Dropping invalid buffered block ({:?}): {:?}", e, k);
+                    dropped.push(e);
                 }
                 goldfish_type::ValidationResult::Unknown => {
-                    try_to_merge.push_back(k.clone());
+                    // waits for its parent, unless that is nowhere to be merged
+                    if try_to_merge.contains(&b.parent()) {
+                        try_to_merge.push_back(k.clone());
+                    }
                     buffer_blocks.insert(k, b);
                 }
             }
@@ -374,75 +879,125 @@ impl BvTree {
 
         if let Some(p) = proposal {
             let b = p.b();
-            assert!(
-                b.is_valid(lotteries, cache, pki, self) == goldfish_type::ValidationResult::Valid
-            );
-            self.insert_block(&b);
+            let res = if self.is_discarded(&b) {
+                goldfish_type::ValidationResult::Unknown
+            } else {
+                b.is_valid(lotteries, cache, pki, self)
+            };
+            match res {
+                goldfish_type::ValidationResult::Valid => self.insert_block(&b),
+                goldfish_type::ValidationResult::Invalid(e) => {
+                    log::warn!(
+                        "Dropping invalid proposed block ({:?}): {:?}",
+                        e,
+                        b.digest()
+                    );
+                    dropped.push(e);
+                }
+                goldfish_type::ValidationResult::Unknown => {
+                    log::warn!("Proposed block does not extend the tree: {:?}", b.digest());
+                }
+            }
         }
 
-        for (_, v) in buffer_votes.drain_filter(|k, _v| match proposal {
-            None => true,
-            Some(p) => p.votes().contains(&k),
-        }) {
-            let v_is_valid = v.is_valid(lotteries, cache, pki, self);
-            assert!(v_is_valid == goldfish_type::ValidationResult::Valid);
-            self.votes.insert(v.digest(), v.clone());
-
-            let mut b_target_hash = v.h;
-            loop {
-                let b_target_votecount = self.votecount.get_mut(&b_target_hash).unwrap();
-                b_target_votecount.insert(v.ticket);
-
-                if b_target_hash == Block::default().digest() {
-                    break;
+        let votes: Vec<Vote> = buffer_votes
+            .drain_filter(|k, _v| match proposal {
+                None => true,
+                Some(p) => p.votes().contains(&k),
+            })
+            .map(|(_, v)| v)
+            .collect();
+        let mut votes_valid = Vec::new();
+        for v in votes {
+            match v.is_valid(lotteries, cache, pki, self) {
+                goldfish_type::ValidationResult::Valid => votes_valid.push(v),
+                goldfish_type::ValidationResult::Invalid(e) => {
+                    log::warn!("Dropping invalid buffered vote ({:?}): {:?}", e, v.digest());
+                    dropped.push(e);
+                }
+                goldfish_type::ValidationResult::Unknown => {
+                    log::warn!(
+                        "Dropping buffered vote for a block not merged: {:?}",
+                        v.digest()
+                    );
                 }
-
-                let b_target = self.blocks.get(&b_target_hash).unwrap();
-                b_target_hash = b_target.h.clone();
             }
         }
+        self.insert_votes(votes_valid);
+
+        dropped
     }
 
-    pub fn expire_votes_before(&mut self, t: isize) {
-        self.votes.retain(|_, v| (v.slot() as isize) >= t);
+    // keeps the votes of the eta slots from t on (none can be newer than that)
+    pub fn expire_votes_before(&mut self, t: isize, eta: u64) {
+        let t_max = t + eta as isize;
+        let mut deltas: BTreeMap<usize, WeightDelta> = BTreeMap::new();
+        let expired: Vec<Vote> = self
+            .votes
+            .drain_filter(|_, v| (v.slot() as isize) < t)
+            .map(|(_, v)| v)
+            .collect();
+        for v in expired.iter() {
+            self.count_ticket(&mut deltas, v, -1);
+        }
         assert!(
             self.votes
                 .iter()
-                .filter(|(_, v)| (v.slot() as isize) > t + 1)
+                .filter(|(_, v)| (v.slot() as isize) > t_max)
                 .count()
                 == 0
         );
-        for (_, votes) in self.votecount.iter_mut() {
-            votes.retain(|(_, t_)| (*t_ as isize) >= t);
-            assert!(
-                votes
-                    .iter()
-                    .filter(|(_, t_)| (*t_ as isize) > t + 1)
-                    .count()
-                    == 0
-            );
-        }
+        self.apply_deltas(deltas);
+    }
+
+    // among the descendants of h_root, counting the votes of the eta slots up to t
+    // (with latest_only, just the latest vote of every validator, if among them)
+    pub fn ghost_eph(
+        &self,
+        h_root: &goldfish_type::Hash,
+        t: isize,
+        eta: u64,
+        latest_only: bool,
+        min_votes: usize,
+    ) -> goldfish_type::Hash {
+        self.ghost(h_root, min_votes, |node| {
+            let weights = if latest_only {
+                &node.weights_latest
+            } else {
+                &node.weights
+            };
+            ProtoNode::weight(weights, t - eta as isize + 1, t)
+        })
     }
 
-    pub fn ghost_eph(&self, t: isize, min_votes: usize) -> goldfish_type::Hash {
-        let mut h = &Block::default().digest();
+    // GHOST among the descendants of h_root, weighing each block by the latest
+    // votes (cast in slot t_min or later) for it or its descendants
+    pub fn ghost_lmd(
+        &self,
+        h_root: &goldfish_type::Hash,
+        t_min: isize,
+        min_votes: usize,
+    ) -> goldfish_type::Hash {
+        self.ghost(h_root, min_votes, |node| {
+            ProtoNode::weight(&node.weights_latest, t_min, isize::MAX)
+        })
+    }
+
+    // follows the heaviest child from h_root while it has at least min_votes
+    fn ghost<F: Fn(&ProtoNode) -> usize>(
+        &self,
+        h_root: &goldfish_type::Hash,
+        min_votes: usize,
+        weight: F,
+    ) -> goldfish_type::Hash {
+        let mut h = h_root;
         while self.children.get(&h).unwrap().len() > 0 {
             let (h_, cnt) = self
                 .children
                 .get(&h)
                 .unwrap()
                 .iter()
-                .map(|c| {
-                    (
-                        c,
-                        self.votecount
-                            .get(c)
-                            .unwrap()
-                            .iter()
-                            .filter(|(_id, t_)| t == (*t_ as isize))
-                            .count(),
-                    )
-                })
+                .map(|c| (c, weight(&self.nodes[self.indices[c]])))
                 .max_by_key(|(_c, count)| *count)
                 .unwrap();
 
@@ -455,25 +1010,369 @@ impl BvTree {
         h.clone()
     }
 
+    // deepest block among the descendants of h_root, ignoring votes
+    // (ties broken by lottery priority, as for proposals)
+    pub fn longest_chain(&self, h_root: &goldfish_type::Hash) -> goldfish_type::Hash {
+        let mut best = (0, std::cmp::Reverse(0), h_root);
+        let mut stack = vec![(h_root, 0)];
+        while let Some((h, depth)) = stack.pop() {
+            let children = self.children.get(h).unwrap();
+            if children.is_empty() {
+                let key = (
+                    depth,
+                    std::cmp::Reverse(self.blocks.get(h).unwrap().prio()),
+                    h,
+                );
+                if (key.0, key.1) > (best.0, best.1) {
+                    best = key;
+                }
+            }
+            stack.extend(children.iter().map(|c| (c, depth + 1)));
+        }
+        best.2.clone()
+    }
+
+    // collapses everything below h_anchor into it: only genesis, h_anchor and its
+    // descendants are kept, with the votes for them (h_anchor must extend the
+    // current anchor)
+    pub fn prune(&mut self, h_anchor: &goldfish_type::Hash) {
+        if *h_anchor == self.anchor {
+            return;
+        }
+        let b0 = Block::default().digest();
+        let mut keep = HashSet::from([b0.clone()]);
+        let mut stack = vec![h_anchor.clone()];
+        while let Some(h) = stack.pop() {
+            stack.extend(self.children[&h].iter().cloned());
+            keep.insert(h);
+        }
+
+        // take the votes for discarded blocks out of the weights of genesis
+        let mut deltas: BTreeMap<usize, WeightDelta> = BTreeMap::new();
+        let discarded: Vec<Vote> = self
+            .votes
+            .drain_filter(|_, v| !keep.contains(&v.h))
+            .map(|(_, v)| v)
+            .collect();
+        for v in discarded.iter() {
+            self.count_ticket(&mut deltas, v, -1);
+        }
+        for (_, v) in self.latest_votes.drain_filter(|_, v| !keep.contains(&v.h)) {
+            let delta = deltas.entry(self.indices[&v.h]).or_default();
+            WeightDelta::add(&mut delta.weights_latest, v.slot(), -1);
+        }
+        self.apply_deltas(deltas);
+
+        self.blocks.retain(|h, _| keep.contains(h));
+        self.children.retain(|h, _| keep.contains(h));
+        self.children
+            .insert(b0.clone(), HashSet::from([h_anchor.clone()]));
+        self.tips.retain(|h| keep.contains(h));
+        self.header_only.retain(|h| keep.contains(h));
+
+        // renumber the remaining nodes, keeping their order and heights
+        let mut kept: Vec<(usize, goldfish_type::Hash)> = self
+            .indices
+            .drain()
+            .filter(|(h, _)| keep.contains(h))
+            .map(|(h, i)| (i, h))
+            .collect();
+        kept.sort_by_key(|(i, _)| *i);
+        let mut renumbered = HashMap::new();
+        let nodes = std::mem::take(&mut self.nodes);
+        for (i, h) in kept {
+            let mut node = nodes[i].clone();
+            node.parent = if h == *h_anchor {
+                Some(0)
+            } else {
+                node.parent.map(|parent| renumbered[&parent])
+            };
+            renumbered.insert(i, self.nodes.len());
+            self.indices.insert(h, self.nodes.len());
+            self.nodes.push(node);
+        }
+        self.anchor = h_anchor.clone();
+    }
+
+    pub fn anchor(&self) -> goldfish_type::Hash {
+        self.anchor.clone()
+    }
+
+    // once pruned, the anchor is the only child of genesis: other blocks building
+    // on genesis belong to the discarded history
+    fn is_discarded(&self, b: &Block) -> bool {
+        let b0 = Block::default().digest();
+        self.anchor != b0 && b.parent() == b0
+    }
+
+    // the tree is rooted at genesis, every block hangs off its parent (the anchor
+    // off genesis), and children, tips, nodes and votes agree with the blocks
+    #[cfg(test)]
+    pub fn check_tree(&self) -> Result<(), String> {
+        let b0 = Block::default().digest();
+        if self.indices.get(&b0) != Some(&0) || self.nodes[0].parent.is_some() {
+            return Err("genesis is not the root".to_string());
+        }
+        if !self.blocks.contains_key(&self.anchor) {
+            return Err(format!("anchor {:?} is missing", self.anchor));
+        }
+        let n = self.blocks.len();
+        if self.nodes.len() != n || self.indices.len() != n || self.children.len() != n {
+            return Err(format!(
+                "{} blocks, but {} nodes, {} indices and {} children",
+                n,
+                self.nodes.len(),
+                self.indices.len(),
+                self.children.len()
+            ));
+        }
+
+        for (h, b) in self.blocks.iter() {
+            if *h == b0 {
+                continue;
+            }
+            if b.digest() != *h {
+                return Err(format!("block {:?} is stored under {:?}", b.digest(), h));
+            }
+            let parent = self.parent(h);
+            if !self.blocks.contains_key(&parent) {
+                return Err(format!("parent of block {:?} is missing", h));
+            }
+            let (i, i_parent) = match (self.indices.get(h), self.indices.get(&parent)) {
+                (Some(i), Some(i_parent)) => (*i, *i_parent),
+                _ => return Err(format!("block {:?} or its parent has no node", h)),
+            };
+            if self.nodes[i].parent != Some(i_parent) {
+                return Err(format!("node of block {:?} has the wrong parent", h));
+            }
+            if *h != self.anchor && self.nodes[i].height != self.nodes[i_parent].height + 1 {
+                return Err(format!("block {:?} has the wrong height", h));
+            }
+            if b.slot() <= self.blocks[&parent].slot() {
+                return Err(format!("block {:?} is not after its parent", h));
+            }
+            if !self.children[&parent].contains(h) {
+                return Err(format!("block {:?} is not a child of its parent", h));
+            }
+            if b.payload().is_none() != self.header_only.contains(h) {
+                return Err(format!("block {:?} is wrongly (not) header-only", h));
+            }
+        }
+        if self
+            .header_only
+            .iter()
+            .any(|h| !self.blocks.contains_key(h))
+        {
+            return Err("a header-only block is missing from the blocks".to_string());
+        }
+
+        for (h, children) in self.children.iter() {
+            if children.iter().any(|c| self.parent(c) != *h) {
+                return Err(format!("block {:?} has a child of another block", h));
+            }
+            if children.is_empty() != self.tips.contains(h) {
+                return Err(format!("block {:?} is wrongly (not) a tip", h));
+            }
+        }
+        if self.tips.iter().any(|h| !self.blocks.contains_key(h)) {
+            return Err("a tip is missing from the blocks".to_string());
+        }
+
+        // every block reaches genesis, so there are no cycles
+        for h in self.blocks.keys() {
+            let mut h_ = h.clone();
+            for _ in 0..n {
+                if h_ == b0 {
+                    break;
+                }
+                h_ = self.parent(&h_);
+            }
+            if h_ != b0 {
+                return Err(format!("block {:?} does not reach genesis", h));
+            }
+        }
+
+        for v in self.votes.values() {
+            if !self.blocks.contains_key(&v.h) {
+                return Err(format!("vote {:?} for a missing block", v.digest()));
+            }
+        }
+
+        // the weights agree with a naive recount, in which every ticket counts once
+        // on each block it (or one of its equivocations) voted for or below
+        let mut covered: HashMap<goldfish_type::Ticket, HashSet<goldfish_type::Hash>> =
+            HashMap::new();
+        for v in self.votes.values() {
+            let covered = covered.entry(v.ticket).or_default();
+            let mut h = v.h.clone();
+            while covered.insert(h.clone()) && h != b0 {
+                h = self.parent(&h);
+            }
+        }
+        let mut weights: HashMap<goldfish_type::Hash, BTreeMap<goldfish_type::Slot, usize>> =
+            HashMap::new();
+        for (ticket, covered) in covered {
+            for h in covered {
+                *weights.entry(h).or_default().entry(ticket.1).or_default() += 1;
+            }
+        }
+        let mut weights_latest: HashMap<goldfish_type::Hash, BTreeMap<goldfish_type::Slot, usize>> =
+            HashMap::new();
+        for v in self.latest_votes.values() {
+            for b in self.ancestors(&v.h) {
+                *weights_latest
+                    .entry(b.digest())
+                    .or_default()
+                    .entry(v.slot())
+                    .or_default() += 1;
+            }
+            *weights_latest
+                .entry(b0.clone())
+                .or_default()
+                .entry(v.slot())
+                .or_default() += 1;
+        }
+        for (h, i) in self.indices.iter() {
+            if self.nodes[*i].weights != weights.remove(h).unwrap_or_default()
+                || self.nodes[*i].weights_latest != weights_latest.remove(h).unwrap_or_default()
+            {
+                return Err(format!("block {:?} has the wrong weights", h));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn contains_block(&self, h: &goldfish_type::Hash) -> bool {
+        self.blocks.contains_key(h)
+    }
+
+    pub fn contains_vote(&self, h: &goldfish_type::Hash) -> bool {
+        self.votes.contains_key(h)
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn vote_count(&self) -> usize {
+        self.votes.len()
+    }
+
+    // whether h is h_ancestor or one of its descendants
+    pub fn extends(&self, h: &goldfish_type::Hash, h_ancestor: &goldfish_type::Hash) -> bool {
+        let (mut i, i_ancestor) = match (self.indices.get(h), self.indices.get(h_ancestor)) {
+            (Some(i), Some(i_ancestor)) => (*i, *i_ancestor),
+            _ => return false,
+        };
+        while self.nodes[i].height > self.nodes[i_ancestor].height {
+            i = self.nodes[i].parent.unwrap();
+        }
+        i == i_ancestor
+    }
+
     pub fn get_block_height(&self, h: &goldfish_type::Hash) -> usize {
-        let mut h_ = h;
-        let mut height = 0;
-        while h_ != &Block::default().digest() {
-            height += 1;
-            let b = self.blocks.get(h_).unwrap();
-            h_ = &b.h;
+        self.nodes[self.indices[h]].height
+    }
+
+    // parent of h in the tree (genesis for the anchor)
+    pub fn parent(&self, h: &goldfish_type::Hash) -> goldfish_type::Hash {
+        if *h == self.anchor {
+            Block::default().digest()
+        } else {
+            self.blocks.get(h).unwrap().parent()
+        }
+    }
+
+    // deepest block on the chain of h up to which all payloads are at hand
+    pub fn payload_tip(&self, h: &goldfish_type::Hash) -> goldfish_type::Hash {
+        let height_min = match self
+            .header_only
+            .iter()
+            .map(|h| self.get_block_height(h))
+            .min()
+        {
+            Some(height) => height,
+            None => return h.clone(),
+        };
+        let mut h_tip = h.clone();
+        for b in self
+            .ancestors(h)
+            .take_while(|b| self.get_block_height(&b.digest()) >= height_min)
+        {
+            if b.payload().is_none() {
+                h_tip = self.parent(&b.digest());
+            }
         }
-        height
+        h_tip
+    }
+
+    pub fn header_only(&self) -> impl Iterator<Item = &goldfish_type::Hash> {
+        self.header_only.iter()
+    }
+
+    // ancestor of h (or h itself) at the given height, which must not be below the anchor
+    pub fn ancestor_at_height(
+        &self,
+        h: &goldfish_type::Hash,
+        height: usize,
+    ) -> goldfish_type::Hash {
+        let mut h_ = h.clone();
+        for _ in height..self.get_block_height(h) {
+            h_ = self.parent(&h_);
+        }
+        h_
+    }
+
+    // blocks from h back to (excluding) genesis, or to the anchor once pruned
+    pub fn ancestors<'a>(&'a self, h: &goldfish_type::Hash) -> impl Iterator<Item = &'a Block> {
+        let b0 = Block::default().digest();
+        let mut h_ = h.clone();
+        std::iter::from_fn(move || {
+            if h_ == b0 {
+                return None;
+            }
+            let b = self.blocks.get(&h_).unwrap();
+            h_ = self.parent(&h_);
+            Some(b)
+        })
+    }
+
+    pub fn get_block_votecount(&self, h: &goldfish_type::Hash) -> usize {
+        self.indices
+            .get(h)
+            .map_or(0, |i| self.nodes[*i].weights.values().sum())
+    }
+
+    pub fn get_votes_in_slot(&self, t: goldfish_type::Slot) -> Vec<Vote> {
+        let mut votes: Vec<Vote> = self
+            .votes
+            .values()
+            .filter(|v| v.slot() == t)
+            .cloned()
+            .collect();
+        votes.sort_by_key(|v| v.ticket);
+        votes
+    }
+
+    pub fn get_votes_since(&self, t: goldfish_type::Slot) -> Vec<Vote> {
+        let mut votes: Vec<Vote> = self
+            .votes
+            .values()
+            .filter(|v| v.slot() >= t)
+            .cloned()
+            .collect();
+        votes.sort_by_key(|v| v.ticket);
+        votes
     }
 
     pub fn truncate_back_to_slot(&self, h: &goldfish_type::Hash, t: isize) -> goldfish_type::Hash {
-        let mut h_ = h;
-        let mut b = self.blocks.get(h_).unwrap();
-        while h_ != &Block::default().digest() && (b.ticket.1 as isize) > t {
-            h_ = &b.h;
-            b = self.blocks.get(h_).unwrap();
+        let mut h_ = h.clone();
+        let mut b = self.blocks.get(&h_).unwrap();
+        while h_ != Block::default().digest() && (b.slot() as isize) > t {
+            h_ = self.parent(&h_);
+            b = self.blocks.get(&h_).unwrap();
         }
-        h_.clone()
+        h_
     }
 
     //     kappa: usize) -> goldfish_type::Hash {
@@ -504,7 +1403,7 @@ impl BvTree {
                 v,
                 export_digest(b.digest()),
                 format!("{:?}", b.digest()).replace("\"", ""),
-                b.payload.graffiti.replace("\"", "")
+                b.graffiti().replace("\"", "")
             );
         }
         for b in self.blocks.values() {
@@ -513,7 +1412,7 @@ impl BvTree {
                     "{}  b_{} -> b_{};\n",
                     v,
                     export_digest(b.digest()),
-                    export_digest(b.h.clone())
+                    export_digest(self.parent(&b.digest()))
                 );
             }
         }
@@ -1,11 +1,13 @@
+use std::borrow::Cow;
 use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ffg;
 use crate::ghash;
 use crate::goldfish_blockvote;
 use crate::goldfish_type;
-use crate::{sig, sig::Scheme};
+use crate::{sig, sig::HashingBytes as _, sig::Scheme};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Piece {
@@ -27,6 +29,8 @@ impl Piece {
         match self {
             Piece::Block(b) => {
                 b.digest_hasher_update(hasher);
+                // so that a block is not taken for a duplicate of its header
+                hasher.update(&[b.payload().is_some() as u8]);
             }
             Piece::Vote(v) => {
                 v.digest_hasher_update(hasher);
@@ -48,11 +52,55 @@ impl Piece {
     }
 }
 
+// compact proposals carry only the header; the block itself is relayed or fetched separately
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProposalBlock {
+    Full(goldfish_blockvote::Block),
+    Header(goldfish_blockvote::BlockHeader),
+}
+
+impl ProposalBlock {
+    pub fn digest(&self) -> goldfish_type::Hash {
+        match self {
+            ProposalBlock::Full(b) => b.digest(),
+            ProposalBlock::Header(header) => header.digest(),
+        }
+    }
+
+    pub fn slot(&self) -> goldfish_type::Slot {
+        match self {
+            ProposalBlock::Full(b) => b.slot(),
+            ProposalBlock::Header(header) => header.slot(),
+        }
+    }
+
+    pub fn prio(&self) -> u64 {
+        match self {
+            ProposalBlock::Full(b) => b.prio(),
+            ProposalBlock::Header(header) => header.prio(),
+        }
+    }
+
+    pub fn data_root(&self) -> goldfish_type::Hash {
+        match self {
+            ProposalBlock::Full(b) => b.data_root(),
+            ProposalBlock::Header(header) => header.data_root(),
+        }
+    }
+
+    pub fn parent(&self) -> goldfish_type::Hash {
+        match self {
+            ProposalBlock::Full(b) => b.parent(),
+            ProposalBlock::Header(header) => header.parent(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Proposal {
     tips: HashSet<goldfish_type::Hash>,
     votes: HashSet<goldfish_type::Hash>,
-    b: goldfish_blockvote::Block,
+    b: ProposalBlock,
     sigma: <goldfish_type::Sigs as sig::Scheme>::Sig,
 }
 
@@ -67,6 +115,10 @@ impl Proposal {
 
     pub fn digest_hasher_update(&self, hasher: &mut ghash::Ghasher) {
         hasher.update(b"proposal");
+        // full and compact versions share the signature, but are different messages
+        if let ProposalBlock::Header(_) = self.b {
+            hasher.update(b"compact");
+        }
         self.inner_digest_hasher_update(hasher);
         hasher.update(&self.sigma.as_bytes_for_hashing());
     }
@@ -96,7 +148,7 @@ impl Proposal {
         for v in &self.votes {
             hasher.update(v.as_bytes());
         }
-        self.b.digest_hasher_update(hasher);
+        hasher.update(self.b.digest().as_bytes());
     }
 
     pub fn create(
@@ -106,8 +158,8 @@ impl Proposal {
     ) -> Self {
         let mut p = Self {
             tips: bvtree.tip_digests_for_proposal(),
-            votes: bvtree.vote_digests_for_proposal(),
-            b,
+            votes: bvtree.vote_digests_for_proposal(b.slot()),
+            b: ProposalBlock::Full(b),
             sigma: <goldfish_type::Sigs as sig::Scheme>::Sig::default(),
         };
         p.sigma = goldfish_type::Sigs::sign(sk_sig, &p.inner_digest().as_slice());
@@ -121,11 +173,24 @@ impl Proposal {
         pki: &goldfish_type::Pki,
         bvset: &dyn goldfish_blockvote::BvSet,
     ) -> goldfish_type::ValidationResult {
+        if let ProposalBlock::Full(b) = &self.b {
+            if let Err(e) = b.check_payload() {
+                return goldfish_type::ValidationResult::Invalid(e);
+            }
+        }
         if let Some(ret) = cache.get(&self.digest()) {
             return *ret;
         }
 
-        let block_valid = self.b.is_valid(lotteries, cache, pki, bvset);
+        let b = match &self.b {
+            ProposalBlock::Full(b) => Cow::Borrowed(b),
+            ProposalBlock::Header(header) => match bvset.get_block(header.digest()) {
+                Some(b) => Cow::Owned(b),
+                None => return goldfish_type::ValidationResult::Unknown,
+            },
+        };
+
+        let block_valid = b.is_valid_header(lotteries, cache, pki, bvset);
         if block_valid != goldfish_type::ValidationResult::Valid {
             if block_valid != goldfish_type::ValidationResult::Unknown {
                 cache.insert(self.digest(), block_valid);
@@ -133,50 +198,57 @@ impl Proposal {
             return block_valid;
         }
 
-        let id = self.b.id();
-        let (pk_sig, _pk_vrf) = pki.get(&id).unwrap();
+        let invalid = |cache: &mut C, e| {
+            let ret = goldfish_type::ValidationResult::Invalid(e);
+            cache.insert(self.digest(), ret);
+            ret
+        };
+
+        let pk_sig = match pki.get(&b.id()) {
+            Some((pk_sig, _pk_vrf)) => pk_sig,
+            None => return invalid(cache, goldfish_type::ValidationError::UnknownSigner),
+        };
         if !goldfish_type::Sigs::verify(pk_sig, &self.inner_digest().as_slice(), &self.sigma) {
-            cache.insert(self.digest(), goldfish_type::ValidationResult::Invalid);
-            return goldfish_type::ValidationResult::Invalid;
+            return invalid(cache, goldfish_type::ValidationError::BadSignature);
         }
 
         for hash_b in &self.tips {
-            let b = bvset.get_block(hash_b.clone());
-            if b == None {
-                return goldfish_type::ValidationResult::Unknown;
-            }
-            let b = b.unwrap();
-            let b_valid = b.is_valid(lotteries, cache, pki, bvset);
-            if b_valid != goldfish_type::ValidationResult::Valid {
-                if b_valid != goldfish_type::ValidationResult::Unknown {
-                    cache.insert(self.digest(), b_valid);
+            let b = match bvset.get_block(hash_b.clone()) {
+                Some(b) => b,
+                None => return goldfish_type::ValidationResult::Unknown,
+            };
+            match b.is_valid_header(lotteries, cache, pki, bvset) {
+                goldfish_type::ValidationResult::Valid => {}
+                goldfish_type::ValidationResult::Unknown => {
+                    return goldfish_type::ValidationResult::Unknown;
+                }
+                goldfish_type::ValidationResult::Invalid(_) => {
+                    return invalid(cache, goldfish_type::ValidationError::InvalidReference);
                 }
-                return b_valid;
             }
             if b.slot() >= self.b.slot() {
-                cache.insert(self.digest(), goldfish_type::ValidationResult::Invalid);
-                return goldfish_type::ValidationResult::Invalid;
+                return invalid(cache, goldfish_type::ValidationError::NonIncreasingSlot);
             }
         }
 
         for hash_v in &self.votes {
-            let v = bvset.get_vote(hash_v.clone());
-            if v == None {
-                return goldfish_type::ValidationResult::Unknown;
-            }
-            let v = v.unwrap();
-            let v_valid = v.is_valid(lotteries, cache, pki, bvset);
-            if v_valid != goldfish_type::ValidationResult::Valid {
-                if v_valid != goldfish_type::ValidationResult::Unknown {
-                    cache.insert(self.digest(), v_valid);
+            let v = match bvset.get_vote(hash_v.clone()) {
+                Some(v) => v,
+                None => return goldfish_type::ValidationResult::Unknown,
+            };
+            match v.is_valid(lotteries, cache, pki, bvset) {
+                goldfish_type::ValidationResult::Valid => {}
+                goldfish_type::ValidationResult::Unknown => {
+                    return goldfish_type::ValidationResult::Unknown;
+                }
+                goldfish_type::ValidationResult::Invalid(_) => {
+                    return invalid(cache, goldfish_type::ValidationError::InvalidReference);
                 }
-                return v_valid;
             }
             // if v.slot() >= self.b.slot() {
             if self.b.slot() == 0 || v.slot() != self.b.slot() - 1 {
                 // can only include votes from the previous slot
-                cache.insert(self.digest(), goldfish_type::ValidationResult::Invalid);
-                return goldfish_type::ValidationResult::Invalid;
+                return invalid(cache, goldfish_type::ValidationError::BadVoteSlot);
             }
         }
 
@@ -200,8 +272,80 @@ impl Proposal {
         self.votes.clone()
     }
 
+    // only the header of the block for compact proposals, see `to_full`
     pub fn b(&self) -> goldfish_blockvote::Block {
-        self.b.clone()
+        match &self.b {
+            ProposalBlock::Full(b) => b.clone(),
+            ProposalBlock::Header(header) => goldfish_blockvote::Block::from_header(header.clone()),
+        }
+    }
+
+    pub fn block_digest(&self) -> goldfish_type::Hash {
+        self.b.digest()
+    }
+
+    pub fn header(&self) -> goldfish_blockvote::BlockHeader {
+        match &self.b {
+            ProposalBlock::Full(b) => b.header(),
+            ProposalBlock::Header(header) => header.clone(),
+        }
+    }
+
+    // none if compact, or if only the header of the block is at hand
+    pub fn payload(&self) -> Option<&goldfish_blockvote::Payload> {
+        match &self.b {
+            ProposalBlock::Full(b) => b.payload(),
+            ProposalBlock::Header(_) => None,
+        }
+    }
+
+    pub fn data_root(&self) -> goldfish_type::Hash {
+        self.b.data_root()
+    }
+
+    pub fn parent(&self) -> goldfish_type::Hash {
+        self.b.parent()
+    }
+
+    pub fn is_compact(&self) -> bool {
+        matches!(self.b, ProposalBlock::Header(_))
+    }
+
+    pub fn to_compact(&self) -> Self {
+        let b = match &self.b {
+            ProposalBlock::Full(b) => ProposalBlock::Header(b.header()),
+            ProposalBlock::Header(header) => ProposalBlock::Header(header.clone()),
+        };
+        Self {
+            tips: self.tips.clone(),
+            votes: self.votes.clone(),
+            b,
+            sigma: self.sigma.clone(),
+        }
+    }
+
+    // the full proposal, if the block is known
+    pub fn to_full(&self, bvset: &dyn goldfish_blockvote::BvSet) -> Option<Self> {
+        let b = match &self.b {
+            ProposalBlock::Full(b) => b.clone(),
+            ProposalBlock::Header(header) => bvset.get_block(header.digest())?,
+        };
+        Some(Self {
+            tips: self.tips.clone(),
+            votes: self.votes.clone(),
+            b: ProposalBlock::Full(b),
+            sigma: self.sigma.clone(),
+        })
+    }
+
+    // bytes saved by sending the header instead of the block
+    pub fn saved_size(&self) -> usize {
+        match &self.b {
+            ProposalBlock::Full(_) => 0,
+            ProposalBlock::Header(header) => (header.payload_size() as usize).saturating_sub(
+                bincode::serialized_size(&(goldfish_type::Hash::default(), 0u64)).unwrap() as usize,
+            ),
+        }
     }
 }
 
@@ -209,6 +353,7 @@ impl Proposal {
 pub enum Message {
     Piece(Piece),
     Proposal(Proposal),
+    FfgVote(ffg::FfgVote),
 }
 
 impl Message {
@@ -224,6 +369,7 @@ impl Message {
         match self {
             Message::Piece(p) => p.digest_hasher_update(hasher),
             Message::Proposal(p) => p.digest_hasher_update(hasher),
+            Message::FfgVote(v) => v.digest_hasher_update(hasher),
         }
     }
 
@@ -234,12 +380,14 @@ impl Message {
                 Piece::Vote(v) => v.slot(),
             },
             Message::Proposal(p) => p.b.slot(),
+            Message::FfgVote(v) => v.slot(),
         }
     }
 
     pub fn is_valid<C: goldfish_type::ValidationCache>(
         &self,
         lotteries: &goldfish_type::Lotteries,
+        ffg_config: &ffg::FfgConfig,
         cache: &mut C,
         pki: &goldfish_type::Pki,
         bvset: &dyn goldfish_blockvote::BvSet,
@@ -247,6 +395,7 @@ impl Message {
         match self {
             Message::Piece(p) => p.is_valid(lotteries, cache, pki, bvset),
             Message::Proposal(p) => p.is_valid(lotteries, cache, pki, bvset),
+            Message::FfgVote(v) => v.is_valid(ffg_config, cache, pki, bvset),
         }
     }
 
@@ -1,13 +1,19 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::ghash;
 use crate::lottery;
 use crate::sig;
 use crate::vrf;
 
-// pub type Sigs = sig::MockScheme;
-// pub type Vrfs = vrf::MockScheme;
+// tests run with mock crypto, which is fast and actually checks signatures
+#[cfg(test)]
+pub type Sigs = sig::MockScheme;
+#[cfg(test)]
+pub type Vrfs = vrf::MockScheme;
+#[cfg(not(test))]
 pub type Sigs = sig::MilagroBlsScheme;
+#[cfg(not(test))]
 pub type Vrfs = vrf::MilagroBlsVrfScheme;
 pub type Hash = ghash::Ghash;
 
@@ -31,11 +37,71 @@ impl Lotteries {
     }
 }
 
+// why a message is invalid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValidationError {
+    UnknownSigner,
+    BadLottery,
+    BadSignature,
+    // not after the slot of the block it builds on (or votes for)
+    NonIncreasingSlot,
+    InvalidParent,
+    // a tip or vote of a proposal, or the target of a vote, is invalid
+    InvalidReference,
+    // proposals can only include votes from the previous slot
+    BadVoteSlot,
+    // the source of an FFG vote is not an ancestor of its target in an earlier epoch
+    BadLink,
+    // an FFG vote not for the epoch of its slot, or with a checkpoint block from
+    // after the start of the checkpoint epoch
+    BadEpoch,
+    // the payload of a block does not match the commitments in its header
+    BadPayload,
+    // the payload of a block is not the one erasure-coded under its data root
+    BadDataRoot,
+    // a block without its payload, but payloads are not sampled (see --das)
+    MissingPayload,
+}
+
+impl ValidationError {
+    pub const ALL: [ValidationError; 12] = [
+        ValidationError::UnknownSigner,
+        ValidationError::BadLottery,
+        ValidationError::BadSignature,
+        ValidationError::NonIncreasingSlot,
+        ValidationError::InvalidParent,
+        ValidationError::InvalidReference,
+        ValidationError::BadVoteSlot,
+        ValidationError::BadLink,
+        ValidationError::BadEpoch,
+        ValidationError::BadPayload,
+        ValidationError::BadDataRoot,
+        ValidationError::MissingPayload,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValidationError::UnknownSigner => "unknown-signer",
+            ValidationError::BadLottery => "bad-lottery",
+            ValidationError::BadSignature => "bad-signature",
+            ValidationError::NonIncreasingSlot => "non-increasing-slot",
+            ValidationError::InvalidParent => "invalid-parent",
+            ValidationError::InvalidReference => "invalid-reference",
+            ValidationError::BadVoteSlot => "bad-vote-slot",
+            ValidationError::BadLink => "bad-link",
+            ValidationError::BadEpoch => "bad-epoch",
+            ValidationError::BadPayload => "bad-payload",
+            ValidationError::BadDataRoot => "bad-data-root",
+            ValidationError::MissingPayload => "missing-payload",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ValidationResult {
-    Invalid = 0,
-    Valid = 1,
-    Unknown = 2,
+    Invalid(ValidationError),
+    Valid,
+    Unknown,
 }
 
 pub trait ValidationCache {
@@ -53,6 +119,45 @@ impl ValidationCache for HashMap<Hash, ValidationResult> {
     }
 }
 
+#[derive(Debug, Default)]
+pub struct CountingValidationCache<C> {
+    cache: C,
+    // atomics, since lookups take `&self` and validators are shared across threads
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl<C> CountingValidationCache<C> {
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl CountingValidationCache<HashMap<Hash, ValidationResult>> {
+    pub fn retain<F: FnMut(&Hash) -> bool>(&mut self, mut f: F) {
+        self.cache.retain(|h, _| f(h));
+    }
+}
+
+impl<C: ValidationCache> ValidationCache for CountingValidationCache<C> {
+    fn get(&self, hash: &Hash) -> Option<&ValidationResult> {
+        let ret = self.cache.get(hash);
+        match ret {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        ret
+    }
+
+    fn insert(&mut self, hash: Hash, result: ValidationResult) {
+        self.cache.insert(hash, result);
+    }
+}
+
 pub struct RoValidationCache<'a, C> {
     cache: &'a C,
 }
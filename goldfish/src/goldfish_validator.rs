@@ -1,15 +1,28 @@
+use clap::ValueEnum as _;
 use log;
 use serde::{Deserialize, Serialize};
 use serde_with::with_prefix;
 use std::collections::{HashMap, HashSet, VecDeque};
-
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::confirmation;
+use crate::das;
+use crate::execution;
+use crate::ffg;
 use crate::goldfish_blockvote;
 use crate::goldfish_blockvote::BvSet as _;
 use crate::goldfish_message;
 use crate::goldfish_type;
 use crate::lottery::Lottery as _;
+use crate::mempool;
 use crate::network;
 use crate::sig;
+use crate::slot;
+use crate::store;
+use crate::sync;
+use crate::transaction;
 use crate::vrf;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -17,6 +30,8 @@ use crate::vrf;
 pub struct LedgerStats {
     pub length: usize,
     pub age: goldfish_type::Slot,
+    pub txs: usize,
+    pub tx_latency_sum: usize,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -28,16 +43,197 @@ pub struct ValidatorLedgerStats {
     pub ledger_fast: LedgerStats,
     #[serde(flatten, with = "prefix_ledger_slow")]
     pub ledger_slow: LedgerStats,
+    #[serde(flatten, with = "prefix_ledger_final")]
+    pub ledger_final: LedgerStats,
 }
 
 with_prefix!(prefix_ledger_best "ledger_best_");
 with_prefix!(prefix_ledger_fast "ledger_fast_");
 with_prefix!(prefix_ledger_slow "ledger_slow_");
+with_prefix!(prefix_ledger_final "ledger_final_");
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct ValidatorDaStats {
     pub status: DaValidatorSleepStatus,
+    // messages lost in this round while asleep or crashed
+    pub msgs_missed: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ValidatorBufferSizes {
+    pub limbo: usize,
+    pub buffer_blocks: usize,
+    pub buffer_votes: usize,
+    pub buffer_proposals: usize,
+    pub msgs_relayed: usize,
+    pub mempool: usize,
+    pub tree_blocks: usize,
+    pub tree_votes: usize,
+}
+
+// transactions in a ledger, and the total rounds they took from submission into the ledger
+#[derive(Debug, Clone)]
+struct LedgerTxTracker {
+    tip: goldfish_type::Hash,
+    txs: usize,
+    tx_latency_sum: usize,
+    // latency added per block of the ledger, to be taken back when the block is rolled back
+    latencies: HashMap<goldfish_type::Hash, usize>,
+    anchor: goldfish_type::Hash,
+}
+
+impl Default for LedgerTxTracker {
+    fn default() -> Self {
+        Self {
+            tip: goldfish_blockvote::Block::default().digest(),
+            txs: 0,
+            tx_latency_sum: 0,
+            latencies: HashMap::new(),
+            anchor: goldfish_blockvote::Block::default().digest(),
+        }
+    }
+}
+
+impl LedgerTxTracker {
+    // returns the digests of the transactions that entered the ledger
+    fn advance(
+        &mut self,
+        bvtree: &goldfish_blockvote::BvTree,
+        tip: &goldfish_type::Hash,
+        r: usize,
+    ) -> HashSet<goldfish_type::Hash> {
+        let mut added = HashSet::new();
+        if *tip == self.tip {
+            return added;
+        }
+
+        // walk both tips back to their common ancestor
+        let mut h_old = self.tip.clone();
+        let mut h_new = tip.clone();
+        let mut height_old = bvtree.get_block_height(&h_old);
+        let mut height_new = bvtree.get_block_height(&h_new);
+        while h_old != h_new {
+            if height_new >= height_old {
+                let b = bvtree.ancestors(&h_new).next().unwrap();
+                let mut latency = 0;
+                for tx in b.txs() {
+                    self.txs += 1;
+                    // the creation round is up to the proposer, and may be in the future
+                    latency += r.saturating_sub(tx.created());
+                    added.insert(tx.digest());
+                }
+                self.tx_latency_sum += latency;
+                self.latencies.insert(h_new.clone(), latency);
+                h_new = bvtree.parent(&h_new);
+                height_new = bvtree.get_block_height(&h_new);
+            } else {
+                let b = bvtree.ancestors(&h_old).next().unwrap();
+                self.txs -= b.txs().len();
+                self.tx_latency_sum -= self.latencies.remove(&h_old).unwrap_or(0);
+                h_old = bvtree.parent(&h_old);
+                height_old = bvtree.get_block_height(&h_old);
+            }
+        }
+
+        // blocks below the anchor are never rolled back
+        if bvtree.anchor() != self.anchor {
+            self.anchor = bvtree.anchor();
+            self.latencies.retain(|h, _| bvtree.contains_block(h));
+        }
+
+        self.tip = tip.clone();
+        added
+    }
+}
+
+/// Fork choice run by honest validators
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Protocol {
+    /// GHOST-Eph on the votes of the previous slot, with vote buffering (Goldfish)
+    Goldfish,
+    /// GHOST on the latest vote of every validator, merged as soon as it arrives
+    LmdGhost,
+    /// Longest chain, without votes (Sleepy/Ouroboros-style)
+    LongestChain,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PhaseTiming {
+    pub count: usize,
+    pub total: Duration,
+}
+
+// the options of honest validators, shared by simulations, nodes and testnets
+#[derive(clap::Args, Debug, Clone)]
+pub struct ValidatorConfig {
+    /// Slow confirmation kappa
+    #[arg(long, default_value_t = 10)]
+    pub confirm_slow_kappa: u64,
+
+    /// Fast confirmation epsilon
+    #[arg(long, default_value_t = 0.001)]
+    pub confirm_fast_eps: f64,
+
+    /// Fork choice of honest validators
+    #[arg(long, value_enum, default_value_t = Protocol::Goldfish)]
+    pub protocol: Protocol,
+
+    /// Rounds of a slot and their phases: PHASE[:ROUNDS],... (e.g., propose:2,vote,fast-confirm:0,slow-confirm)
+    #[arg(long, default_value_t = slot::SlotSchedule::default())]
+    pub slot_schedule: slot::SlotSchedule,
+
+    #[command(flatten)]
+    pub mempool: mempool::MempoolConfig,
+
+    #[command(flatten)]
+    pub das: das::DasConfig,
+
+    #[command(flatten)]
+    pub ffg: ffg::FfgConfig,
+
+    #[command(flatten)]
+    pub confirmation: confirmation::ConfirmationConfig,
+
+    #[command(flatten)]
+    pub expiry: goldfish_blockvote::ExpiryConfig,
+
+    #[command(flatten)]
+    pub prune: goldfish_blockvote::PruneConfig,
+
+    #[command(flatten)]
+    pub sleep: SleepConfig,
+
+    #[command(flatten)]
+    pub sync: sync::SyncConfig,
+}
+
+impl ValidatorConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--confirm-slow-kappa".to_string(),
+            self.confirm_slow_kappa.to_string(),
+            "--confirm-fast-eps".to_string(),
+            self.confirm_fast_eps.to_string(),
+            "--protocol".to_string(),
+            self.protocol
+                .to_possible_value()
+                .unwrap()
+                .get_name()
+                .to_string(),
+            "--slot-schedule".to_string(),
+            self.slot_schedule.to_string(),
+        ];
+        args.extend(self.mempool.to_args());
+        args.extend(self.das.to_args());
+        args.extend(self.ffg.to_args());
+        args.extend(self.confirmation.to_args());
+        args.extend(self.expiry.to_args());
+        args.extend(self.prune.to_args());
+        args.extend(self.sleep.to_args());
+        args.extend(self.sync.to_args());
+        args
+    }
 }
 
 pub trait HonestValidator {
@@ -45,8 +241,7 @@ pub trait HonestValidator {
         &mut self,
         lotteries: &goldfish_type::Lotteries,
         r: usize,
-        inboxes: &mut Vec<network::SimulationInbox>,
-        my_inbox: usize,
+        transport: &mut dyn network::Transport,
     );
 }
 
@@ -56,18 +251,38 @@ pub struct Validator {
     sk_sig: <goldfish_type::Sigs as sig::Scheme>::Sk,
     sk_vrf: <goldfish_type::Vrfs as vrf::Scheme>::Sk,
     pki: goldfish_type::Pki,
+    protocol: Protocol,
+    slot_schedule: slot::SlotSchedule,
     bvtree: goldfish_blockvote::BvTree,
     limbo: VecDeque<goldfish_message::Message>,
-    msgs_relayed: HashSet<goldfish_type::Hash>,
+    // with the slot of every message, to forget them once pruned
+    msgs_relayed: HashMap<goldfish_type::Hash, goldfish_type::Slot>,
     buffer_blocks: HashMap<goldfish_type::Hash, goldfish_blockvote::Block>,
     buffer_votes: HashMap<goldfish_type::Hash, goldfish_blockvote::Vote>,
     buffer_proposals: Vec<goldfish_message::Proposal>,
+    // blocks asked for while rejoining, with the round of the last request and the attempts
+    sync_requested: HashMap<goldfish_type::Hash, (usize, usize)>,
     confirm_slow_kappa: usize,
-    confirm_fast_eps: f64,
-    validation_cache: HashMap<goldfish_type::Hash, goldfish_type::ValidationResult>,
+    confirm_fast_rule: Box<dyn confirmation::ConfirmationRule>,
+    vote_expiry: goldfish_blockvote::ExpiryConfig,
+    prune_config: goldfish_blockvote::PruneConfig,
+    compact_proposals: bool,
+    das_sampler: das::Sampler,
+    ffg_config: ffg::FfgConfig,
+    ffg: ffg::Ffg,
+    validation_cache: goldfish_type::CountingValidationCache<
+        HashMap<goldfish_type::Hash, goldfish_type::ValidationResult>,
+    >,
+    phase_timings: [PhaseTiming; slot::Phase::ALL.len()],
+    mempool: mempool::Mempool,
+    ledger_trackers: [LedgerTxTracker; 4],
+    executor: execution::Executor<execution::AccountState>,
     tip_fast: goldfish_type::Hash,
     tip_slow: goldfish_type::Hash,
     tip_best: goldfish_type::Hash,
+    store: Box<dyn store::Store>,
+    // messages dropped for being invalid, by reason
+    msgs_invalid: HashMap<goldfish_type::ValidationError, usize>,
     stats: HashMap<usize, ValidatorLedgerStats>,
 }
 
@@ -77,95 +292,295 @@ impl Validator {
         sk_sig: <goldfish_type::Sigs as sig::Scheme>::Sk,
         sk_vrf: <goldfish_type::Vrfs as vrf::Scheme>::Sk,
         pki: goldfish_type::Pki,
-        confirm_slow_kappa: usize,
-        confirm_fast_eps: f64,
+        config: ValidatorConfig,
+        store: Box<dyn store::Store>,
     ) -> Self {
+        let n = pki.len();
         Self {
             id,
             sk_sig,
             sk_vrf,
             pki,
+            protocol: config.protocol,
+            slot_schedule: config.slot_schedule,
             bvtree: goldfish_blockvote::BvTree::default(),
             limbo: VecDeque::new(),
-            msgs_relayed: HashSet::new(),
+            msgs_relayed: HashMap::new(),
             buffer_blocks: HashMap::new(),
             buffer_votes: HashMap::new(),
             buffer_proposals: Vec::new(),
-            confirm_slow_kappa,
-            confirm_fast_eps,
-            validation_cache: HashMap::new(),
+            sync_requested: HashMap::new(),
+            confirm_slow_kappa: config.confirm_slow_kappa as usize,
+            confirm_fast_rule: config.confirmation.build(config.confirm_fast_eps),
+            vote_expiry: config.expiry,
+            prune_config: config.prune,
+            // with DAS, voters sample payloads instead of downloading them
+            compact_proposals: config.mempool.compact_proposals || config.das.das,
+            das_sampler: das::Sampler::new(config.das),
+            ffg_config: config.ffg,
+            ffg: ffg::Ffg::new(n),
+            validation_cache: goldfish_type::CountingValidationCache::default(),
+            phase_timings: [PhaseTiming::default(); slot::Phase::ALL.len()],
+            mempool: mempool::Mempool::new(config.mempool),
+            ledger_trackers: Default::default(),
+            executor: execution::Executor::default(),
             tip_fast: goldfish_blockvote::Block::default().digest(),
             tip_slow: goldfish_blockvote::Block::default().digest(),
             tip_best: goldfish_blockvote::Block::default().digest(),
+            store,
+            msgs_invalid: HashMap::new(),
             stats: HashMap::new(),
         }
     }
 
-    fn broadcast(msg: &goldfish_message::Message, inboxes: &mut Vec<network::SimulationInbox>) {
+    fn broadcast(msg: &goldfish_message::Message, transport: &mut dyn network::Transport) {
         log::debug!("Broadcasting: {:?}", msg);
 
-        for inbox in inboxes {
-            inbox.make_available(msg);
+        transport.broadcast(msg);
+    }
+
+    // if the best proposal of slot t so far is compact, make sure its block is at hand for
+    // voting (with DAS, only its header, the payload being sampled)
+    fn fetch_best_proposal_block(
+        &mut self,
+        t: goldfish_type::Slot,
+        transport: &mut dyn network::Transport,
+    ) {
+        let best = self
+            .limbo
+            .iter()
+            .filter_map(|m| match m {
+                goldfish_message::Message::Proposal(p) => Some(p),
+                _ => None,
+            })
+            .chain(self.buffer_proposals.iter())
+            .filter(|p| p.slot() == t)
+            .min_by_key(|p| p.prio());
+        let (h, header) = match best {
+            Some(p) if p.is_compact() => (p.block_digest(), p.header()),
+            _ => return,
+        };
+
+        if self.bvtree.get_block(h.clone()).is_some()
+            || self.buffer_blocks.contains_key(&h)
+            || self.limbo.iter().any(|m| match m {
+                goldfish_message::Message::Piece(goldfish_message::Piece::Block(b)) => {
+                    b.digest() == h
+                }
+                _ => false,
+            })
+        {
+            return;
+        }
+
+        if self.das_sampler.config().das {
+            self.limbo.push_back(goldfish_message::Message::Piece(
+                goldfish_message::Piece::Block(goldfish_blockvote::Block::from_header(header)),
+            ));
+            return;
+        }
+        match transport.fetch_block(&h) {
+            Some(b) => {
+                log::debug!("Fetched block {:?}", h);
+                self.limbo.push_back(goldfish_message::Message::Piece(
+                    goldfish_message::Piece::Block(b),
+                ));
+            }
+            None => log::debug!("Could not fetch block {:?}", h),
         }
     }
 
-    #[allow(dead_code)]
-    pub fn stats(&self) -> HashMap<usize, ValidatorLedgerStats> {
-        self.stats.clone()
+    // blocks merged with only their header (see fetch_best_proposal_block) get their
+    // payload fetched afterwards, for execution
+    fn fetch_payloads(&mut self, transport: &mut dyn network::Transport) {
+        let has_payload = |b: &goldfish_blockvote::Block| b.payload().is_some();
+        let missing: Vec<goldfish_type::Hash> = self
+            .bvtree
+            .header_only()
+            .filter(|h| !self.buffer_blocks.get(*h).map_or(false, has_payload))
+            .filter(|h| {
+                !self.limbo.iter().any(|m| match m {
+                    goldfish_message::Message::Piece(goldfish_message::Piece::Block(b)) => {
+                        b.digest() == **h && has_payload(b)
+                    }
+                    _ => false,
+                })
+            })
+            .cloned()
+            .collect();
+
+        for h in missing {
+            match transport.fetch_block(&h) {
+                Some(b) if has_payload(&b) => {
+                    log::debug!("Fetched payload of block {:?}", h);
+                    self.limbo.push_back(goldfish_message::Message::Piece(
+                        goldfish_message::Piece::Block(b),
+                    ));
+                }
+                _ => log::debug!("Could not fetch payload of block {:?}", h),
+            }
+        }
     }
 
-    pub fn update_stats(&mut self, r: usize) {
-        let stats = ValidatorLedgerStats {
-            ledger_best: LedgerStats {
-                length: self.bvtree.get_block_height(&self.tip_best),
-                age: self.bvtree.get_block(self.tip_best.clone()).unwrap().slot(),
-            },
-            ledger_fast: LedgerStats {
-                length: self.bvtree.get_block_height(&self.tip_fast),
-                age: self.bvtree.get_block(self.tip_fast.clone()).unwrap().slot(),
-            },
-            ledger_slow: LedgerStats {
-                length: self.bvtree.get_block_height(&self.tip_slow),
-                age: self.bvtree.get_block(self.tip_slow.clone()).unwrap().slot(),
-            },
-        };
-        self.stats.insert(r, stats);
+    // answer what peers asked for, from the block tree as of the end of the last round
+    fn serve_sync_requests(&mut self, r: usize, transport: &mut dyn network::Transport) {
+        for (peer, req) in transport.collect_sync_requests() {
+            let msgs = sync::serve(&self.bvtree, &self.tip_best, &req);
+            log::debug!(
+                "r={} id={} SYNC serving {:?} to {}: {} msgs",
+                r,
+                self.id,
+                req,
+                peer,
+                msgs.len()
+            );
+            transport.sync_respond(peer, msgs);
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn dump_dotfile(&self) -> String {
-        self.bvtree.dump_dotfile()
+    // after waking up, ask for the canonical chain since the own best tip and the current votes
+    pub fn request_sync(&mut self, r: usize, transport: &mut dyn network::Transport) {
+        let t = self.slot_schedule.slot(r);
+        let eta = self.vote_expiry.vote_expiry;
+        let from = self.bvtree.get_block(self.tip_best.clone()).unwrap().slot() + 1;
+        log::info!("r={} id={} SYNC slots {}..={}", r, self.id, from, t);
+
+        transport.sync_request(&sync::SyncRequest::BlocksByRange { from, to: t });
+        transport.sync_request(&sync::SyncRequest::CurrentVotes {
+            from: t.saturating_sub(eta),
+        });
+        self.sync_requested.clear();
     }
-}
 
-impl HonestValidator for Validator {
-    fn step(
+    // blocks that messages in limbo refer to, but that are nowhere at hand
+    fn missing_blocks(&self, t: goldfish_type::Slot) -> HashSet<goldfish_type::Hash> {
+        let in_limbo: HashSet<goldfish_type::Hash> = self
+            .limbo
+            .iter()
+            .filter_map(|msg| match msg {
+                goldfish_message::Message::Piece(goldfish_message::Piece::Block(b)) => {
+                    Some(b.digest())
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut missing = HashSet::new();
+        for msg in self.limbo.iter().filter(|msg| msg.slot() <= t) {
+            let deps = match msg {
+                goldfish_message::Message::Piece(goldfish_message::Piece::Block(b)) => {
+                    vec![b.parent()]
+                }
+                goldfish_message::Message::Piece(goldfish_message::Piece::Vote(v)) => {
+                    vec![v.target()]
+                }
+                goldfish_message::Message::Proposal(p) => {
+                    let mut deps: Vec<goldfish_type::Hash> = p.tips().into_iter().collect();
+                    deps.push(p.parent());
+                    if p.is_compact() {
+                        deps.push(p.block_digest());
+                    }
+                    deps
+                }
+                goldfish_message::Message::FfgVote(_) => vec![],
+            };
+            missing.extend(deps.into_iter().filter(|h| {
+                !self.bvtree.contains_block(h)
+                    && !self.buffer_blocks.contains_key(h)
+                    && !in_limbo.contains(h)
+            }));
+        }
+        missing
+    }
+
+    // while rejoining, take in what arrives (sync responses included) without taking part in
+    // the protocol, and ask for blocks that are still missing; returns whether caught up
+    pub fn catch_up(
         &mut self,
         lotteries: &goldfish_type::Lotteries,
         r: usize,
-        inboxes: &mut Vec<network::SimulationInbox>,
-        my_inbox: usize,
-    ) {
-        let t = (r / 4) as goldfish_type::Slot;
-        let myid = self.id;
+        transport: &mut dyn network::Transport,
+    ) -> bool {
+        self.fill_limbo(r, transport);
+        self.process_limbo(lotteries, r, transport);
+
+        // blocks are merged right away, votes are left to the phases as usual
+        let mut no_votes = HashMap::new();
+        let buffer_votes = if self.protocol == Protocol::Goldfish {
+            &mut no_votes
+        } else {
+            &mut self.buffer_votes
+        };
+        let dropped = self.bvtree.merge(
+            lotteries,
+            &mut self.validation_cache,
+            &self.pki,
+            &mut self.buffer_blocks,
+            buffer_votes,
+            None,
+        );
+        Self::count_invalid(&mut self.msgs_invalid, dropped);
+
+        let missing = self.missing_blocks(self.slot_schedule.slot(r));
+        self.sync_requested.retain(|h, _| missing.contains(h));
+        let mut waiting = false;
+        let mut request = Vec::new();
+        for h in missing {
+            let (r_requested, attempts) = self.sync_requested.entry(h.clone()).or_insert((r, 0));
+            if *attempts > 0 && r < *r_requested + sync::SYNC_TIMEOUT {
+                waiting = true;
+            } else if *attempts < sync::SYNC_MAX_ATTEMPTS {
+                request.push(h);
+                *r_requested = r;
+                *attempts += 1;
+                waiting = true;
+            }
+            // otherwise no peer could provide it, so it is given up on
+        }
+        if !request.is_empty() {
+            log::info!(
+                "r={} id={} SYNC {} missing blocks",
+                r,
+                self.id,
+                request.len()
+            );
+            transport.sync_request(&sync::SyncRequest::BlocksByRoot(request));
+        }
 
-        // log::info!("r={} id={} STEP", r, myid);
+        !waiting
+    }
 
-        self.limbo.extend(inboxes[my_inbox].collect_inbox());
-        log::info!("r={} id={} LIMBO {}", r, myid, self.limbo.len());
+    fn fill_limbo(&mut self, r: usize, transport: &mut dyn network::Transport) {
+        let t = self.slot_schedule.slot(r);
+        let eta = self.vote_expiry.vote_expiry;
+
+        self.limbo.extend(transport.collect_inbox());
+        log::info!("r={} id={} LIMBO {}", r, self.id, self.limbo.len());
 
         // drop messages we have processed before
         self.limbo
-            .retain(|msg| !self.msgs_relayed.contains(&msg.digest()));
+            .retain(|msg| !self.msgs_relayed.contains_key(&msg.digest()));
 
         // drop votes that have expired and won't be needed anyway
         self.limbo.retain(|msg| {
             if let goldfish_message::Message::Piece(goldfish_message::Piece::Vote(_)) = msg {
-                !(t > 0 && msg.slot() < t - 1)
+                msg.slot().saturating_add(eta) >= t
             } else {
                 true
             }
         });
+    }
+
+    // validate what is in limbo and move it to the buffers (or the FFG votes), until
+    // nothing more becomes valid
+    fn process_limbo(
+        &mut self,
+        lotteries: &goldfish_type::Lotteries,
+        r: usize,
+        transport: &mut dyn network::Transport,
+    ) {
+        let t = self.slot_schedule.slot(r);
+        let myid = self.id;
 
         let mut bvset_validation_rw_cache = HashMap::new();
         let mut bvset_validation_cache = goldfish_type::UnionValidationCache::new(
@@ -183,6 +598,7 @@ impl HonestValidator for Validator {
                         goldfish_message::Message::Piece(goldfish_message::Piece::Block(_)) => 0,
                         goldfish_message::Message::Piece(goldfish_message::Piece::Vote(_)) => 1,
                         goldfish_message::Message::Proposal(_) => 2,
+                        goldfish_message::Message::FfgVote(_) => 3,
                     },
                     m.slot(),
                 )
@@ -198,6 +614,7 @@ impl HonestValidator for Validator {
 
                 let msg_is_valid = msg.is_valid(
                     lotteries,
+                    &self.ffg_config,
                     &mut goldfish_type::RoValidationCache::new(&bvset_validation_cache),
                     &self.pki,
                     &goldfish_blockvote::BufferAugmentedBvTree::new(
@@ -207,30 +624,92 @@ impl HonestValidator for Validator {
                     ),
                 );
 
+                // without DAS, blocks come with their payload
+                let msg_is_valid = match (&msg_is_valid, &msg) {
+                    (
+                        goldfish_type::ValidationResult::Valid,
+                        goldfish_message::Message::Piece(goldfish_message::Piece::Block(b)),
+                    ) if b.payload().is_none() && !self.das_sampler.config().das => {
+                        goldfish_type::ValidationResult::Invalid(
+                            goldfish_type::ValidationError::MissingPayload,
+                        )
+                    }
+                    (
+                        goldfish_type::ValidationResult::Valid,
+                        goldfish_message::Message::Proposal(p),
+                    ) => self.das_sampler.check_data(
+                        &p.block_digest(),
+                        &p.data_root(),
+                        p.payload(),
+                        r,
+                        transport,
+                    ),
+                    (
+                        goldfish_type::ValidationResult::Valid,
+                        goldfish_message::Message::Piece(goldfish_message::Piece::Block(b)),
+                    ) => self.das_sampler.check_data(
+                        &b.digest(),
+                        &b.data_root(),
+                        b.payload(),
+                        r,
+                        transport,
+                    ),
+                    _ => msg_is_valid,
+                };
+
                 match msg_is_valid {
                     goldfish_type::ValidationResult::Valid => {
                         log::trace!("r={} id={} VALID msg from limbo: {:?}", r, myid, msg);
                         match msg.clone() {
                             goldfish_message::Message::Proposal(p) => {
-                                self.buffer_proposals.push(p.clone());
+                                // compact proposals wait in limbo until their block is known
+                                match p.to_full(&goldfish_blockvote::BufferAugmentedBvTree::new(
+                                    &self.bvtree,
+                                    &self.buffer_blocks,
+                                    &self.buffer_votes,
+                                )) {
+                                    Some(p) => self.buffer_proposals.push(p),
+                                    None => {
+                                        log::debug!(
+                                            "r={} id={} UNKNOWN block of proposal from limbo: {:?}",
+                                            r,
+                                            myid,
+                                            msg
+                                        );
+                                        self.limbo.push_back(msg);
+                                        continue;
+                                    }
+                                }
                             }
                             goldfish_message::Message::Piece(x) => match x {
                                 goldfish_message::Piece::Vote(v) => {
                                     self.buffer_votes.insert(v.digest(), v.clone());
                                 }
                                 goldfish_message::Piece::Block(b) => {
-                                    self.buffer_blocks.insert(b.digest(), b.clone());
+                                    // a header does not replace its block
+                                    if b.payload().is_some()
+                                        || !self.buffer_blocks.contains_key(&b.digest())
+                                    {
+                                        self.buffer_blocks.insert(b.digest(), b.clone());
+                                    }
                                 }
                             },
+                            goldfish_message::Message::FfgVote(v) => {
+                                self.ffg.insert(&v);
+                                if let Err(e) = self.store.append(&store::Record::FfgVote(v)) {
+                                    log::error!("r={} id={} Could not store: {}", r, myid, e);
+                                }
+                            }
                         }
 
-                        // Self::broadcast(msg, inboxes); // TODO TODO TODO: disabled relaying for simulation experiments
-                        self.msgs_relayed.insert(msg.digest());
+                        // Self::broadcast(msg, transport); // TODO TODO TODO: disabled relaying for simulation experiments
+                        self.msgs_relayed.insert(msg.digest(), msg.slot());
 
                         done = false;
-                        assert!(
+                        debug_assert!(
                             msg.is_valid(
                                 lotteries,
+                                &self.ffg_config,
                                 &mut bvset_validation_cache,
                                 &self.pki,
                                 &goldfish_blockvote::BufferAugmentedBvTree::new(
@@ -241,8 +720,15 @@ impl HonestValidator for Validator {
                             ) == goldfish_type::ValidationResult::Valid
                         );
                     }
-                    goldfish_type::ValidationResult::Invalid => {
-                        log::warn!("r={} id={} INVALID msg from limbo: {:?}", r, myid, msg);
+                    goldfish_type::ValidationResult::Invalid(e) => {
+                        log::warn!(
+                            "r={} id={} INVALID ({:?}) msg from limbo: {:?}",
+                            r,
+                            myid,
+                            e,
+                            msg
+                        );
+                        Self::count_invalid(&mut self.msgs_invalid, vec![e]);
                     }
                     goldfish_type::ValidationResult::Unknown => {
                         log::debug!("r={} id={} UNKNOWN msg from limbo: {:?}", r, myid, msg);
@@ -252,171 +738,606 @@ impl HonestValidator for Validator {
                 }
             }
         }
+    }
 
-        match r % 4 {
-            0 => {
-                log::info!("r={} id={} PROPOSE", r, myid);
+    #[allow(dead_code)]
+    pub fn stats(&self) -> HashMap<usize, ValidatorLedgerStats> {
+        self.stats.clone()
+    }
 
-                let rho = lotteries.block.open(&self.sk_vrf, t);
-                if lotteries
-                    .block
-                    .is_winning(&self.pki.get(&self.id).unwrap().1, t, &rho)
-                {
-                    log::info!("r={} id={} Proposing ...", r, myid);
+    pub fn update_stats(&mut self, r: usize) {
+        // transactions only count once the payloads of their blocks are at hand
+        let tips = [
+            self.tip_best.clone(),
+            self.tip_fast.clone(),
+            self.tip_slow.clone(),
+            self.tip_final(),
+        ]
+        .map(|h| self.bvtree.payload_tip(&h));
+        for (i, (tracker, tip)) in self.ledger_trackers.iter_mut().zip(tips.iter()).enumerate() {
+            let added = tracker.advance(&self.bvtree, tip, r);
+            // once slow-confirmed, transactions need not be proposed again; those only in
+            // the best chain stay pending, as it may be reorged
+            if i == 2 {
+                self.mempool.remove(&added);
+            }
+        }
+        self.executor.advance(&self.bvtree, &tips[0]);
 
-                    let mut bvtree_new = self.bvtree.clone();
-                    bvtree_new.merge(
-                        lotteries,
-                        &mut goldfish_type::RoValidationCache::new(&self.validation_cache),
-                        &self.pki,
-                        &mut self.buffer_blocks.clone(),
-                        &mut self.buffer_votes.clone(),
-                        None,
-                    );
-                    bvtree_new.expire_votes_before((t as isize) - 1);
-                    let h_tip = bvtree_new.ghost_eph((t as isize) - 1, 0);
-                    let b_new = goldfish_blockvote::Block::create(
-                        &self.sk_sig,
-                        (self.id, t),
-                        rho,
-                        h_tip,
-                        format!("t={} id={}", t, self.id),
-                    );
-                    let p_new = goldfish_message::Proposal::create(
-                        &self.sk_sig,
-                        &bvtree_new,
-                        b_new.clone(),
-                    );
+        let stats = self.ledger_stats();
+        self.stats.insert(r, stats);
+    }
 
-                    // debug & pre-heat signature validation cache (for simulation)
-                    assert!(
-                        p_new.is_valid(
-                            lotteries,
-                            &mut goldfish_type::RoValidationCache::new(&self.validation_cache),
-                            &self.pki,
-                            &bvtree_new
-                        ) == goldfish_type::ValidationResult::Valid
-                    );
-                    Self::broadcast(&goldfish_message::Message::Proposal(p_new), inboxes);
-                }
+    pub fn submit_txs(&mut self, txs: &[Arc<transaction::Transaction>]) {
+        self.mempool.insert(txs);
+    }
+
+    pub fn ledger_stats(&self) -> ValidatorLedgerStats {
+        ValidatorLedgerStats {
+            ledger_best: LedgerStats {
+                length: self.bvtree.get_block_height(&self.tip_best),
+                age: self.bvtree.get_block(self.tip_best.clone()).unwrap().slot(),
+                txs: self.ledger_trackers[0].txs,
+                tx_latency_sum: self.ledger_trackers[0].tx_latency_sum,
+            },
+            ledger_fast: LedgerStats {
+                length: self.bvtree.get_block_height(&self.tip_fast),
+                age: self.bvtree.get_block(self.tip_fast.clone()).unwrap().slot(),
+                txs: self.ledger_trackers[1].txs,
+                tx_latency_sum: self.ledger_trackers[1].tx_latency_sum,
+            },
+            ledger_slow: LedgerStats {
+                length: self.bvtree.get_block_height(&self.tip_slow),
+                age: self.bvtree.get_block(self.tip_slow.clone()).unwrap().slot(),
+                txs: self.ledger_trackers[2].txs,
+                tx_latency_sum: self.ledger_trackers[2].tx_latency_sum,
+            },
+            ledger_final: LedgerStats {
+                length: self.bvtree.get_block_height(&self.tip_final()),
+                age: self.bvtree.get_block(self.tip_final()).unwrap().slot(),
+                txs: self.ledger_trackers[3].txs,
+                tx_latency_sum: self.ledger_trackers[3].tx_latency_sum,
+            },
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn dump_dotfile(&self) -> String {
+        self.bvtree.dump_dotfile()
+    }
+
+    pub fn id(&self) -> goldfish_type::Id {
+        self.id
+    }
+
+    pub fn tip_best(&self) -> goldfish_type::Hash {
+        self.tip_best.clone()
+    }
+
+    pub fn tip_fast(&self) -> goldfish_type::Hash {
+        self.tip_fast.clone()
+    }
+
+    pub fn tip_slow(&self) -> goldfish_type::Hash {
+        self.tip_slow.clone()
+    }
+
+    // block of the latest finalized FFG checkpoint (genesis without FFG)
+    pub fn tip_final(&self) -> goldfish_type::Hash {
+        self.ffg.finalized().h.clone()
+    }
+
+    pub fn ffg(&self) -> &ffg::Ffg {
+        &self.ffg
+    }
+
+    // head of the available chain, from the votes of slot t (Goldfish), the
+    // latest votes of all validators (LMD-GHOST), or the longest chain
+    fn fork_choice(&self, bvtree: &goldfish_blockvote::BvTree, t: isize) -> goldfish_type::Hash {
+        match self.protocol {
+            Protocol::Goldfish => bvtree.ghost_eph(
+                &self.tip_final(),
+                t,
+                self.vote_expiry.vote_expiry,
+                self.vote_expiry.vote_expiry_latest,
+                0,
+            ),
+            Protocol::LmdGhost => bvtree.ghost_lmd(&self.tip_final(), isize::MIN, 0),
+            Protocol::LongestChain => bvtree.longest_chain(&self.tip_final()),
+        }
+    }
+
+    // the available ledger must extend the finalized checkpoint
+    fn extend_final(&self, h: goldfish_type::Hash) -> goldfish_type::Hash {
+        let h_final = self.tip_final();
+        if self.bvtree.extends(&h, &h_final) {
+            h
+        } else {
+            h_final
+        }
+    }
+
+    pub fn bvtree(&self) -> &goldfish_blockvote::BvTree {
+        &self.bvtree
+    }
+
+    // state after executing the ledger up to tip_best (as of the last step)
+    pub fn executor(&self) -> &execution::Executor<execution::AccountState> {
+        &self.executor
+    }
+
+    pub fn state_root(&self) -> goldfish_type::Hash {
+        self.executor.state_root()
+    }
+
+    pub fn buffer_sizes(&self) -> ValidatorBufferSizes {
+        ValidatorBufferSizes {
+            limbo: self.limbo.len(),
+            buffer_blocks: self.buffer_blocks.len(),
+            buffer_votes: self.buffer_votes.len(),
+            buffer_proposals: self.buffer_proposals.len(),
+            msgs_relayed: self.msgs_relayed.len(),
+            mempool: self.mempool.len(),
+            tree_blocks: self.bvtree.block_count(),
+            tree_votes: self.bvtree.vote_count(),
+        }
+    }
+
+    // collapses the block tree up to depth blocks below tip_slow, and forgets
+    // everything that only concerns the discarded blocks
+    fn prune(&mut self, depth: usize) {
+        let mut height = self
+            .bvtree
+            .get_block_height(&self.tip_slow)
+            .saturating_sub(depth);
+        // the finalized checkpoint (and those justified after it) are needed for FFG votes
+        if self.ffg_config.ffg {
+            height = height.min(self.bvtree.get_block_height(&self.tip_final()));
+        }
+        if height <= self.bvtree.get_block_height(&self.bvtree.anchor()) {
+            return;
+        }
+        let h_anchor = self.bvtree.ancestor_at_height(&self.tip_slow, height);
+        // a fast confirmed tip on a stale branch holds off pruning until it is replaced
+        if !self.bvtree.extends(&self.tip_fast, &h_anchor)
+            || !self.bvtree.extends(&self.tip_best, &h_anchor)
+        {
+            return;
+        }
+        // so do ledgers lagging behind for payloads still being fetched, as they are
+        // walked back through the tree (the final ledger stays at genesis without FFG)
+        let ledgers = if self.ffg_config.ffg { 4 } else { 3 };
+        if !self.ledger_trackers[..ledgers]
+            .iter()
+            .map(|tracker| &tracker.tip)
+            .chain([self.executor.tip()])
+            .all(|h| self.bvtree.extends(h, &h_anchor))
+        {
+            return;
+        }
+        log::debug!("id={} Pruning below {:?}", self.id, h_anchor);
+        self.bvtree.prune(&h_anchor);
+        let t_anchor = self.bvtree.get_block(h_anchor).unwrap().slot();
+        self.executor.prune(&self.bvtree);
+        self.ffg
+            .prune(&self.bvtree, self.ffg_config.epoch(t_anchor));
+
+        // buffered blocks, votes and proposals building on discarded blocks cannot be merged
+        loop {
+            let orphans: Vec<goldfish_type::Hash> = self
+                .buffer_blocks
+                .iter()
+                .filter(|(_, b)| {
+                    !self.bvtree.contains_block(&b.parent())
+                        && !self.buffer_blocks.contains_key(&b.parent())
+                })
+                .map(|(h, _)| h.clone())
+                .collect();
+            if orphans.is_empty() {
+                break;
             }
+            for h in orphans {
+                self.buffer_blocks.remove(&h);
+            }
+        }
+        let bvtree = &self.bvtree;
+        let buffer_blocks = &self.buffer_blocks;
+        let is_known =
+            |h: &goldfish_type::Hash| bvtree.contains_block(h) || buffer_blocks.contains_key(h);
+        self.buffer_votes.retain(|_, v| is_known(&v.target()));
+        self.buffer_proposals
+            .retain(|p| p.tips().iter().all(is_known) && is_known(&p.b().parent()));
+
+        self.msgs_relayed.retain(|_, t| *t >= t_anchor);
+        self.validation_cache.retain(|h| {
+            bvtree.contains_block(h)
+                || bvtree.contains_vote(h)
+                || buffer_blocks.contains_key(h)
+                || self.buffer_votes.contains_key(h)
+                || self.msgs_relayed.contains_key(h)
+        });
+    }
 
-            1 => {
-                log::info!("r={} id={} VOTE", r, myid);
+    // appends what was merged in round r, and the tips it led to, to the store
+    fn persist(&mut self, r: usize) -> io::Result<()> {
+        for x in self.bvtree.take_journal() {
+            let record = match x {
+                goldfish_message::Piece::Block(b) => store::Record::Block(b),
+                goldfish_message::Piece::Vote(v) => store::Record::Vote(v),
+            };
+            self.store.append(&record)?;
+        }
+        self.store.append(&store::Record::Tips(store::Tips {
+            r,
+            fast: self.tip_fast.clone(),
+            slow: self.tip_slow.clone(),
+            best: self.tip_best.clone(),
+        }))?;
+        self.store.sync()
+    }
 
-                if let Some(p) = self
-                    .buffer_proposals
-                    .iter()
-                    .filter(|p| p.slot() == t)
-                    .min_by_key(|p| p.prio())
-                {
-                    log::info!("r={} id={} Merging: {:?}", r, myid, p);
+    // drops everything that is only held in memory, as a crashed node would, and
+    // rebuilds the block tree, FFG and tips from the store; returns the round of
+    // the restored tips (the ledger trackers and executor stay in sync with them)
+    pub fn restart(&mut self) -> io::Result<Option<usize>> {
+        let records = self.store.load()?;
+
+        self.bvtree = goldfish_blockvote::BvTree::default();
+        self.limbo.clear();
+        self.msgs_relayed.clear();
+        self.buffer_blocks.clear();
+        self.buffer_votes.clear();
+        self.buffer_proposals.clear();
+        self.sync_requested.clear();
+        self.ffg = ffg::Ffg::new(self.pki.len());
+        self.validation_cache = goldfish_type::CountingValidationCache::default();
+        self.mempool.clear();
+        self.tip_fast = goldfish_blockvote::Block::default().digest();
+        self.tip_slow = goldfish_blockvote::Block::default().digest();
+        self.tip_best = goldfish_blockvote::Block::default().digest();
+
+        let mut pieces = Vec::new();
+        let mut tips = None;
+        for record in records {
+            match record {
+                store::Record::Block(b) => pieces.push(goldfish_message::Piece::Block(b)),
+                store::Record::Vote(v) => pieces.push(goldfish_message::Piece::Vote(v)),
+                store::Record::FfgVote(v) => self.ffg.insert(&v),
+                store::Record::Tips(tips_) => tips = Some(tips_),
+            }
+        }
+        self.bvtree.restore(pieces);
+        self.ffg.update(&self.bvtree);
 
-                    self.bvtree.merge(
-                        lotteries,
-                        &mut self.validation_cache,
-                        &self.pki,
-                        &mut self.buffer_blocks,
-                        &mut self.buffer_votes,
-                        Some(p),
-                    );
+        let tips = match tips {
+            Some(tips) => tips,
+            None => return Ok(None),
+        };
+        for h in [&tips.fast, &tips.slow, &tips.best] {
+            if !self.bvtree.contains_block(h) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("stored tip {:?} is not in the stored block tree", h),
+                ));
+            }
+        }
+        log::warn!(
+            "id={} Restored {} blocks and {} votes up to r={}",
+            self.id,
+            self.bvtree.block_count(),
+            self.bvtree.vote_count(),
+            tips.r
+        );
+        self.tip_fast = tips.fast;
+        self.tip_slow = tips.slow;
+        self.tip_best = tips.best;
+        Ok(Some(tips.r))
+    }
 
-                    Self::broadcast(
-                        &goldfish_message::Message::Piece(goldfish_message::Piece::Block(p.b())),
-                        inboxes,
-                    );
+    // (not a method, as merging can borrow from the proposal buffer)
+    fn count_invalid(
+        msgs_invalid: &mut HashMap<goldfish_type::ValidationError, usize>,
+        dropped: Vec<goldfish_type::ValidationError>,
+    ) {
+        for e in dropped {
+            *msgs_invalid.entry(e).or_default() += 1;
+        }
+    }
+
+    pub fn msgs_invalid(&self) -> &HashMap<goldfish_type::ValidationError, usize> {
+        &self.msgs_invalid
+    }
+
+    pub fn validation_cache_hits_misses(&self) -> (usize, usize) {
+        (self.validation_cache.hits(), self.validation_cache.misses())
+    }
+
+    pub fn phase_timings(&self) -> [PhaseTiming; slot::Phase::ALL.len()] {
+        self.phase_timings
+    }
+}
+
+impl HonestValidator for Validator {
+    fn step(
+        &mut self,
+        lotteries: &goldfish_type::Lotteries,
+        r: usize,
+        transport: &mut dyn network::Transport,
+    ) {
+        let t = self.slot_schedule.slot(r);
+        let eta = self.vote_expiry.vote_expiry;
+        let myid = self.id;
+        let step_start = Instant::now();
+
+        // log::info!("r={} id={} STEP", r, myid);
+
+        self.serve_sync_requests(r, transport);
+        self.fill_limbo(r, transport);
+
+        let phases = self.slot_schedule.phases(r).to_vec();
+
+        if phases.contains(&slot::Phase::Vote) {
+            self.fetch_best_proposal_block(t, transport);
+        }
+        self.fetch_payloads(transport);
+
+        self.process_limbo(lotteries, r, transport);
+
+        // without vote buffering, everything valid is merged right away
+        if self.protocol != Protocol::Goldfish {
+            let dropped = self.bvtree.merge(
+                lotteries,
+                &mut self.validation_cache,
+                &self.pki,
+                &mut self.buffer_blocks,
+                &mut self.buffer_votes,
+                None,
+            );
+            Self::count_invalid(&mut self.msgs_invalid, dropped);
+        }
+
+        for phase in phases.iter() {
+            match phase {
+                slot::Phase::Propose => {
+                    log::info!("r={} id={} PROPOSE", r, myid);
+
+                    let rho = lotteries.block.open(&self.sk_vrf, t);
+                    if lotteries
+                        .block
+                        .is_winning(&self.pki.get(&self.id).unwrap().1, t, &rho)
+                    {
+                        log::info!("r={} id={} Proposing ...", r, myid);
+
+                        let mut bvtree_new = self.bvtree.clone();
+                        bvtree_new.merge(
+                            lotteries,
+                            &mut goldfish_type::RoValidationCache::new(&self.validation_cache),
+                            &self.pki,
+                            &mut self.buffer_blocks.clone(),
+                            &mut self.buffer_votes.clone(),
+                            None,
+                        );
+                        bvtree_new.expire_votes_before((t as isize) - eta as isize, eta);
+                        let h_tip = self.fork_choice(&bvtree_new, (t as isize) - 1);
+                        let (txs, padding) =
+                            self.mempool
+                                .select(&bvtree_new, &h_tip, &self.slot_schedule);
+                        let payload = goldfish_blockvote::Payload::new(
+                            format!("t={} id={}", t, self.id),
+                            txs,
+                            padding,
+                        );
+                        let (data_root, samples) = if self.das_sampler.config().das {
+                            let (data_root, samples) =
+                                das::encode(&payload, self.das_sampler.config());
+                            (data_root, Some(samples))
+                        } else {
+                            (goldfish_type::Hash::default(), None)
+                        };
+                        let b_new = goldfish_blockvote::Block::create(
+                            &self.sk_sig,
+                            (self.id, t),
+                            rho,
+                            h_tip.clone(),
+                            payload,
+                            data_root,
+                        );
+                        if let Some(samples) = samples {
+                            transport.publish_chunks(&b_new, samples);
+                        }
+                        let p_new = goldfish_message::Proposal::create(
+                            &self.sk_sig,
+                            &bvtree_new,
+                            b_new.clone(),
+                        );
+
+                        // debug & pre-heat signature validation cache (for simulation)
+                        assert!(
+                            p_new.is_valid(
+                                lotteries,
+                                &mut goldfish_type::RoValidationCache::new(&self.validation_cache),
+                                &self.pki,
+                                &bvtree_new
+                            ) == goldfish_type::ValidationResult::Valid
+                        );
+                        if self.compact_proposals {
+                            transport.publish_block(&b_new);
+                            Self::broadcast(
+                                &goldfish_message::Message::Proposal(p_new.to_compact()),
+                                transport,
+                            );
+                        } else {
+                            Self::broadcast(&goldfish_message::Message::Proposal(p_new), transport);
+                        }
+                    }
                 }
 
-                let rho = lotteries.vote.open(&self.sk_vrf, t);
-                if lotteries
-                    .vote
-                    .is_winning(&self.pki.get(&self.id).unwrap().1, t, &rho)
-                {
-                    log::info!("r={} id={} Voting ...", r, myid);
+                slot::Phase::Vote => {
+                    log::info!("r={} id={} VOTE", r, myid);
 
-                    self.bvtree.expire_votes_before((t as isize) - 1);
-                    let h_tip = self.bvtree.ghost_eph((t as isize) - 1, 0);
-                    let v_new =
-                        goldfish_blockvote::Vote::create(&self.sk_sig, (self.id, t), rho, h_tip);
-                    let x_new = goldfish_message::Piece::Vote(v_new);
+                    if let Some(p) = self
+                        .buffer_proposals
+                        .iter()
+                        .filter(|p| p.slot() == t)
+                        .min_by_key(|p| p.prio())
+                    {
+                        log::info!("r={} id={} Merging: {:?}", r, myid, p);
 
-                    // debug & pre-heat signature validation cache (for simulation)
-                    assert!(
-                        x_new.is_valid(
+                        let dropped = self.bvtree.merge(
                             lotteries,
                             &mut self.validation_cache,
                             &self.pki,
-                            &self.bvtree
-                        ) == goldfish_type::ValidationResult::Valid
-                    );
-                    Self::broadcast(&goldfish_message::Message::Piece(x_new), inboxes);
-                }
-            }
+                            &mut self.buffer_blocks,
+                            &mut self.buffer_votes,
+                            Some(p),
+                        );
+                        Self::count_invalid(&mut self.msgs_invalid, dropped);
 
-            2 => {
-                log::info!("r={} id={} FAST-CONFIRM", r, myid);
+                        Self::broadcast(
+                            &goldfish_message::Message::Piece(goldfish_message::Piece::Block(
+                                p.b(),
+                            )),
+                            transport,
+                        );
+                    }
 
-                // clean up unneeded votes/proposals -> performance (esp. after sleeping)
-                self.buffer_proposals.retain(|p| p.slot() >= t);
-                self.buffer_votes.retain(|_, v| v.slot() >= t);
+                    let rho = lotteries.vote.open(&self.sk_vrf, t);
+                    if self.protocol != Protocol::LongestChain
+                        && lotteries
+                            .vote
+                            .is_winning(&self.pki.get(&self.id).unwrap().1, t, &rho)
+                    {
+                        log::info!("r={} id={} Voting ...", r, myid);
+
+                        self.bvtree
+                            .expire_votes_before((t as isize) - eta as isize, eta);
+                        let h_tip = self.fork_choice(&self.bvtree, (t as isize) - 1);
+                        let v_new = goldfish_blockvote::Vote::create(
+                            &self.sk_sig,
+                            (self.id, t),
+                            rho,
+                            h_tip,
+                        );
+                        let x_new = goldfish_message::Piece::Vote(v_new);
 
-                log::debug!("r={} id={} FAST-CONFIRM-Merge", r, myid);
+                        // debug & pre-heat signature validation cache (for simulation)
+                        assert!(
+                            x_new.is_valid(
+                                lotteries,
+                                &mut self.validation_cache,
+                                &self.pki,
+                                &self.bvtree
+                            ) == goldfish_type::ValidationResult::Valid
+                        );
+                        Self::broadcast(&goldfish_message::Message::Piece(x_new), transport);
+                    }
 
-                self.bvtree.merge(
-                    lotteries,
-                    &mut self.validation_cache,
-                    &self.pki,
-                    &mut self.buffer_blocks,
-                    &mut self.buffer_votes,
-                    None,
-                );
-                self.bvtree.expire_votes_before(t as isize);
-                let h_tip = self.bvtree.ghost_eph(
-                    t as isize,
-                    ((self.pki.len() as f64)
-                        * (0.75 + 0.5 * self.confirm_fast_eps)
-                        * lotteries.vote.success_probability())
-                    .ceil() as usize,
-                );
+                    // FFG votes at the start of every epoch, for the confirmed ledger
+                    if self.ffg_config.ffg && t > 0 && t % self.ffg_config.ffg_epoch_slots == 0 {
+                        let source = self.ffg.latest_justified().clone();
+                        let h_target = if self.bvtree.extends(&self.tip_best, &source.h) {
+                            self.tip_best.clone()
+                        } else {
+                            source.h.clone()
+                        };
+                        let target = ffg::Checkpoint {
+                            h: h_target,
+                            epoch: self.ffg_config.epoch(t),
+                        };
+                        log::info!(
+                            "r={} id={} FFG voting {:?} -> {:?}",
+                            r,
+                            myid,
+                            source,
+                            target
+                        );
+                        let v_new =
+                            ffg::FfgVote::create(&self.sk_sig, (self.id, t), source, target);
+                        Self::broadcast(&goldfish_message::Message::FfgVote(v_new), transport);
+                    }
+                }
 
-                if self.bvtree.get_block_height(&h_tip)
-                    > self.bvtree.get_block_height(&self.tip_fast)
-                {
-                    self.tip_fast = h_tip;
+                slot::Phase::FastConfirm => {
+                    log::info!("r={} id={} FAST-CONFIRM", r, myid);
+
+                    // clean up unneeded votes/proposals -> performance (esp. after sleeping)
+                    self.buffer_proposals.retain(|p| p.slot() >= t);
+                    self.buffer_votes.retain(|_, v| v.slot() + eta > t);
+
+                    log::debug!("r={} id={} FAST-CONFIRM-Merge", r, myid);
+
+                    let dropped = self.bvtree.merge(
+                        lotteries,
+                        &mut self.validation_cache,
+                        &self.pki,
+                        &mut self.buffer_blocks,
+                        &mut self.buffer_votes,
+                        None,
+                    );
+                    Self::count_invalid(&mut self.msgs_invalid, dropped);
+                    self.bvtree
+                        .expire_votes_before((t as isize) + 1 - eta as isize, eta);
+                    let quorum = self.confirm_fast_rule.quorum(
+                        self.pki.len(),
+                        lotteries.vote.success_probability(),
+                        self.bvtree.get_votes_in_slot(t).len(),
+                    );
+                    let h_tip = match self.protocol {
+                        Protocol::Goldfish => {
+                            self.bvtree
+                                .ghost_eph(&self.tip_final(), t as isize, 1, false, quorum)
+                        }
+                        // quorum of latest votes cast in this slot
+                        Protocol::LmdGhost => {
+                            self.bvtree.ghost_lmd(&self.tip_final(), t as isize, quorum)
+                        }
+                        // without votes, there is no fast confirmation
+                        Protocol::LongestChain => self.tip_fast.clone(),
+                    };
+
+                    if self.bvtree.get_block_height(&h_tip)
+                        > self.bvtree.get_block_height(&self.tip_fast)
+                    {
+                        self.tip_fast = h_tip;
+                    }
                 }
-            }
 
-            3 => {
-                log::info!("r={} id={} SLOW-CONFIRM", r, myid);
+                slot::Phase::SlowConfirm => {
+                    log::info!("r={} id={} SLOW-CONFIRM", r, myid);
 
-                // clean up unneeded votes/proposals -> performance (esp. after sleeping)
-                self.buffer_proposals.retain(|p| p.slot() >= t);
-                self.buffer_votes.retain(|_, v| v.slot() >= t);
+                    // clean up unneeded votes/proposals -> performance (esp. after sleeping)
+                    self.buffer_proposals.retain(|p| p.slot() >= t);
+                    self.buffer_votes.retain(|_, v| v.slot() + eta > t);
 
-                log::debug!("r={} id={} SLOW-CONFIRM-Merge", r, myid);
+                    log::debug!("r={} id={} SLOW-CONFIRM-Merge", r, myid);
 
-                self.bvtree.merge(
-                    lotteries,
-                    &mut self.validation_cache,
-                    &self.pki,
-                    &mut self.buffer_blocks,
-                    &mut self.buffer_votes,
-                    None,
-                );
-                self.bvtree.expire_votes_before(t as isize);
-                let h_tip = self.bvtree.ghost_eph(t as isize, 0);
-                let h_tip = self.bvtree.truncate_back_to_slot(
-                    &h_tip,
-                    (t as isize) - (self.confirm_slow_kappa as isize),
-                );
-                self.tip_slow = h_tip;
+                    let dropped = self.bvtree.merge(
+                        lotteries,
+                        &mut self.validation_cache,
+                        &self.pki,
+                        &mut self.buffer_blocks,
+                        &mut self.buffer_votes,
+                        None,
+                    );
+                    Self::count_invalid(&mut self.msgs_invalid, dropped);
+                    self.bvtree
+                        .expire_votes_before((t as isize) + 1 - eta as isize, eta);
+                    let h_tip = self.fork_choice(&self.bvtree, t as isize);
+                    let h_tip = self.bvtree.truncate_back_to_slot(
+                        &h_tip,
+                        (t as isize) - (self.confirm_slow_kappa as isize),
+                    );
+                    self.tip_slow = h_tip;
+                }
             }
-
-            _ => unreachable!(),
         }
 
         log::info!("r={} id={} CLEANUP", r, myid);
 
+        self.ffg.update(&self.bvtree);
+        self.tip_fast = self.extend_final(self.tip_fast.clone());
+        self.tip_slow = self.extend_final(self.tip_slow.clone());
+
         if self.bvtree.get_block_height(&self.tip_fast)
             > self.bvtree.get_block_height(&self.tip_slow)
         {
@@ -426,12 +1347,27 @@ impl HonestValidator for Validator {
         }
 
         self.buffer_proposals.retain(|p| p.slot() >= t);
-        self.buffer_votes.retain(|_, v| v.slot() >= t - 1);
+        self.buffer_votes.retain(|_, v| v.slot() + eta >= t);
         self.buffer_blocks.retain(|_, v| v.slot() >= t - 1);
         self.limbo
             .retain(|m| (m.slot() as isize) >= (t as isize) - (self.confirm_slow_kappa as isize));
 
         self.update_stats(r);
+
+        if let Err(e) = self.persist(r) {
+            log::error!("r={} id={} Could not store: {}", r, myid, e);
+        }
+
+        if let Some(depth) = self.prune_config.prune_depth {
+            self.prune(depth as usize);
+        }
+
+        // a round with several phases is accounted to the first one
+        if let Some(phase) = phases.first() {
+            let timing = &mut self.phase_timings[*phase as usize];
+            timing.count += 1;
+            timing.total += step_start.elapsed();
+        }
     }
 }
 
@@ -441,6 +1377,43 @@ pub enum DaValidatorSleepStatus {
     Asleep,
     Dreamy,
     Awake,
+    Crashed,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SleepSemantics {
+    /// Messages wait in the inbox until the validator wakes up
+    KeepInbox,
+    /// Messages that arrive while the validator is asleep are lost
+    DropWhileAsleep,
+    /// Only the latest messages that arrive while the validator is asleep are kept
+    BoundedBuffer,
+}
+
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct SleepConfig {
+    /// What happens to messages that arrive while a validator is asleep
+    #[arg(long, value_enum, default_value_t = SleepSemantics::KeepInbox)]
+    pub sleep_semantics: SleepSemantics,
+
+    /// Messages kept for an asleep validator (with --sleep-semantics bounded-buffer)
+    #[arg(long, default_value_t = 1024)]
+    pub sleep_buffer: usize,
+}
+
+impl SleepConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        vec![
+            "--sleep-semantics".to_string(),
+            self.sleep_semantics
+                .to_possible_value()
+                .unwrap()
+                .get_name()
+                .to_string(),
+            "--sleep-buffer".to_string(),
+            self.sleep_buffer.to_string(),
+        ]
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -448,28 +1421,56 @@ pub enum DaValidatorSleepStatus {
 pub enum DaScheduleStatus {
     Asleep,
     Awake,
+    // down, losing everything not in its store and the messages sent meanwhile
+    Crashed,
 }
 
 #[derive(Debug)]
 pub struct DaSimulationValidator {
     validator: Validator,
     da_schedule: Vec<DaScheduleStatus>,
+    sleep_config: SleepConfig,
+    sync_config: sync::SyncConfig,
     sleep_status: DaValidatorSleepStatus,
+    // latest messages that arrived while asleep, with bounded-buffer semantics
+    sleep_buffer: VecDeque<goldfish_message::Message>,
+    msgs_missed: usize,
+    r_woken: usize,
     r_end_of_joining: usize,
+    // rounds from waking up until taking part again, for every time the validator woke up
+    rejoin_rounds: Vec<usize>,
     stats: HashMap<usize, ValidatorDaStats>,
 }
 
 impl DaSimulationValidator {
-    pub fn new(validator: Validator, da_schedule: Vec<DaScheduleStatus>) -> Self {
+    pub fn new(
+        validator: Validator,
+        da_schedule: Vec<DaScheduleStatus>,
+        sleep_config: SleepConfig,
+        sync_config: sync::SyncConfig,
+    ) -> Self {
         Self {
             validator,
             da_schedule,
+            sleep_config,
+            sync_config,
             sleep_status: DaValidatorSleepStatus::Awake,
+            sleep_buffer: VecDeque::new(),
+            msgs_missed: 0,
+            r_woken: 0,
             r_end_of_joining: 0,
+            rejoin_rounds: Vec::new(),
             stats: HashMap::new(),
         }
     }
 
+    pub fn set_schedule_status(&mut self, r: usize, status: DaScheduleStatus) {
+        if self.da_schedule.len() <= r {
+            self.da_schedule.resize(r + 1, DaScheduleStatus::Awake);
+        }
+        self.da_schedule[r] = status;
+    }
+
     #[allow(dead_code)]
     pub fn stats(&self) -> HashMap<usize, (ValidatorDaStats, ValidatorLedgerStats)> {
         let keys: Vec<usize> = self.stats.keys().cloned().collect();
@@ -482,6 +1483,30 @@ impl DaSimulationValidator {
     pub fn dump_dotfile(&self) -> String {
         self.validator.dump_dotfile()
     }
+
+    pub fn validator(&self) -> &Validator {
+        &self.validator
+    }
+
+    pub fn submit_txs(&mut self, txs: &[Arc<transaction::Transaction>]) {
+        self.validator.submit_txs(txs);
+    }
+
+    pub fn sleep_status(&self) -> DaValidatorSleepStatus {
+        self.sleep_status
+    }
+
+    pub fn rejoin_rounds(&self) -> &[usize] {
+        &self.rejoin_rounds
+    }
+
+    pub fn msgs_missed(&self) -> usize {
+        self.msgs_missed
+    }
+
+    pub fn restart(&mut self) -> io::Result<Option<usize>> {
+        self.validator.restart()
+    }
 }
 
 impl HonestValidator for DaSimulationValidator {
@@ -489,33 +1514,79 @@ impl HonestValidator for DaSimulationValidator {
         &mut self,
         lotteries: &goldfish_type::Lotteries,
         r: usize,
-        inboxes: &mut Vec<network::SimulationInbox>,
-        my_inbox: usize,
+        transport: &mut dyn network::Transport,
     ) {
         let myid = self.validator.id;
+        let mut msgs_missed = 0;
 
         // https://stackoverflow.com/a/49908888
         (|| match self.da_schedule[r as usize] {
             DaScheduleStatus::Asleep => {
                 log::info!("r={} id={} ASLEEP", r, myid);
                 self.sleep_status = DaValidatorSleepStatus::Asleep;
+                match self.sleep_config.sleep_semantics {
+                    SleepSemantics::KeepInbox => {}
+                    SleepSemantics::DropWhileAsleep => {
+                        msgs_missed += transport.collect_inbox().len();
+                    }
+                    SleepSemantics::BoundedBuffer => {
+                        self.sleep_buffer.extend(transport.collect_inbox());
+                        while self.sleep_buffer.len() > self.sleep_config.sleep_buffer {
+                            self.sleep_buffer.pop_front();
+                            msgs_missed += 1;
+                        }
+                    }
+                }
+                // asleep validators do not answer sync requests
+                transport.collect_sync_requests();
+                self.validator.update_stats(r);
+                return;
+            }
+
+            DaScheduleStatus::Crashed => {
+                log::info!("r={} id={} CRASHED", r, myid);
+                if self.sleep_status != DaValidatorSleepStatus::Crashed {
+                    self.sleep_status = DaValidatorSleepStatus::Crashed;
+                    msgs_missed += self.sleep_buffer.len();
+                    self.sleep_buffer.clear();
+                    // nothing is stored while down, so the node can recover right away
+                    self.validator
+                        .restart()
+                        .expect("could not restart validator from its store");
+                }
+                // messages sent while down are lost
+                msgs_missed += transport.collect_inbox().len();
+                transport.collect_sync_requests();
                 self.validator.update_stats(r);
                 return;
             }
 
             DaScheduleStatus::Awake => {
-                if self.sleep_status == DaValidatorSleepStatus::Asleep {
+                if self.sleep_status == DaValidatorSleepStatus::Asleep
+                    || self.sleep_status == DaValidatorSleepStatus::Crashed
+                {
                     self.sleep_status = DaValidatorSleepStatus::Dreamy;
-                    let t = (r / 4) as goldfish_type::Slot;
-                    self.r_end_of_joining = t as usize * 4 + 3;
+                    self.r_woken = r;
+                    self.validator.limbo.extend(self.sleep_buffer.drain(..));
+                    let schedule = &self.validator.slot_schedule;
+                    self.r_end_of_joining = schedule.last_round(schedule.slot(r));
+                    if self.sync_config.sync {
+                        self.validator.request_sync(r, transport);
+                    }
                 }
 
                 if self.sleep_status == DaValidatorSleepStatus::Dreamy {
-                    if r == self.r_end_of_joining {
+                    // with sync, also wait for the responses and for missing blocks
+                    let caught_up = !self.sync_config.sync
+                        || (self.validator.catch_up(lotteries, r, transport)
+                            && r >= self.r_woken + sync::SYNC_TIMEOUT);
+                    if r >= self.r_end_of_joining && caught_up {
                         self.sleep_status = DaValidatorSleepStatus::Awake;
+                        self.rejoin_rounds.push(r - self.r_woken);
                     } else {
                         log::info!("r={} id={} DREAMY", r, myid);
                         // TODO TODO TODO: relaying (disabled for simulation experiments)
+                        transport.collect_sync_requests();
                         self.validator.update_stats(r);
                         return;
                     }
@@ -524,12 +1595,14 @@ impl HonestValidator for DaSimulationValidator {
                 assert!(self.sleep_status == DaValidatorSleepStatus::Awake);
                 log::info!("r={} id={} AWAKE", r, myid);
 
-                self.validator.step(lotteries, r, inboxes, my_inbox);
+                self.validator.step(lotteries, r, transport);
             }
         })();
 
+        self.msgs_missed += msgs_missed;
         let stats = ValidatorDaStats {
             status: self.sleep_status,
+            msgs_missed,
         };
         self.stats.insert(r, stats);
     }
@@ -0,0 +1,124 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+// bounds on the request line and headers, so that a client cannot make us buffer without end
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+// a stalled client does not hold up the (single-threaded) server for longer than this
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    pub fn error(status: &'static str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: status.as_bytes().to_vec(),
+        }
+    }
+}
+
+// a line of the request head, or none if it is longer than MAX_LINE_LENGTH
+fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let n = reader
+        .by_ref()
+        .take(MAX_LINE_LENGTH as u64)
+        .read_line(&mut line)?;
+    if n == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+// minimal HTTP/1.1: one request per connection, body by Content-Length only
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Result<Request, Response>> {
+    let too_large = || Ok(Err(Response::error("431 Request Header Fields Too Large")));
+    let request_line = match read_line(reader)? {
+        Some(line) => line,
+        None => return too_large(),
+    };
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Err(Response::error("400 Bad Request"))),
+    };
+
+    let mut content_length = 0;
+    for i in 0..=MAX_HEADERS {
+        let line = match read_line(reader)? {
+            Some(line) => line,
+            None => return too_large(),
+        };
+        if line.is_empty() || line == "\r\n" || line == "\n" {
+            break;
+        }
+        if i == MAX_HEADERS {
+            return too_large();
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.trim().eq_ignore_ascii_case("content-length") {
+                content_length = match v.trim().parse() {
+                    Ok(len) => len,
+                    Err(_) => return Ok(Err(Response::error("400 Bad Request"))),
+                };
+            }
+        }
+    }
+
+    if content_length > MAX_REQUEST_SIZE {
+        return Ok(Err(Response::error("413 Payload Too Large")));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Ok(Request { method, path, body }))
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+pub fn serve_connection<F: FnOnce(&Request) -> Response>(
+    stream: TcpStream,
+    handler: F,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let response = match read_request(&mut reader)? {
+        Ok(request) => handler(&request),
+        Err(response) => response,
+    };
+    write_response(&mut stream, &response)
+}
@@ -0,0 +1,352 @@
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::aead::{Aead as _, KeyInit as _, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::goldfish_type;
+use crate::{sig, sig::Scheme as _};
+use crate::{vrf, vrf::Scheme as _};
+
+const KEYSTORE_VERSION: u32 = 2;
+// scrypt cost for new keystores (2^15 iterations, 32 MiB of memory), and the
+// highest cost accepted from a keystore file
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const SCRYPT_MAX_LOG_N: u8 = 20;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Bincode,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Bincode => "bin",
+        }
+    }
+
+    // files are written with the extension of their format
+    fn from_path(path: &Path) -> io::Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(Format::Json),
+            Some("bin") => Ok(Format::Bincode),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: expected a .json or .bin file", path.display()),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PkiFileEntry {
+    id: goldfish_type::Id,
+    pk_sig: String,
+    pk_vrf: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PkiFile {
+    validators: Vec<PkiFileEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum KeystoreSecret {
+    Plain {
+        sk_sig: String,
+        sk_vrf: String,
+    },
+    // scrypt for the key, and XChaCha20-Poly1305 with the rest of the keystore as
+    // associated data, so that neither can be swapped without the password
+    Encrypted {
+        log_n: u8,
+        r: u32,
+        p: u32,
+        salt: String,
+        nonce: String,
+        ciphertext: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    id: goldfish_type::Id,
+    pk_sig: String,
+    pk_vrf: String,
+    secret: KeystoreSecret,
+}
+
+pub type Keypair = (
+    <goldfish_type::Sigs as sig::Scheme>::Sk,
+    <goldfish_type::Vrfs as vrf::Scheme>::Sk,
+);
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn encode(bytes: &[u8]) -> String {
+    general_purpose::STANDARD.encode(bytes)
+}
+
+fn decode(s: &str) -> io::Result<Vec<u8>> {
+    general_purpose::STANDARD.decode(s).map_err(invalid_data)
+}
+
+fn write_file<T: Serialize>(path: &Path, value: &T, format: Format) -> io::Result<()> {
+    let bytes = match format {
+        Format::Json => serde_json::to_vec_pretty(value).map_err(invalid_data)?,
+        Format::Bincode => bincode::serialize(value).map_err(invalid_data)?,
+    };
+    fs::write(path, bytes)
+}
+
+fn read_file<T: for<'de> Deserialize<'de>>(path: &Path) -> io::Result<T> {
+    let format = Format::from_path(path)?;
+    let bytes = fs::read(path)?;
+    match format {
+        Format::Json => serde_json::from_slice(&bytes).map_err(invalid_data),
+        Format::Bincode => bincode::deserialize(&bytes).map_err(invalid_data),
+    }
+}
+
+pub fn pki_path(dir: &Path, format: Format) -> PathBuf {
+    dir.join(format!("pki.{}", format.extension()))
+}
+
+pub fn keystore_path(dir: &Path, id: goldfish_type::Id, format: Format) -> PathBuf {
+    dir.join(format!("keystore-{}.{}", id, format.extension()))
+}
+
+fn find_file<F: Fn(Format) -> PathBuf>(path_for: F) -> io::Result<PathBuf> {
+    [Format::Json, Format::Bincode]
+        .into_iter()
+        .map(path_for)
+        .find(|path| path.exists())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such keystore file"))
+}
+
+pub fn read_password_file(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?
+        .trim_end_matches(&['\r', '\n'][..])
+        .to_string())
+}
+
+pub fn write_pki(path: &Path, pki: &goldfish_type::Pki, format: Format) -> io::Result<()> {
+    let mut validators: Vec<PkiFileEntry> = pki
+        .iter()
+        .map(|(id, (pk_sig, pk_vrf))| PkiFileEntry {
+            id: *id,
+            pk_sig: encode(&goldfish_type::Sigs::pk_to_bytes(pk_sig)),
+            pk_vrf: encode(&goldfish_type::Vrfs::pk_to_bytes(pk_vrf)),
+        })
+        .collect();
+    validators.sort_by_key(|e| e.id);
+    write_file(path, &PkiFile { validators }, format)
+}
+
+pub fn read_pki(path: &Path) -> io::Result<goldfish_type::Pki> {
+    let pki_file: PkiFile = read_file(path)?;
+    let mut pki = goldfish_type::Pki::new();
+    for e in pki_file.validators {
+        let pk_sig = goldfish_type::Sigs::pk_from_bytes(&decode(&e.pk_sig)?)
+            .ok_or_else(|| invalid_data(format!("bad signature key for id={}", e.id)))?;
+        let pk_vrf = goldfish_type::Vrfs::pk_from_bytes(&decode(&e.pk_vrf)?)
+            .ok_or_else(|| invalid_data(format!("bad VRF key for id={}", e.id)))?;
+        if pki.insert(e.id, (pk_sig, pk_vrf)).is_some() {
+            return Err(invalid_data(format!("duplicate id={}", e.id)));
+        }
+    }
+    Ok(pki)
+}
+
+fn derive_key(password: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> io::Result<Key> {
+    if log_n > SCRYPT_MAX_LOG_N {
+        return Err(invalid_data(format!("scrypt cost 2^{} is too high", log_n)));
+    }
+    let params = scrypt::Params::new(log_n, r, p, 32).map_err(invalid_data)?;
+    let mut key = Key::default();
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key).map_err(invalid_data)?;
+    Ok(key)
+}
+
+// the public part of a keystore, authenticated along with its secret
+fn associated_data(id: goldfish_type::Id, pk_sig: &str, pk_vrf: &str) -> io::Result<Vec<u8>> {
+    bincode::serialize(&(KEYSTORE_VERSION, id, pk_sig, pk_vrf)).map_err(invalid_data)
+}
+
+pub fn write_keystore(
+    path: &Path,
+    id: goldfish_type::Id,
+    pki: &goldfish_type::Pki,
+    sk_sig: &<goldfish_type::Sigs as sig::Scheme>::Sk,
+    sk_vrf: &<goldfish_type::Vrfs as vrf::Scheme>::Sk,
+    format: Format,
+    password: Option<&str>,
+) -> io::Result<()> {
+    let (pk_sig, pk_vrf) = pki
+        .get(&id)
+        .ok_or_else(|| invalid_data(format!("id={} not in PKI", id)))?;
+    let sk_sig = goldfish_type::Sigs::sk_to_bytes(sk_sig);
+    let sk_vrf = goldfish_type::Vrfs::sk_to_bytes(sk_vrf);
+    let pk_sig = encode(&goldfish_type::Sigs::pk_to_bytes(pk_sig));
+    let pk_vrf = encode(&goldfish_type::Vrfs::pk_to_bytes(pk_vrf));
+
+    let secret = match password {
+        None => KeystoreSecret::Plain {
+            sk_sig: encode(&sk_sig),
+            sk_vrf: encode(&sk_vrf),
+        },
+        Some(password) => {
+            let mut salt = [0u8; 16];
+            rand::thread_rng().fill_bytes(&mut salt);
+            let mut nonce = XNonce::default();
+            rand::thread_rng().fill_bytes(&mut nonce);
+            let key = derive_key(password, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+            let plaintext = bincode::serialize(&(sk_sig, sk_vrf)).map_err(invalid_data)?;
+            let ciphertext = XChaCha20Poly1305::new(&key)
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: &plaintext,
+                        aad: &associated_data(id, &pk_sig, &pk_vrf)?,
+                    },
+                )
+                .map_err(|_| invalid_data("encryption failed"))?;
+            KeystoreSecret::Encrypted {
+                log_n: SCRYPT_LOG_N,
+                r: SCRYPT_R,
+                p: SCRYPT_P,
+                salt: encode(&salt),
+                nonce: encode(&nonce),
+                ciphertext: encode(&ciphertext),
+            }
+        }
+    };
+
+    let keystore = KeystoreFile {
+        version: KEYSTORE_VERSION,
+        id,
+        pk_sig,
+        pk_vrf,
+        secret,
+    };
+    write_file(path, &keystore, format)
+}
+
+pub fn read_keystore(
+    path: &Path,
+    pki: &goldfish_type::Pki,
+    password: Option<&str>,
+) -> io::Result<(goldfish_type::Id, Keypair)> {
+    let keystore: KeystoreFile = read_file(path)?;
+    if keystore.version != KEYSTORE_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported keystore version {}",
+            keystore.version
+        )));
+    }
+
+    let (sk_sig, sk_vrf) = match keystore.secret {
+        KeystoreSecret::Plain { sk_sig, sk_vrf } => (decode(&sk_sig)?, decode(&sk_vrf)?),
+        KeystoreSecret::Encrypted {
+            log_n,
+            r,
+            p,
+            salt,
+            nonce,
+            ciphertext,
+        } => {
+            let password = password.ok_or_else(|| invalid_data("keystore needs a password"))?;
+            let key = derive_key(password, &decode(&salt)?, log_n, r, p)?;
+            let nonce = decode(&nonce)?;
+            if nonce.len() != XNonce::default().len() {
+                return Err(invalid_data("bad nonce length"));
+            }
+            let plaintext = XChaCha20Poly1305::new(&key)
+                .decrypt(
+                    XNonce::from_slice(&nonce),
+                    Payload {
+                        msg: &decode(&ciphertext)?,
+                        aad: &associated_data(keystore.id, &keystore.pk_sig, &keystore.pk_vrf)?,
+                    },
+                )
+                .map_err(|_| invalid_data("wrong password or corrupted keystore"))?;
+            bincode::deserialize(&plaintext).map_err(invalid_data)?
+        }
+    };
+    let sk_sig = goldfish_type::Sigs::sk_from_bytes(&sk_sig)
+        .ok_or_else(|| invalid_data("bad signature key"))?;
+    let sk_vrf =
+        goldfish_type::Vrfs::sk_from_bytes(&sk_vrf).ok_or_else(|| invalid_data("bad VRF key"))?;
+
+    // the keystore must belong to the validator the PKI knows under this id
+    let (pk_sig, pk_vrf) = pki
+        .get(&keystore.id)
+        .ok_or_else(|| invalid_data(format!("id={} not in PKI", keystore.id)))?;
+    if encode(&goldfish_type::Sigs::pk_to_bytes(pk_sig)) != keystore.pk_sig
+        || encode(&goldfish_type::Vrfs::pk_to_bytes(pk_vrf)) != keystore.pk_vrf
+    {
+        return Err(invalid_data(format!(
+            "keystore for id={} does not match PKI",
+            keystore.id
+        )));
+    }
+
+    Ok((keystore.id, (sk_sig, sk_vrf)))
+}
+
+pub fn write_dir(
+    dir: &Path,
+    pki: &goldfish_type::Pki,
+    sks: &[Keypair],
+    format: Format,
+    password: Option<&str>,
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = pki_path(dir, format);
+    write_pki(&path, pki, format)?;
+    for (id, (sk_sig, sk_vrf)) in sks.iter().enumerate() {
+        let id = id as goldfish_type::Id;
+        write_keystore(
+            &keystore_path(dir, id, format),
+            id,
+            pki,
+            sk_sig,
+            sk_vrf,
+            format,
+            password,
+        )?;
+    }
+    Ok(path)
+}
+
+pub fn read_dir(
+    dir: &Path,
+    password: Option<&str>,
+) -> io::Result<(goldfish_type::Pki, Vec<Keypair>)> {
+    let pki = read_pki(&find_file(|format| pki_path(dir, format))?)?;
+    let mut sks = Vec::new();
+    for id in 0..(pki.len() as goldfish_type::Id) {
+        let path = find_file(|format| keystore_path(dir, id, format))?;
+        let (id_, keypair) = read_keystore(&path, &pki, password)?;
+        if id_ != id {
+            return Err(invalid_data(format!(
+                "{} holds the key of id={}",
+                path.display(),
+                id_
+            )));
+        }
+        sks.push(keypair);
+    }
+    Ok((pki, sks))
+}
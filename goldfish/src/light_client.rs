@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+use crate::goldfish_blockvote;
+use crate::goldfish_type;
+use crate::merkle;
+use crate::transaction;
+
+/// Evidence that a transaction is in the ledger ending at a given tip (e.g.,
+/// `tip_slow` or `tip_fast` of a full node): the headers from the block that
+/// contains the transaction up to the tip, oldest first, and the Merkle proof
+/// of the transaction against the transaction root of the first header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    headers: Vec<goldfish_blockvote::BlockHeader>,
+    tx_index: usize,
+    tx_count: usize,
+    tx_proof: Vec<goldfish_type::Hash>,
+}
+
+impl InclusionProof {
+    pub fn headers(&self) -> &[goldfish_blockvote::BlockHeader] {
+        &self.headers
+    }
+}
+
+/// Full-node side: proves that the transaction with digest h_tx is in the ledger ending at tip
+pub fn prove(
+    bvtree: &goldfish_blockvote::BvTree,
+    tip: &goldfish_type::Hash,
+    h_tx: &goldfish_type::Hash,
+) -> Option<InclusionProof> {
+    let mut headers = Vec::new();
+    for b in bvtree.ancestors(tip) {
+        headers.push(b.header());
+        if let Some((tx_index, tx_proof)) = b.tx_proof(h_tx) {
+            headers.reverse();
+            return Some(InclusionProof {
+                headers,
+                tx_index,
+                tx_count: b.txs().len(),
+                tx_proof,
+            });
+        }
+    }
+    None
+}
+
+/// Light-client side: checks that the proof links tx to the trusted tip through
+/// a chain of headers, each signed by a winner of its slot's block lottery
+pub fn verify(
+    proof: &InclusionProof,
+    tip: &goldfish_type::Hash,
+    tx: &transaction::Transaction,
+    lotteries: &goldfish_type::Lotteries,
+    pki: &goldfish_type::Pki,
+) -> bool {
+    let (first, last) = match (proof.headers.first(), proof.headers.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return false,
+    };
+    last.digest() == *tip
+        && proof
+            .headers
+            .windows(2)
+            .all(|pair| pair[1].parent() == pair[0].digest() && pair[1].slot() > pair[0].slot())
+        && proof
+            .headers
+            .iter()
+            .all(|header| header.is_signed(lotteries, pki))
+        && merkle::verify(
+            &first.tx_root(),
+            proof.tx_count,
+            proof.tx_index,
+            tx.digest(),
+            &proof.tx_proof,
+        )
+}
@@ -8,17 +8,43 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_with::with_prefix;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::thread;
 
 mod adversary;
+mod confirmation;
+mod das;
+mod execution;
+mod ffg;
+#[cfg(test)]
+mod fuzz;
 mod ghash;
 mod goldfish_blockvote;
 mod goldfish_message;
 mod goldfish_type;
 mod goldfish_validator;
+mod http;
+mod keystore;
+mod light_client;
 mod lottery;
+mod mempool;
+mod merkle;
+mod metrics;
 mod network;
+mod network_tcp;
+#[cfg(test)]
+mod properties;
+mod rpc;
 mod sig;
+mod slot;
+mod store;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod testnet;
+mod transaction;
 mod vrf;
 
 #[derive(Parser)]
@@ -48,13 +74,154 @@ enum Commands {
         #[arg(long, default_value_t = 0)]
         f: u64,
 
-        /// Slow confirmation kappa
-        #[arg(long, default_value_t = 10)]
-        confirm_slow_kappa: u64,
+        /// Behavior of the adversary parties
+        #[arg(long, value_enum, default_value_t = adversary::Kind::Crash)]
+        adversary: adversary::Kind,
 
-        /// Fast confirmation epsilon
-        #[arg(long, default_value_t = 0.001)]
-        confirm_fast_eps: f64,
+        /// Block lottery success probability
+        #[arg(long, default_value_t = 1.0)]
+        probability_lottery_block: f64,
+
+        /// Vote lottery success probability
+        #[arg(long, default_value_t = 1.0)]
+        probability_lottery_vote: f64,
+
+        #[command(flatten)]
+        workload: transaction::WorkloadConfig,
+
+        #[command(flatten)]
+        validator: goldfish_validator::ValidatorConfig,
+
+        #[command(flatten)]
+        store: store::StoreConfig,
+
+        #[command(flatten)]
+        crash: store::CrashConfig,
+
+        /// Load validator keys from a keystore directory (see `keygen`) instead of generating them
+        #[arg(long)]
+        keystore_dir: Option<PathBuf>,
+
+        /// File containing the keystore password
+        #[arg(long)]
+        password_file: Option<PathBuf>,
+
+        /// Serve the JSON-RPC inspection API on this address while paused
+        #[arg(long)]
+        rpc_listen: Option<SocketAddr>,
+
+        /// Pause after this round until resumed over RPC (default: after the last round)
+        #[arg(long, requires = "rpc_listen")]
+        rpc_pause_after: Vec<usize>,
+
+        #[command(subcommand)]
+        command: SimulationCommands,
+    },
+
+    /// Generate validator keystores and a PKI file
+    Keygen {
+        /// Number of parties
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t=5)]
+        n: u64,
+
+        /// Output directory
+        #[arg(long)]
+        out_dir: PathBuf,
+
+        /// File format
+        #[arg(long, value_enum, default_value_t = keystore::Format::Json)]
+        format: keystore::Format,
+
+        /// File containing the password to encrypt keystores with (otherwise unencrypted)
+        #[arg(long)]
+        password_file: Option<PathBuf>,
+    },
+
+    /// Run a single validator over TCP in real time
+    Node {
+        /// Validator id
+        #[arg(long)]
+        id: u64,
+
+        /// PKI file (.json or .bin)
+        #[arg(long)]
+        pki: PathBuf,
+
+        /// Keystore file of this validator (.json or .bin)
+        #[arg(long)]
+        keystore: PathBuf,
+
+        /// File containing the keystore password
+        #[arg(long)]
+        password_file: Option<PathBuf>,
+
+        /// Address to listen on for peers
+        #[arg(long)]
+        listen: SocketAddr,
+
+        /// Address of a peer (repeat for every peer)
+        #[arg(long = "peer")]
+        peers: Vec<SocketAddr>,
+
+        /// Start of the first round (milliseconds since Unix epoch)
+        #[arg(long)]
+        genesis_time_ms: u64,
+
+        /// Duration of a round (milliseconds)
+        #[arg(long, default_value_t = 1000)]
+        round_duration_ms: u64,
+
+        /// Timeout for connecting to peers (milliseconds)
+        #[arg(long, default_value_t = 10000)]
+        connect_timeout_ms: u64,
+
+        /// Duration of run (number of slots)
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t=1)]
+        t_horizon: u64,
+
+        /// Block lottery success probability
+        #[arg(long, default_value_t = 1.0)]
+        probability_lottery_block: f64,
+
+        /// Vote lottery success probability
+        #[arg(long, default_value_t = 1.0)]
+        probability_lottery_vote: f64,
+
+        #[command(flatten)]
+        workload: transaction::WorkloadConfig,
+
+        #[command(flatten)]
+        validator: goldfish_validator::ValidatorConfig,
+
+        #[command(flatten)]
+        store: store::StoreConfig,
+
+        /// Participation schedule to follow (otherwise always awake)
+        #[arg(long)]
+        schedule: Option<PathBuf>,
+
+        /// Serve the JSON-RPC inspection API on this address
+        #[arg(long)]
+        rpc_listen: Option<SocketAddr>,
+
+        /// Serve Prometheus metrics at /metrics on this address
+        #[arg(long)]
+        metrics_listen: Option<SocketAddr>,
+
+        /// File to write per-round metrics to (CSV)
+        #[arg(long)]
+        metrics_out: Option<PathBuf>,
+    },
+
+    /// Run a local multi-process testnet
+    Testnet {
+        /// Duration of run (number of slots)
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t=1)]
+        t_horizon: u64,
+
+        /// Number of parties
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t=5)]
+        n: u64,
 
         /// Block lottery success probability
         #[arg(long, default_value_t = 1.0)]
@@ -64,12 +231,49 @@ enum Commands {
         #[arg(long, default_value_t = 1.0)]
         probability_lottery_vote: f64,
 
+        #[command(flatten)]
+        workload: transaction::WorkloadConfig,
+
+        #[command(flatten)]
+        validator: goldfish_validator::ValidatorConfig,
+
+        #[command(flatten)]
+        store: store::StoreConfig,
+
+        /// Directory for keys, schedules, logs and metrics
+        #[arg(long, default_value = "testnet")]
+        dir: PathBuf,
+
+        /// Port of the first node (node i listens on base port + i)
+        #[arg(long, default_value_t = 19000)]
+        base_port: u16,
+
+        /// Duration of a round (milliseconds)
+        #[arg(long, default_value_t = 1000)]
+        round_duration_ms: u64,
+
+        /// RPC port of the first node (node i serves RPC on this port + i; default: no RPC)
+        #[arg(long)]
+        rpc_base_port: Option<u16>,
+
+        /// Prometheus metrics port of the first node (node i serves on this port + i; default: none)
+        #[arg(long)]
+        metrics_base_port: Option<u16>,
+
+        /// Time for nodes to start up and connect before genesis (milliseconds)
+        #[arg(long, default_value_t = 3000)]
+        startup_delay_ms: u64,
+
+        /// How the participation schedule is applied to the nodes
+        #[arg(long, value_enum, default_value_t = testnet::ParticipationControl::Signal)]
+        participation_control: testnet::ParticipationControl,
+
         #[command(subcommand)]
         command: SimulationCommands,
     },
 }
 
-#[derive(Subcommand)]
+#[derive(Subcommand, Clone)]
 enum SimulationCommands {
     /// Full participation
     FullParticipation {},
@@ -133,6 +337,7 @@ enum SimulationCommands {
 }
 
 use crate::adversary::Adversary as _;
+use crate::goldfish_blockvote::BvSet as _;
 use crate::goldfish_validator::HonestValidator as _;
 use crate::sig::Scheme as _;
 use crate::vrf::Scheme as _;
@@ -144,8 +349,8 @@ fn _instantiate_validators_with_da_schedules(
         <goldfish_type::Vrfs as vrf::Scheme>::Sk,
     )>,
     _sim_horizon: std::ops::Range<usize>,
-    confirm_slow_kappa: usize,
-    confirm_fast_eps: f64,
+    validator_config: goldfish_validator::ValidatorConfig,
+    store_config: store::StoreConfig,
     da_schedules: Vec<Vec<DaScheduleStatus>>,
 ) -> (
     Vec<goldfish_validator::DaSimulationValidator>,
@@ -160,10 +365,14 @@ fn _instantiate_validators_with_da_schedules(
                 sk_sig,
                 sk_vrf,
                 pki.clone(),
-                confirm_slow_kappa,
-                confirm_fast_eps,
+                validator_config.clone(),
+                store_config
+                    .create(id)
+                    .expect("could not create validator store"),
             ),
             da_schedules[id as usize].clone(),
+            validator_config.sleep,
+            validator_config.sync,
         ));
     }
     (validators, da_schedules)
@@ -178,8 +387,8 @@ fn instantiate_validators_with_da_schedule_fn<
         <goldfish_type::Vrfs as vrf::Scheme>::Sk,
     )>,
     sim_horizon: std::ops::Range<usize>,
-    confirm_slow_kappa: usize,
-    confirm_fast_eps: f64,
+    validator_config: goldfish_validator::ValidatorConfig,
+    store_config: store::StoreConfig,
     da_schedule_fn: F,
 ) -> (
     Vec<goldfish_validator::DaSimulationValidator>,
@@ -187,7 +396,7 @@ fn instantiate_validators_with_da_schedule_fn<
 ) {
     let mut da_schedules = Vec::new();
     for id in 0..(pki.len() as u64) {
-        let mut da_schedule = [goldfish_validator::DaScheduleStatus::Awake; 4].to_vec();
+        let mut da_schedule = vec![goldfish_validator::DaScheduleStatus::Awake; sim_horizon.start];
         for r in sim_horizon.clone() {
             da_schedule.push(da_schedule_fn(id, r));
         }
@@ -198,8 +407,8 @@ fn instantiate_validators_with_da_schedule_fn<
         pki,
         sks,
         sim_horizon,
-        confirm_slow_kappa,
-        confirm_fast_eps,
+        validator_config,
+        store_config,
         da_schedules,
     )
 }
@@ -211,8 +420,8 @@ fn instantiate_validators_always_awake(
         <goldfish_type::Vrfs as vrf::Scheme>::Sk,
     )>,
     sim_horizon: std::ops::Range<usize>,
-    confirm_slow_kappa: usize,
-    confirm_fast_eps: f64,
+    validator_config: goldfish_validator::ValidatorConfig,
+    store_config: store::StoreConfig,
 ) -> (
     Vec<goldfish_validator::DaSimulationValidator>,
     Vec<Vec<goldfish_validator::DaScheduleStatus>>,
@@ -221,8 +430,8 @@ fn instantiate_validators_always_awake(
         pki,
         sks,
         sim_horizon,
-        confirm_slow_kappa,
-        confirm_fast_eps,
+        validator_config,
+        store_config,
         |_id, _r| goldfish_validator::DaScheduleStatus::Awake,
     )
 }
@@ -234,8 +443,8 @@ fn instantiate_validators_intermittent_fraction_asleep_01(
         <goldfish_type::Vrfs as vrf::Scheme>::Sk,
     )>,
     sim_horizon: std::ops::Range<usize>,
-    confirm_slow_kappa: usize,
-    confirm_fast_eps: f64,
+    validator_config: goldfish_validator::ValidatorConfig,
+    store_config: store::StoreConfig,
     fraction_warmup: f64,
     fraction_low_participation: f64,
     periods: usize,
@@ -248,9 +457,9 @@ fn instantiate_validators_intermittent_fraction_asleep_01(
     non_sleepy.shuffle(&mut thread_rng());
     non_sleepy = non_sleepy[..(low_participation * pki.len() as f64).ceil() as usize].to_vec();
 
-    let mut sleep_pattern = [goldfish_validator::DaScheduleStatus::Awake; 4].to_vec();
+    let mut sleep_pattern = vec![goldfish_validator::DaScheduleStatus::Awake; sim_horizon.start];
     for r in sim_horizon.clone() {
-        let progression = (r - 4) as f64 / (sim_horizon.end as f64);
+        let progression = (r - sim_horizon.start) as f64 / (sim_horizon.end as f64);
         if progression < fraction_warmup {
             sleep_pattern.push(goldfish_validator::DaScheduleStatus::Awake);
         } else {
@@ -269,8 +478,8 @@ fn instantiate_validators_intermittent_fraction_asleep_01(
         pki,
         sks,
         sim_horizon,
-        confirm_slow_kappa,
-        confirm_fast_eps,
+        validator_config,
+        store_config,
         |id, r| {
             if non_sleepy.iter().find(|&&i| i == id as usize).is_some() {
                 goldfish_validator::DaScheduleStatus::Awake
@@ -288,8 +497,8 @@ fn instantiate_validators_with_awake_count_schedule_fn<F: Fn(usize) -> usize>(
         <goldfish_type::Vrfs as vrf::Scheme>::Sk,
     )>,
     sim_horizon: std::ops::Range<usize>,
-    confirm_slow_kappa: usize,
-    confirm_fast_eps: f64,
+    validator_config: goldfish_validator::ValidatorConfig,
+    store_config: store::StoreConfig,
     awake_count_schedule_fn: F,
 ) -> (
     Vec<goldfish_validator::DaSimulationValidator>,
@@ -299,7 +508,7 @@ fn instantiate_validators_with_awake_count_schedule_fn<F: Fn(usize) -> usize>(
 
     let mut da_schedules = Vec::new();
     for _ in 0..(n as u64) {
-        let da_schedule = [goldfish_validator::DaScheduleStatus::Awake; 4].to_vec();
+        let da_schedule = vec![goldfish_validator::DaScheduleStatus::Awake; sim_horizon.start];
         da_schedules.push(da_schedule.clone());
     }
 
@@ -352,8 +561,8 @@ fn instantiate_validators_with_awake_count_schedule_fn<F: Fn(usize) -> usize>(
         pki,
         sks,
         sim_horizon,
-        confirm_slow_kappa,
-        confirm_fast_eps,
+        validator_config,
+        store_config,
         da_schedules,
     )
 }
@@ -365,8 +574,8 @@ fn instantiate_validators_iid01(
         <goldfish_type::Vrfs as vrf::Scheme>::Sk,
     )>,
     sim_horizon: std::ops::Range<usize>,
-    confirm_slow_kappa: usize,
-    confirm_fast_eps: f64,
+    validator_config: goldfish_validator::ValidatorConfig,
+    store_config: store::StoreConfig,
     fraction_iid: f64,
     fraction_participation_lb: f64,
 ) -> (
@@ -380,10 +589,11 @@ fn instantiate_validators_iid01(
         pki,
         sks,
         sim_horizon.clone(),
-        confirm_slow_kappa,
-        confirm_fast_eps,
+        validator_config,
+        store_config,
         |r| {
-            let progression = (r - 4) as f64 / (sim_horizon.end as f64 - 4.0);
+            let progression = (r - sim_horizon.start) as f64
+                / (sim_horizon.end as f64 - sim_horizon.start as f64);
             if progression < (1.0 - fraction_iid) / 2.0
                 || progression > 1.0 - (1.0 - fraction_iid) / 2.0
             {
@@ -402,8 +612,8 @@ fn instantiate_validators_momoseren(
         <goldfish_type::Vrfs as vrf::Scheme>::Sk,
     )>,
     sim_horizon: std::ops::Range<usize>,
-    confirm_slow_kappa: usize,
-    confirm_fast_eps: f64,
+    validator_config: goldfish_validator::ValidatorConfig,
+    store_config: store::StoreConfig,
     fraction_warmup: f64,
     fraction_crement: f64,
     fraction_low_participation_lb: f64,
@@ -415,7 +625,7 @@ fn instantiate_validators_momoseren(
     Vec<Vec<goldfish_validator::DaScheduleStatus>>,
 ) {
     let n = pki.len();
-    let mut awake_count_schedule = [1.0; 4].to_vec();
+    let mut awake_count_schedule = vec![1.0; sim_horizon.start];
     let mut state: usize = 0;
 
     fn truncate(x: f64, lb: f64, ub: f64) -> f64 {
@@ -434,7 +644,8 @@ fn instantiate_validators_momoseren(
     };
 
     for r in sim_horizon.clone() {
-        let progression = (r - 4) as f64 / (sim_horizon.end as f64 - 4.0);
+        let progression =
+            (r - sim_horizon.start) as f64 / (sim_horizon.end as f64 - sim_horizon.start as f64);
         if progression < fraction_warmup || progression > 1.0 - fraction_warmup {
             awake_count_schedule.push(1.0);
         } else {
@@ -503,12 +714,125 @@ fn instantiate_validators_momoseren(
         pki,
         sks,
         sim_horizon.clone(),
-        confirm_slow_kappa,
-        confirm_fast_eps,
+        validator_config,
+        store_config,
         |r| awake_count_schedule[r],
     )
 }
 
+fn instantiate_validators(
+    scenario: SimulationCommands,
+    pki: &goldfish_type::Pki,
+    sks: Vec<(
+        <goldfish_type::Sigs as sig::Scheme>::Sk,
+        <goldfish_type::Vrfs as vrf::Scheme>::Sk,
+    )>,
+    sim_horizon: std::ops::Range<usize>,
+    validator_config: goldfish_validator::ValidatorConfig,
+    store_config: store::StoreConfig,
+) -> (
+    Vec<goldfish_validator::DaSimulationValidator>,
+    Vec<Vec<goldfish_validator::DaScheduleStatus>>,
+) {
+    match scenario {
+        SimulationCommands::FullParticipation {} => instantiate_validators_always_awake(
+            pki,
+            sks,
+            sim_horizon,
+            validator_config,
+            store_config,
+        ),
+
+        SimulationCommands::SimpleAlternatingParticipation {
+            fraction_warmup: param_fraction_warmup,
+            fraction_low_participation: param_fraction_low_participation,
+            periods: param_periods,
+            low_participation: param_low_participation,
+        } => instantiate_validators_intermittent_fraction_asleep_01(
+            pki,
+            sks,
+            sim_horizon,
+            validator_config,
+            store_config,
+            param_fraction_warmup,
+            param_fraction_low_participation,
+            param_periods as usize,
+            param_low_participation,
+        ),
+
+        SimulationCommands::MomoseRenParticipation {
+            fraction_warmup: param_fraction_warmup,
+            fraction_crement: param_fraction_crement,
+            fraction_low_participation_lb: param_fraction_low_participation_lb,
+            fraction_low_participation_ub: param_fraction_low_participation_ub,
+            fraction_high_participation_lb: param_fraction_high_participation_lb,
+            fraction_high_participation_ub: param_fraction_high_participation_ub,
+        } => instantiate_validators_momoseren(
+            pki,
+            sks,
+            sim_horizon,
+            validator_config,
+            store_config,
+            param_fraction_warmup,
+            param_fraction_crement,
+            param_fraction_low_participation_lb,
+            param_fraction_low_participation_ub,
+            param_fraction_high_participation_lb,
+            param_fraction_high_participation_ub,
+        ),
+
+        SimulationCommands::IidParticipation {
+            fraction_iid: param_fraction_iid,
+            fraction_participation_lb: param_fraction_participation_lb,
+        } => instantiate_validators_iid01(
+            pki,
+            sks,
+            sim_horizon,
+            validator_config,
+            store_config,
+            param_fraction_iid,
+            param_fraction_participation_lb,
+        ),
+    }
+}
+
+fn generate_keys(
+    n: u64,
+) -> (
+    goldfish_type::Pki,
+    Vec<(
+        <goldfish_type::Sigs as sig::Scheme>::Sk,
+        <goldfish_type::Vrfs as vrf::Scheme>::Sk,
+    )>,
+) {
+    let mut sigs = goldfish_type::Sigs::new();
+    let mut vrfs = goldfish_type::Vrfs::new();
+    let mut pki = goldfish_type::Pki::new();
+    let mut sks = Vec::new();
+    for id in 0..n {
+        let (sk_sig, pk_sig) = sigs.gen();
+        let (sk_vrf, pk_vrf) = vrfs.gen();
+        pki.insert(id, (pk_sig, pk_vrf));
+        sks.push((sk_sig, sk_vrf));
+    }
+    (pki, sks)
+}
+
+fn instantiate_lotteries(
+    probability_lottery_block: f64,
+    probability_lottery_vote: f64,
+) -> goldfish_type::Lotteries {
+    let lottery_block: goldfish_type::Lottery = goldfish_type::Lottery::new(
+        "block".as_bytes(),
+        probability_f64_to_u64(probability_lottery_block),
+    );
+    let lottery_vote: goldfish_type::Lottery = goldfish_type::Lottery::new(
+        "vote".as_bytes(),
+        probability_f64_to_u64(probability_lottery_vote),
+    );
+    goldfish_type::Lotteries::new(lottery_block, lottery_vote)
+}
+
 fn probability_f64_to_u64(probability: f64) -> u64 {
     if probability > 0.999999 {
         0xffffffffffffffffu64
@@ -546,109 +870,100 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             t_horizon: param_t_horizon,
             n: param_n,
             f: param_f,
-            confirm_slow_kappa: param_confirm_slow_kappa,
-            confirm_fast_eps: param_confirm_fast_eps,
+            adversary: param_adversary,
             probability_lottery_block: param_probability_lottery_block,
             probability_lottery_vote: param_probability_lottery_vote,
+            workload: param_workload,
+            validator: param_validator,
+            store: param_store,
+            crash: param_crash,
+            keystore_dir: param_keystore_dir,
+            password_file: param_password_file,
+            rpc_listen: param_rpc_listen,
+            rpc_pause_after: mut param_rpc_pause_after,
             command: param_scenario,
         } => {
-            let param_r_horizon = 4 * param_t_horizon as usize;
-            let param_sim_horizon = 4..(4 + param_r_horizon);
-            let param_confirm_slow_kappa = param_confirm_slow_kappa as usize;
+            let param_slot_schedule = param_validator.slot_schedule.clone();
+            let param_prune = param_validator.prune;
+            let param_sim_horizon = param_slot_schedule.horizon(param_t_horizon);
+            let param_r_last = param_sim_horizon.end - 1;
+            if param_crash.crash_rate > 0.0 && !param_store.is_persistent() {
+                return Err("crash faults need --store-dir or --store-in-memory".into());
+            }
 
             // SETUP
 
-            let mut sigs = goldfish_type::Sigs::new();
-            let mut vrfs = goldfish_type::Vrfs::new();
-            let mut pki = goldfish_type::Pki::new();
-            let mut tmp_sks = Vec::new();
-            for id in 0..param_n {
-                let (sk_sig, pk_sig) = sigs.gen();
-                let (sk_vrf, pk_vrf) = vrfs.gen();
-                pki.insert(id, (pk_sig, pk_vrf));
-                tmp_sks.push((sk_sig, sk_vrf));
-            }
-
-            let lottery_block: goldfish_type::Lottery = goldfish_type::Lottery::new(
-                "block".as_bytes(),
-                probability_f64_to_u64(param_probability_lottery_block),
+            let (pki, tmp_sks) = match param_keystore_dir {
+                Some(dir) => {
+                    let password = match param_password_file {
+                        Some(path) => Some(keystore::read_password_file(&path)?),
+                        None => None,
+                    };
+                    let (pki, sks) = keystore::read_dir(&dir, password.as_deref())?;
+                    if pki.len() as u64 != param_n {
+                        return Err(format!(
+                            "keystore has {} validators, but n={}",
+                            pki.len(),
+                            param_n
+                        )
+                        .into());
+                    }
+                    (pki, sks)
+                }
+                None => generate_keys(param_n),
+            };
+            let lotteries = instantiate_lotteries(
+                param_probability_lottery_block,
+                param_probability_lottery_vote,
             );
-            let lottery_vote: goldfish_type::Lottery = goldfish_type::Lottery::new(
-                "vote".as_bytes(),
-                probability_f64_to_u64(param_probability_lottery_vote),
+
+            let (mut validators, mut da_schedules) = instantiate_validators(
+                param_scenario,
+                &pki,
+                tmp_sks,
+                param_sim_horizon.clone(),
+                param_validator,
+                param_store,
             );
-            let lotteries = goldfish_type::Lotteries::new(lottery_block, lottery_vote);
-
-            let (mut validators, mut da_schedules) = match param_scenario {
-                SimulationCommands::FullParticipation {} => instantiate_validators_always_awake(
-                    &pki,
-                    tmp_sks,
-                    param_sim_horizon.clone(),
-                    param_confirm_slow_kappa,
-                    param_confirm_fast_eps,
-                ),
-
-                SimulationCommands::SimpleAlternatingParticipation {
-                    fraction_warmup: param_fraction_warmup,
-                    fraction_low_participation: param_fraction_low_participation,
-                    periods: param_periods,
-                    low_participation: param_low_participation,
-                } => instantiate_validators_intermittent_fraction_asleep_01(
-                    &pki,
-                    tmp_sks,
-                    param_sim_horizon.clone(),
-                    param_confirm_slow_kappa,
-                    param_confirm_fast_eps,
-                    param_fraction_warmup,
-                    param_fraction_low_participation,
-                    param_periods as usize,
-                    param_low_participation,
-                ),
-
-                SimulationCommands::MomoseRenParticipation {
-                    fraction_warmup: param_fraction_warmup,
-                    fraction_crement: param_fraction_crement,
-                    fraction_low_participation_lb: param_fraction_low_participation_lb,
-                    fraction_low_participation_ub: param_fraction_low_participation_ub,
-                    fraction_high_participation_lb: param_fraction_high_participation_lb,
-                    fraction_high_participation_ub: param_fraction_high_participation_ub,
-                } => instantiate_validators_momoseren(
-                    &pki,
-                    tmp_sks,
-                    param_sim_horizon.clone(),
-                    param_confirm_slow_kappa,
-                    param_confirm_fast_eps,
-                    param_fraction_warmup,
-                    param_fraction_crement,
-                    param_fraction_low_participation_lb,
-                    param_fraction_low_participation_ub,
-                    param_fraction_high_participation_lb,
-                    param_fraction_high_participation_ub,
-                ),
-
-                SimulationCommands::IidParticipation {
-                    fraction_iid: param_fraction_iid,
-                    fraction_participation_lb: param_fraction_participation_lb,
-                } => instantiate_validators_iid01(
-                    &pki,
-                    tmp_sks,
-                    param_sim_horizon.clone(),
-                    param_confirm_slow_kappa,
-                    param_confirm_fast_eps,
-                    param_fraction_iid,
-                    param_fraction_participation_lb,
-                ),
-            };
+            let workload = transaction::Workload::new(param_workload);
 
             // CORRUPTION
 
-            type Adversary = adversary::CrashFaults<goldfish_validator::DaSimulationValidator>;
-            let mut adversary = Adversary::new();
+            type Validator = goldfish_validator::DaSimulationValidator;
+            let mut adversary: Box<dyn adversary::Adversary<Validator> + Send> =
+                match param_adversary {
+                    adversary::Kind::Crash => Box::new(adversary::CrashFaults::<Validator>::new()),
+                    adversary::Kind::Withhold => {
+                        Box::new(adversary::WithholdingAdversary::<Validator>::new())
+                    }
+                };
             for _id in 0..param_f {
                 adversary.corrupt(validators.remove(0));
                 da_schedules.remove(0);
             }
 
+            // CRASH-RECOVERY FAULTS
+
+            let n_crashes = param_crash.apply(
+                &mut da_schedules,
+                &param_slot_schedule,
+                param_sim_horizon.clone(),
+            );
+            for (validator, da_schedule) in validators.iter_mut().zip(da_schedules.iter()) {
+                for r in param_sim_horizon.clone() {
+                    validator.set_schedule_status(r, da_schedule[r]);
+                }
+            }
+            log::info!("Crash-recovery faults: {}", n_crashes);
+
+            let rpc_server = match param_rpc_listen {
+                Some(addr) => Some(rpc::RpcServer::bind(addr)?),
+                None => None,
+            };
+            if param_rpc_pause_after.is_empty() {
+                param_rpc_pause_after.push(param_sim_horizon.end - 1);
+            }
+
             // MAIN LOOP
             log::info!("Main loop");
 
@@ -659,9 +974,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             // let mut inboxes_ptrs: Vec<&mut network::SimulationInbox> = inboxes.iter_mut().collect();
 
             for r in param_sim_horizon.clone() {
-                let t: goldfish_type::Slot = (r as goldfish_type::Slot) / 4;
-                let phase = (r as goldfish_type::Slot) % 4;
-                log::warn!("Main loop: r={} t={} phase={}", r, t, phase);
+                let t = param_slot_schedule.slot(r);
+                let phases = param_slot_schedule.phases(r);
+                log::warn!("Main loop: r={} t={} phases={:?}", r, t, phases);
 
                 {
                     let mut inboxes = inboxes.clone();
@@ -684,13 +999,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .unwrap();
                 }
 
+                let txs = Arc::new(workload.generate(r));
                 let mut handles = vec![];
                 for inbox_id in 0..(param_n - param_f) {
                     let handle = {
                         let mut inboxes = inboxes.clone();
                         let mut this_validator = validators.remove(0);
+                        let txs = txs.clone();
                         thread::spawn(move || {
-                            this_validator.step(&lotteries, r, &mut inboxes, inbox_id as usize);
+                            this_validator.submit_txs(&txs);
+                            let mut transport =
+                                network::SimulationTransport::new(&mut inboxes, inbox_id as usize);
+                            this_validator.step(&lotteries, r, &mut transport);
                             this_validator
                         })
                     };
@@ -699,6 +1019,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 for handle in handles {
                     validators.push(handle.join().unwrap());
                 }
+
+                // no validator needs messages from before its anchor anymore
+                if param_prune.prune_depth.is_some() {
+                    let t_anchor = validators
+                        .iter()
+                        .map(|v| {
+                            let bvtree = v.validator().bvtree();
+                            bvtree.get_block(bvtree.anchor()).unwrap().slot()
+                        })
+                        .min()
+                        .unwrap_or(0);
+                    for inbox in inboxes.iter_mut() {
+                        inbox.forget_seen_before(t_anchor);
+                    }
+                }
+
+                if let Some(rpc_server) = &rpc_server {
+                    if param_rpc_pause_after.contains(&r) {
+                        log::warn!("r={} Paused, waiting for RPC resume", r);
+                        let targets: Vec<&dyn rpc::Inspect> =
+                            validators.iter().map(|v| v as &dyn rpc::Inspect).collect();
+                        rpc_server.serve_until_resume(&targets);
+                    }
+                }
             }
 
             // STATS
@@ -709,9 +1053,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 r: usize,
                 n_asleep: usize,
                 n_awake: usize,
+                n_crashed: usize,
                 n_honest_asleep: usize,
                 n_honest_dreamy: usize,
                 n_honest_awake: usize,
+                n_honest_crashed: usize,
+                n_honest_msgs_missed: usize,
                 n_adversary: usize,
                 n_all: usize,
                 #[serde(flatten, with = "prefix_party0_ledger")]
@@ -763,7 +1110,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .unwrap();
             assert!(da_schedules
                 .iter()
-                .all(|da_schedule| da_schedule.len() == param_r_horizon + 4));
+                .all(|da_schedule| da_schedule.len() == param_sim_horizon.end));
             let n_asleep: HashMap<usize, usize> =
                 HashMap::from_iter(param_sim_horizon.clone().map(|r| {
                     (
@@ -788,6 +1135,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                             .count(),
                     )
                 }));
+            let n_crashed: HashMap<usize, usize> =
+                HashMap::from_iter(param_sim_horizon.clone().map(|r| {
+                    (
+                        r,
+                        da_schedules
+                            .par_iter()
+                            .filter(|da_schedule| {
+                                da_schedule[r] == goldfish_validator::DaScheduleStatus::Crashed
+                            })
+                            .count(),
+                    )
+                }));
             let n_honest_asleep: HashMap<usize, usize> =
                 HashMap::from_iter(param_sim_horizon.clone().map(|r| {
                     (
@@ -827,15 +1186,42 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                             .count(),
                     )
                 }));
+            let n_honest_crashed: HashMap<usize, usize> =
+                HashMap::from_iter(param_sim_horizon.clone().map(|r| {
+                    (
+                        r,
+                        validators
+                            .par_iter()
+                            .filter(|val| {
+                                val.stats().get(&r).unwrap().0.status
+                                    == goldfish_validator::DaValidatorSleepStatus::Crashed
+                            })
+                            .count(),
+                    )
+                }));
+
+            let n_honest_msgs_missed: HashMap<usize, usize> =
+                HashMap::from_iter(param_sim_horizon.clone().map(|r| {
+                    (
+                        r,
+                        validators
+                            .par_iter()
+                            .map(|val| val.stats().get(&r).unwrap().0.msgs_missed)
+                            .sum(),
+                    )
+                }));
 
             let records: Vec<Stats> = param_sim_horizon
                 .map(|r| Stats {
                     r,
                     n_asleep: n_asleep[&r],
                     n_awake: n_awake[&r],
+                    n_crashed: n_crashed[&r],
                     n_honest_asleep: n_honest_asleep[&r],
                     n_honest_dreamy: n_honest_dreamy[&r],
                     n_honest_awake: n_honest_awake[&r],
+                    n_honest_crashed: n_honest_crashed[&r],
+                    n_honest_msgs_missed: n_honest_msgs_missed[&r],
                     n_adversary: param_f as usize,
                     n_all: param_n as usize,
                     party0_ledger: validators[0].stats().get(&r).unwrap().1,
@@ -871,31 +1257,94 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             println!(
                 "ALWAYS AWAKE final ledgers: {} {} {} {} {} {}",
-                validators[idx_always_awake].stats()[&(param_r_horizon + 4 - 1)]
+                validators[idx_always_awake].stats()[&param_r_last]
                     .1
                     .ledger_best
                     .length,
-                validators[idx_always_awake].stats()[&(param_r_horizon + 4 - 1)]
+                validators[idx_always_awake].stats()[&param_r_last]
                     .1
                     .ledger_best
                     .age,
-                validators[idx_always_awake].stats()[&(param_r_horizon + 4 - 1)]
+                validators[idx_always_awake].stats()[&param_r_last]
                     .1
                     .ledger_fast
                     .length,
-                validators[idx_always_awake].stats()[&(param_r_horizon + 4 - 1)]
+                validators[idx_always_awake].stats()[&param_r_last]
                     .1
                     .ledger_fast
                     .age,
-                validators[idx_always_awake].stats()[&(param_r_horizon + 4 - 1)]
+                validators[idx_always_awake].stats()[&param_r_last]
                     .1
                     .ledger_slow
                     .length,
-                validators[idx_always_awake].stats()[&(param_r_horizon + 4 - 1)]
+                validators[idx_always_awake].stats()[&param_r_last]
                     .1
                     .ledger_slow
                     .age,
             );
+            let final_ledgers = validators[idx_always_awake].stats()[&param_r_last].1;
+            println!(
+                "ALWAYS AWAKE final transactions (count, mean latency in rounds): {} {:.2} {} {:.2} {} {:.2}",
+                final_ledgers.ledger_best.txs,
+                final_ledgers.ledger_best.tx_latency_sum as f64
+                    / final_ledgers.ledger_best.txs.max(1) as f64,
+                final_ledgers.ledger_fast.txs,
+                final_ledgers.ledger_fast.tx_latency_sum as f64
+                    / final_ledgers.ledger_fast.txs.max(1) as f64,
+                final_ledgers.ledger_slow.txs,
+                final_ledgers.ledger_slow.tx_latency_sum as f64
+                    / final_ledgers.ledger_slow.txs.max(1) as f64,
+            );
+            println!(
+                "ALWAYS AWAKE finalized ledger (length, age, txs, mean latency, justified epoch, finalized epoch): {} {} {} {:.2} {} {}",
+                final_ledgers.ledger_final.length,
+                final_ledgers.ledger_final.age,
+                final_ledgers.ledger_final.txs,
+                final_ledgers.ledger_final.tx_latency_sum as f64
+                    / final_ledgers.ledger_final.txs.max(1) as f64,
+                validators[idx_always_awake]
+                    .validator()
+                    .ffg()
+                    .latest_justified()
+                    .epoch,
+                validators[idx_always_awake].validator().ffg().finalized().epoch,
+            );
+            let (state_roots_checked, state_root_mismatches) =
+                execution::check_state_roots(validators.iter().map(|v| v.validator().executor()));
+            for h in state_root_mismatches.iter() {
+                log::error!("Honest validators disagree on the state root after {:?}", h);
+            }
+            println!(
+                "HONEST state roots (blocks checked, mismatches, max rolled back): {} {} {}",
+                state_roots_checked,
+                state_root_mismatches.len(),
+                validators
+                    .iter()
+                    .map(|v| v.validator().executor().rolled_back())
+                    .max()
+                    .unwrap_or(0),
+            );
+            // a light client checks one transaction per block of the slow ledger
+            let validator = validators[idx_always_awake].validator();
+            let tip = validator.tip_slow();
+            let (mut proofs_verified, mut proofs_total, mut proofs_headers) = (0, 0, 0);
+            for b in validator.bvtree().ancestors(&tip) {
+                if let Some(tx) = b.txs().first() {
+                    let proof =
+                        light_client::prove(validator.bvtree(), &tip, &tx.digest()).unwrap();
+                    proofs_total += 1;
+                    proofs_headers += proof.headers().len();
+                    if light_client::verify(&proof, &tip, tx, &lotteries, &pki) {
+                        proofs_verified += 1;
+                    }
+                }
+            }
+            println!(
+                "ALWAYS AWAKE light client inclusion proofs (verified, total, mean headers): {} {} {:.2}",
+                proofs_verified,
+                proofs_total,
+                proofs_headers as f64 / proofs_total.max(1) as f64,
+            );
             println!(
                 "ALWAYS AWAKE total communication: {} {}",
                 inboxes[idx_always_awake]
@@ -948,8 +1397,197 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .map(|(_, v)| v.proposal_count)
                     .sum::<usize>()
             );
+            println!(
+                "ALWAYS AWAKE compact proposals (bytes saved, bytes fetched, blocks fetched): {} {} {}",
+                inboxes[idx_always_awake]
+                    .stats()
+                    .iter()
+                    .map(|(_, v)| v.proposal_saved_size)
+                    .sum::<usize>(),
+                inboxes[idx_always_awake]
+                    .stats()
+                    .iter()
+                    .map(|(_, v)| v.block_fetch_size)
+                    .sum::<usize>(),
+                inboxes[idx_always_awake]
+                    .stats()
+                    .iter()
+                    .map(|(_, v)| v.block_fetch_count)
+                    .sum::<usize>()
+            );
+            println!(
+                "ALWAYS AWAKE data availability sampling (bytes, samples, missing): {} {} {}",
+                inboxes[idx_always_awake]
+                    .stats()
+                    .iter()
+                    .map(|(_, v)| v.das_sample_size)
+                    .sum::<usize>(),
+                inboxes[idx_always_awake]
+                    .stats()
+                    .iter()
+                    .map(|(_, v)| v.das_sample_count)
+                    .sum::<usize>(),
+                inboxes[idx_always_awake]
+                    .stats()
+                    .iter()
+                    .map(|(_, v)| v.das_sample_missing)
+                    .sum::<usize>()
+            );
+            println!(
+                "HONEST messages missed while asleep or crashed (total, per validator): {} {:?}",
+                validators.iter().map(|v| v.msgs_missed()).sum::<usize>(),
+                validators
+                    .iter()
+                    .map(|v| v.msgs_missed())
+                    .collect::<Vec<usize>>(),
+            );
+            let mut msgs_invalid: HashMap<goldfish_type::ValidationError, usize> = HashMap::new();
+            for v in validators.iter() {
+                for (reason, count) in v.validator().msgs_invalid() {
+                    *msgs_invalid.entry(*reason).or_default() += count;
+                }
+            }
+            println!(
+                "HONEST invalid messages dropped (total, by reason): {} {}",
+                msgs_invalid.values().sum::<usize>(),
+                goldfish_type::ValidationError::ALL
+                    .iter()
+                    .map(|reason| format!(
+                        "{}={}",
+                        reason.name(),
+                        msgs_invalid.get(reason).cloned().unwrap_or(0)
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            );
+            let rejoin_rounds: Vec<usize> = validators
+                .iter()
+                .flat_map(|v| v.rejoin_rounds().iter().cloned())
+                .collect();
+            println!(
+                "HONEST rejoins (count, mean rounds, max rounds): {} {:.2} {}",
+                rejoin_rounds.len(),
+                rejoin_rounds.iter().sum::<usize>() as f64 / rejoin_rounds.len().max(1) as f64,
+                rejoin_rounds.iter().max().cloned().unwrap_or(0),
+            );
+            let sync_comms: Vec<network::CommunicationStats> = inboxes
+                .iter()
+                .flat_map(|inbox| inbox.stats().into_values())
+                .collect();
+            println!(
+                "HONEST sync communication (request bytes, requests, response bytes, responses): {} {} {} {}",
+                sync_comms.iter().map(|v| v.sync_request_size).sum::<usize>(),
+                sync_comms.iter().map(|v| v.sync_request_count).sum::<usize>(),
+                sync_comms.iter().map(|v| v.sync_response_size).sum::<usize>(),
+                sync_comms.iter().map(|v| v.sync_response_count).sum::<usize>(),
+            );
 
             Ok(())
         }
+
+        Commands::Keygen {
+            n: param_n,
+            out_dir: param_out_dir,
+            format: param_format,
+            password_file: param_password_file,
+        } => {
+            let password = match param_password_file {
+                Some(path) => Some(keystore::read_password_file(&path)?),
+                None => None,
+            };
+            let (pki, sks) = generate_keys(param_n);
+            let pki_path = keystore::write_dir(
+                &param_out_dir,
+                &pki,
+                &sks,
+                param_format,
+                password.as_deref(),
+            )?;
+            println!(
+                "PKI with {} validators written to {}",
+                param_n,
+                pki_path.display()
+            );
+            Ok(())
+        }
+
+        Commands::Node {
+            id: param_id,
+            pki: param_pki,
+            keystore: param_keystore,
+            password_file: param_password_file,
+            listen: param_listen,
+            peers: param_peers,
+            genesis_time_ms: param_genesis_time_ms,
+            round_duration_ms: param_round_duration_ms,
+            connect_timeout_ms: param_connect_timeout_ms,
+            t_horizon: param_t_horizon,
+            probability_lottery_block: param_probability_lottery_block,
+            probability_lottery_vote: param_probability_lottery_vote,
+            workload: param_workload,
+            validator: param_validator,
+            store: param_store,
+            schedule: param_schedule,
+            rpc_listen: param_rpc_listen,
+            metrics_listen: param_metrics_listen,
+            metrics_out: param_metrics_out,
+        } => testnet::run_node(testnet::NodeConfig {
+            id: param_id,
+            pki: param_pki,
+            keystore: param_keystore,
+            password_file: param_password_file,
+            listen: param_listen,
+            peers: param_peers,
+            genesis_time_ms: param_genesis_time_ms,
+            round_duration_ms: param_round_duration_ms,
+            connect_timeout_ms: param_connect_timeout_ms,
+            t_horizon: param_t_horizon,
+            probability_lottery_block: param_probability_lottery_block,
+            probability_lottery_vote: param_probability_lottery_vote,
+            workload: param_workload,
+            validator: param_validator,
+            store: param_store,
+            schedule: param_schedule,
+            rpc_listen: param_rpc_listen,
+            metrics_listen: param_metrics_listen,
+            metrics_out: param_metrics_out,
+        }),
+
+        Commands::Testnet {
+            t_horizon: param_t_horizon,
+            n: param_n,
+            probability_lottery_block: param_probability_lottery_block,
+            probability_lottery_vote: param_probability_lottery_vote,
+            workload: param_workload,
+            validator: param_validator,
+            store: param_store,
+            dir: param_dir,
+            base_port: param_base_port,
+            round_duration_ms: param_round_duration_ms,
+            rpc_base_port: param_rpc_base_port,
+            metrics_base_port: param_metrics_base_port,
+            startup_delay_ms: param_startup_delay_ms,
+            participation_control: param_participation_control,
+            command: param_scenario,
+        } => testnet::run_testnet(
+            testnet::TestnetConfig {
+                verbose: cli.verbose,
+                t_horizon: param_t_horizon,
+                n: param_n,
+                probability_lottery_block: param_probability_lottery_block,
+                probability_lottery_vote: param_probability_lottery_vote,
+                workload: param_workload,
+                validator: param_validator,
+                store: param_store,
+                dir: param_dir,
+                base_port: param_base_port,
+                round_duration_ms: param_round_duration_ms,
+                rpc_base_port: param_rpc_base_port,
+                metrics_base_port: param_metrics_base_port,
+                startup_delay_ms: param_startup_delay_ms,
+                participation_control: param_participation_control,
+            },
+            param_scenario,
+        ),
     }
 }
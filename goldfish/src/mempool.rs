@@ -0,0 +1,201 @@
+use rand::Rng;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+use crate::goldfish_blockvote;
+use crate::goldfish_type;
+use crate::slot;
+use crate::transaction;
+
+/// Distribution of block sizes, sampled anew for every block:
+/// `N`, `uniform:LO:HI` or `exp:MEAN` (bytes)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockSize {
+    Fixed(usize),
+    Uniform(usize, usize),
+    Exponential(f64),
+}
+
+impl BlockSize {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        match *self {
+            BlockSize::Fixed(size) => size,
+            BlockSize::Uniform(lo, hi) => rng.gen_range(lo..=hi),
+            BlockSize::Exponential(mean) => {
+                (-mean * (1.0 - rng.gen::<f64>()).ln()).round() as usize
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for BlockSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| {
+            v.parse::<usize>()
+                .map_err(|e| format!("invalid block size {:?}: {}", v, e))
+        };
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            [size] => Ok(BlockSize::Fixed(parse(size)?)),
+            ["uniform", lo, hi] => {
+                let (lo, hi) = (parse(lo)?, parse(hi)?);
+                if lo > hi {
+                    return Err(format!("empty block size range {}..={}", lo, hi));
+                }
+                Ok(BlockSize::Uniform(lo, hi))
+            }
+            ["exp", mean] => Ok(BlockSize::Exponential(parse(mean)? as f64)),
+            _ => Err(format!(
+                "invalid block size {:?} (expected N, uniform:LO:HI or exp:MEAN)",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for BlockSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockSize::Fixed(size) => write!(f, "{}", size),
+            BlockSize::Uniform(lo, hi) => write!(f, "uniform:{}:{}", lo, hi),
+            BlockSize::Exponential(mean) => write!(f, "exp:{}", mean),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct MempoolConfig {
+    /// Maximum number of pending transactions per validator (oldest are dropped first)
+    #[arg(long, default_value_t = 10_000)]
+    pub mempool_capacity: usize,
+
+    /// Block payload size in bytes: N, uniform:LO:HI or exp:MEAN (sampled per block)
+    #[arg(long, default_value_t = BlockSize::Fixed(80_000))]
+    pub block_size: BlockSize,
+
+    /// Do not pad blocks that are not filled by transactions up to the sampled size
+    #[arg(long)]
+    pub no_block_padding: bool,
+
+    /// Propose block headers only; validators fetch the block of the best proposal
+    #[arg(long)]
+    pub compact_proposals: bool,
+
+    /// Maximum total gas of the transactions in a block (default: unlimited)
+    #[arg(long)]
+    pub block_gas_limit: Option<u64>,
+}
+
+impl MempoolConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--mempool-capacity".to_string(),
+            self.mempool_capacity.to_string(),
+            "--block-size".to_string(),
+            self.block_size.to_string(),
+        ];
+        if self.no_block_padding {
+            args.push("--no-block-padding".to_string());
+        }
+        if self.compact_proposals {
+            args.push("--compact-proposals".to_string());
+        }
+        if let Some(block_gas_limit) = self.block_gas_limit {
+            args.push("--block-gas-limit".to_string());
+            args.push(block_gas_limit.to_string());
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mempool {
+    config: MempoolConfig,
+    pending: VecDeque<(goldfish_type::Hash, Arc<transaction::Transaction>)>,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Self {
+            config,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn insert(&mut self, txs: &[Arc<transaction::Transaction>]) {
+        self.pending
+            .extend(txs.iter().map(|tx| (tx.digest(), tx.clone())));
+        while self.pending.len() > self.config.mempool_capacity {
+            self.pending.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn remove(&mut self, digests: &HashSet<goldfish_type::Hash>) {
+        if !digests.is_empty() {
+            self.pending.retain(|(h, _)| !digests.contains(h));
+        }
+    }
+
+    // transactions for a block extending h, oldest first, skipping those already in the chain up to h,
+    // and the number of padding bytes needed to reach the sampled block size
+    pub fn select(
+        &self,
+        bvtree: &goldfish_blockvote::BvTree,
+        h: &goldfish_type::Hash,
+        slot_schedule: &slot::SlotSchedule,
+    ) -> (Vec<transaction::Transaction>, usize) {
+        let block_size = self.config.block_size.sample(&mut rand::thread_rng());
+        let padding = |bytes: usize| {
+            if self.config.no_block_padding {
+                0
+            } else {
+                block_size - bytes
+            }
+        };
+        let r_oldest = match self.pending.front() {
+            Some((_, tx)) => tx.created(),
+            None => return (Vec::new(), padding(0)),
+        };
+
+        // blocks proposed before the oldest pending transaction was created cannot contain any of them
+        let included: HashSet<goldfish_type::Hash> = bvtree
+            .ancestors(h)
+            .take_while(|b| {
+                slot_schedule.round(b.slot(), slot::Phase::Propose).unwrap() >= r_oldest
+            })
+            .flat_map(|b| b.txs().iter().map(|tx| tx.digest()))
+            .collect();
+
+        let mut txs = Vec::new();
+        let mut bytes = 0;
+        let mut gas = 0;
+        for (h_tx, tx) in self.pending.iter() {
+            if included.contains(h_tx) {
+                continue;
+            }
+            let size = tx.size();
+            if bytes + size > block_size {
+                break;
+            }
+            if let Some(block_gas_limit) = self.config.block_gas_limit {
+                if gas + tx.gas() > block_gas_limit {
+                    break;
+                }
+            }
+            bytes += size;
+            gas += tx.gas();
+            txs.push((**tx).clone());
+        }
+        (txs, padding(bytes))
+    }
+}
@@ -0,0 +1,69 @@
+use crate::ghash;
+use crate::goldfish_type;
+
+// Binary Merkle trees over precomputed leaf hashes, padded with default
+// hashes to a power of two. Leaf hashes should be domain-separated by the
+// caller so that they cannot be mistaken for inner nodes.
+
+pub fn node_hash(l: &goldfish_type::Hash, r: &goldfish_type::Hash) -> goldfish_type::Hash {
+    let mut hasher = ghash::Ghasher::new();
+    hasher.update(b"node");
+    hasher.update(l.as_bytes());
+    hasher.update(r.as_bytes());
+    hasher.into()
+}
+
+/// All levels of the tree, leaves first, root last
+pub fn levels(leaves: Vec<goldfish_type::Hash>) -> Vec<Vec<goldfish_type::Hash>> {
+    let mut level = leaves;
+    level.resize(
+        level.len().next_power_of_two(),
+        goldfish_type::Hash::default(),
+    );
+    let mut levels = vec![level];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| node_hash(&pair[0], &pair[1]))
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+pub fn root(levels: &[Vec<goldfish_type::Hash>]) -> goldfish_type::Hash {
+    levels.last().unwrap()[0].clone()
+}
+
+/// Sibling hashes from the leaf at index up to (excluding) the root
+pub fn proof(levels: &[Vec<goldfish_type::Hash>], index: usize) -> Vec<goldfish_type::Hash> {
+    levels[..levels.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(level, hashes)| hashes[(index >> level) ^ 1].clone())
+        .collect()
+}
+
+/// Checks a proof for the leaf at index in a tree of n leaves
+pub fn verify(
+    root: &goldfish_type::Hash,
+    n: usize,
+    index: usize,
+    leaf: goldfish_type::Hash,
+    proof: &[goldfish_type::Hash],
+) -> bool {
+    if index >= n || proof.len() != n.next_power_of_two().trailing_zeros() as usize {
+        return false;
+    }
+    let mut h = leaf;
+    for (level, sibling) in proof.iter().enumerate() {
+        h = if (index >> level) & 1 == 0 {
+            node_hash(&h, sibling)
+        } else {
+            node_hash(sibling, &h)
+        };
+    }
+    h == *root
+}
@@ -0,0 +1,262 @@
+use std::fmt::Write as _;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::goldfish_type;
+use crate::goldfish_validator::DaValidatorSleepStatus;
+use crate::http;
+use crate::network;
+use crate::network_tcp;
+use crate::rpc;
+use crate::slot;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// Prometheus text exposition format; every sample is labeled with the validator id
+struct Exposition {
+    out: String,
+    id: String,
+}
+
+impl Exposition {
+    fn metric<V: std::fmt::Display>(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        label: Option<&str>,
+        samples: &[(&str, V)],
+    ) {
+        writeln!(self.out, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.out, "# TYPE {} {}", name, kind).unwrap();
+        for (label_value, value) in samples {
+            match label {
+                Some(label) => writeln!(
+                    self.out,
+                    "{}{{id=\"{}\",{}=\"{}\"}} {}",
+                    name, self.id, label, label_value, value
+                ),
+                None => writeln!(self.out, "{}{{id=\"{}\"}} {}", name, self.id, value),
+            }
+            .unwrap();
+        }
+    }
+}
+
+fn render(target: &dyn rpc::Inspect, comms: &network::CommunicationStats) -> String {
+    let validator = target.validator();
+    let mut e = Exposition {
+        out: String::new(),
+        id: target.id().to_string(),
+    };
+
+    e.metric(
+        "goldfish_messages_received_total",
+        "counter",
+        "Messages received, by type",
+        Some("type"),
+        &[
+            ("proposal", comms.proposal_count),
+            ("block", comms.piece_block_count),
+            ("vote", comms.piece_vote_count),
+            ("ffg_vote", comms.ffg_vote_count),
+        ],
+    );
+    e.metric(
+        "goldfish_message_bytes_received_total",
+        "counter",
+        "Bytes of messages received, by type",
+        Some("type"),
+        &[
+            ("proposal", comms.proposal_size),
+            ("block", comms.piece_block_size),
+            ("vote", comms.piece_vote_size),
+            ("ffg_vote", comms.ffg_vote_size),
+        ],
+    );
+
+    let ledgers = validator.ledger_stats();
+    e.metric(
+        "goldfish_ledger_length",
+        "gauge",
+        "Height of the ledger tip",
+        Some("ledger"),
+        &[
+            ("best", ledgers.ledger_best.length),
+            ("fast", ledgers.ledger_fast.length),
+            ("slow", ledgers.ledger_slow.length),
+            ("final", ledgers.ledger_final.length),
+        ],
+    );
+    e.metric(
+        "goldfish_ledger_tip_slot",
+        "gauge",
+        "Slot of the ledger tip",
+        Some("ledger"),
+        &[
+            ("best", ledgers.ledger_best.age),
+            ("fast", ledgers.ledger_fast.age),
+            ("slow", ledgers.ledger_slow.age),
+            ("final", ledgers.ledger_final.age),
+        ],
+    );
+
+    let (hits, misses) = validator.validation_cache_hits_misses();
+    e.metric(
+        "goldfish_validation_cache_lookups_total",
+        "counter",
+        "Validation cache lookups, by outcome",
+        Some("result"),
+        &[("hit", hits), ("miss", misses)],
+    );
+    e.metric(
+        "goldfish_validation_cache_hit_ratio",
+        "gauge",
+        "Fraction of validation cache lookups that hit",
+        None,
+        &[("", hits as f64 / ((hits + misses) as f64).max(1.0))],
+    );
+
+    let sizes = validator.buffer_sizes();
+    e.metric(
+        "goldfish_limbo_length",
+        "gauge",
+        "Messages in limbo",
+        None,
+        &[("", sizes.limbo)],
+    );
+    e.metric(
+        "goldfish_buffer_length",
+        "gauge",
+        "Validated messages waiting to be merged",
+        Some("buffer"),
+        &[
+            ("blocks", sizes.buffer_blocks),
+            ("votes", sizes.buffer_votes),
+            ("proposals", sizes.buffer_proposals),
+        ],
+    );
+    e.metric(
+        "goldfish_msgs_relayed",
+        "gauge",
+        "Messages processed so far (since the last pruning)",
+        None,
+        &[("", sizes.msgs_relayed)],
+    );
+    e.metric(
+        "goldfish_tree_size",
+        "gauge",
+        "Blocks and unexpired votes in the block tree",
+        Some("kind"),
+        &[("blocks", sizes.tree_blocks), ("votes", sizes.tree_votes)],
+    );
+    let msgs_invalid = validator.msgs_invalid();
+    e.metric(
+        "goldfish_messages_invalid_total",
+        "counter",
+        "Messages dropped for being invalid, by reason",
+        Some("reason"),
+        &goldfish_type::ValidationError::ALL
+            .iter()
+            .map(|reason| {
+                (
+                    reason.name(),
+                    msgs_invalid.get(reason).cloned().unwrap_or(0),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    if let Some(status) = target.sleep_status() {
+        e.metric(
+            "goldfish_sleep_status",
+            "gauge",
+            "Current sleep status",
+            Some("status"),
+            &[
+                ("asleep", (status == DaValidatorSleepStatus::Asleep) as u8),
+                ("dreamy", (status == DaValidatorSleepStatus::Dreamy) as u8),
+                ("awake", (status == DaValidatorSleepStatus::Awake) as u8),
+                ("crashed", (status == DaValidatorSleepStatus::Crashed) as u8),
+            ],
+        );
+    }
+
+    // a summary without quantiles, so that per-phase averages can be computed from _sum/_count
+    let timings = validator.phase_timings();
+    writeln!(
+        e.out,
+        "# HELP goldfish_step_duration_seconds Time spent in validator steps, by phase"
+    )
+    .unwrap();
+    writeln!(e.out, "# TYPE goldfish_step_duration_seconds summary").unwrap();
+    for (phase, timing) in slot::Phase::ALL.iter().zip(timings.iter()) {
+        let phase = phase.name().replace('-', "_");
+        writeln!(
+            e.out,
+            "goldfish_step_duration_seconds_sum{{id=\"{}\",phase=\"{}\"}} {}",
+            e.id,
+            phase,
+            timing.total.as_secs_f64()
+        )
+        .unwrap();
+        writeln!(
+            e.out,
+            "goldfish_step_duration_seconds_count{{id=\"{}\",phase=\"{}\"}} {}",
+            e.id, phase, timing.count
+        )
+        .unwrap();
+    }
+
+    e.out
+}
+
+/// Prometheus scrape endpoint at `/metrics`
+pub struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        log::warn!("Metrics listening on {}", listener.local_addr()?);
+        Ok(Self { listener })
+    }
+
+    pub fn spawn<V: rpc::Inspect + Send + 'static>(
+        self,
+        validator: Arc<Mutex<V>>,
+        transport: network_tcp::TcpTransport,
+    ) {
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let served = http::serve_connection(stream, |request| {
+                            if request.method != "GET" {
+                                return http::Response::error("405 Method Not Allowed");
+                            }
+                            if request.path != "/metrics" {
+                                return http::Response::error("404 Not Found");
+                            }
+                            let comms = transport.stats_total();
+                            let validator = validator.lock().unwrap();
+                            http::Response::ok(
+                                CONTENT_TYPE,
+                                render(&*validator, &comms).into_bytes(),
+                            )
+                        });
+                        if let Err(e) = served {
+                            log::warn!("Metrics connection failed: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Metrics accept failed: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
@@ -1,11 +1,51 @@
+use rand::seq::IteratorRandom;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+use crate::das;
+use crate::goldfish_blockvote;
 use crate::goldfish_message;
 use crate::goldfish_type;
+use crate::sync;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub trait Transport {
+    fn broadcast(&mut self, msg: &goldfish_message::Message);
+    fn collect_inbox(&mut self) -> Vec<goldfish_message::Message>;
+
+    // make the block of a compact proposal available to others
+    // (by default, by relaying it right away)
+    fn publish_block(&mut self, b: &goldfish_blockvote::Block) {
+        self.broadcast(&goldfish_message::Message::Piece(
+            goldfish_message::Piece::Block(b.clone()),
+        ));
+    }
+
+    // get a published block on demand, if the transport supports it
+    fn fetch_block(&mut self, _h: &goldfish_type::Hash) -> Option<goldfish_blockvote::Block> {
+        None
+    }
+
+    // make the erasure-coded chunks of a block available for sampling
+    fn publish_chunks(&mut self, _b: &goldfish_blockvote::Block, _samples: Vec<das::Sample>) {}
+
+    // get one chunk of a block, if the transport supports sampling
+    fn sample_chunk(&mut self, _h: &goldfish_type::Hash, _index: usize) -> Option<das::Sample> {
+        None
+    }
+
+    // ask some peers for blocks or votes; their responses arrive with the other messages
+    fn sync_request(&mut self, _req: &sync::SyncRequest) {}
+
+    // requests of peers since the last call, with the peer to respond to
+    fn collect_sync_requests(&mut self) -> Vec<(goldfish_type::Id, sync::SyncRequest)> {
+        Vec::new()
+    }
+
+    fn sync_respond(&mut self, _peer: goldfish_type::Id, _msgs: Vec<goldfish_message::Message>) {}
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct CommunicationStats {
     pub all_size: usize,
@@ -16,14 +56,103 @@ pub struct CommunicationStats {
     pub piece_block_count: usize,
     pub piece_vote_size: usize,
     pub piece_vote_count: usize,
+    pub ffg_vote_size: usize,
+    pub ffg_vote_count: usize,
+    pub proposal_saved_size: usize,
+    pub block_fetch_size: usize,
+    pub block_fetch_count: usize,
+    pub das_sample_size: usize,
+    pub das_sample_count: usize,
+    pub das_sample_missing: usize,
+    pub sync_request_size: usize,
+    pub sync_request_count: usize,
+    pub sync_response_size: usize,
+    pub sync_response_count: usize,
+}
+
+impl CommunicationStats {
+    pub fn count(&mut self, msg: &goldfish_message::Message) {
+        let size = msg.size();
+        self.all_count += 1;
+        self.all_size += size;
+        match msg {
+            goldfish_message::Message::Proposal(p) => {
+                self.proposal_count += 1;
+                self.proposal_size += size;
+                self.proposal_saved_size += p.saved_size();
+            }
+            goldfish_message::Message::Piece(goldfish_message::Piece::Block(_)) => {
+                self.piece_block_count += 1;
+                self.piece_block_size += size;
+            }
+            goldfish_message::Message::Piece(goldfish_message::Piece::Vote(_)) => {
+                self.piece_vote_count += 1;
+                self.piece_vote_size += size;
+            }
+            goldfish_message::Message::FfgVote(_) => {
+                self.ffg_vote_count += 1;
+                self.ffg_vote_size += size;
+            }
+        }
+    }
+
+    pub fn count_fetch(&mut self, b: &goldfish_blockvote::Block) {
+        let size = bincode::serialized_size(b).unwrap() as usize;
+        self.all_count += 1;
+        self.all_size += size;
+        self.block_fetch_count += 1;
+        self.block_fetch_size += size;
+    }
+
+    pub fn count_sync_request(&mut self, req: &sync::SyncRequest) {
+        let size = req.size();
+        self.all_count += 1;
+        self.all_size += size;
+        self.sync_request_count += 1;
+        self.sync_request_size += size;
+    }
+
+    // responses are counted as sync traffic only, not by kind of message
+    pub fn count_sync_response(&mut self, msg: &goldfish_message::Message) {
+        let size = msg.size();
+        self.all_count += 1;
+        self.all_size += size;
+        self.sync_response_count += 1;
+        self.sync_response_size += size;
+    }
+
+    pub fn count_sample(&mut self, sample: Option<&das::Sample>) {
+        match sample {
+            Some(sample) => {
+                let size = sample.size();
+                self.all_count += 1;
+                self.all_size += size;
+                self.das_sample_count += 1;
+                self.das_sample_size += size;
+            }
+            None => self.das_sample_missing += 1,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SimulationInbox {
     msgs: Arc<Mutex<Vec<goldfish_message::Message>>>,
     msgs_inflight: Arc<Mutex<Vec<goldfish_message::Message>>>,
-    msgs_seen: Arc<Mutex<HashSet<goldfish_type::Hash>>>,
+    // with the slot of every message, to forget them once pruned
+    msgs_seen: Arc<Mutex<HashMap<goldfish_type::Hash, goldfish_type::Slot>>>,
     stats: Arc<Mutex<HashMap<usize, CommunicationStats>>>,
+    r_delivered: Arc<Mutex<usize>>,
+    // blocks of own compact proposals, for others to fetch
+    published: Arc<Mutex<HashMap<goldfish_type::Hash, goldfish_blockvote::Block>>>,
+    // chunks of own blocks, with the slot of the block, for others to sample
+    published_chunks: Arc<
+        Mutex<HashMap<goldfish_type::Hash, (goldfish_type::Slot, HashMap<usize, das::Sample>)>>,
+    >,
+    // requests of peers, with the inbox to respond to
+    sync_requests: Arc<Mutex<Vec<(goldfish_type::Id, sync::SyncRequest)>>>,
+    sync_requests_inflight: Arc<Mutex<Vec<(goldfish_type::Id, sync::SyncRequest)>>>,
+    sync_responses_inflight: Arc<Mutex<Vec<goldfish_message::Message>>>,
 }
 
 impl SimulationInbox {
@@ -31,8 +160,14 @@ impl SimulationInbox {
         Self {
             msgs: Arc::new(Mutex::new(Vec::new())),
             msgs_inflight: Arc::new(Mutex::new(Vec::new())),
-            msgs_seen: Arc::new(Mutex::new(HashSet::new())),
+            msgs_seen: Arc::new(Mutex::new(HashMap::new())),
             stats: Arc::new(Mutex::new(HashMap::new())),
+            r_delivered: Arc::new(Mutex::new(0)),
+            published: Arc::new(Mutex::new(HashMap::new())),
+            published_chunks: Arc::new(Mutex::new(HashMap::new())),
+            sync_requests: Arc::new(Mutex::new(Vec::new())),
+            sync_requests_inflight: Arc::new(Mutex::new(Vec::new())),
+            sync_responses_inflight: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
@@ -40,12 +175,36 @@ impl SimulationInbox {
         let hash = msg.digest();
         let mut self_msgs_seen = self.msgs_seen.lock().unwrap();
         let mut self_msgs_inflight = self.msgs_inflight.lock().unwrap();
-        if !self_msgs_seen.contains(&hash) {
+        if !self_msgs_seen.contains_key(&hash) {
             self_msgs_inflight.push(msg.clone());
-            self_msgs_seen.insert(hash);
+            self_msgs_seen.insert(hash, msg.slot());
         }
     }
 
+    pub fn make_sync_request_available(
+        &mut self,
+        peer: goldfish_type::Id,
+        req: &sync::SyncRequest,
+    ) {
+        let mut self_sync_requests_inflight = self.sync_requests_inflight.lock().unwrap();
+        self_sync_requests_inflight.push((peer, req.clone()));
+    }
+
+    // responses are delivered even if their messages were seen (and dropped) before
+    pub fn make_sync_response_available(&mut self, msgs: Vec<goldfish_message::Message>) {
+        let mut self_msgs_seen = self.msgs_seen.lock().unwrap();
+        for msg in msgs.iter() {
+            self_msgs_seen.insert(msg.digest(), msg.slot());
+        }
+        let mut self_sync_responses_inflight = self.sync_responses_inflight.lock().unwrap();
+        self_sync_responses_inflight.extend(msgs);
+    }
+
+    // messages from before slot t are no longer deduplicated
+    pub fn forget_seen_before(&mut self, t: goldfish_type::Slot) {
+        self.msgs_seen.lock().unwrap().retain(|_, t_| *t_ >= t);
+    }
+
     #[allow(dead_code)]
     pub fn adversary_peek(
         &mut self,
@@ -68,47 +227,85 @@ impl SimulationInbox {
         let mut self_msgs_inflight = self.msgs_inflight.lock().unwrap();
         let mut self_stats = self.stats.lock().unwrap();
 
-        let self_msgs_inflight_all = self_msgs_inflight.iter();
-        let self_msgs_inflight_proposal = self_msgs_inflight.iter().filter(|m| {
-            if let goldfish_message::Message::Proposal(_) = m {
-                true
-            } else {
-                false
-            }
-        });
-        let self_msgs_inflight_piece_block = self_msgs_inflight.iter().filter(|m| {
-            if let goldfish_message::Message::Piece(goldfish_message::Piece::Block(_)) = m {
-                true
-            } else {
-                false
-            }
-        });
-        let self_msgs_inflight_piece_vote = self_msgs_inflight.iter().filter(|m| {
-            if let goldfish_message::Message::Piece(goldfish_message::Piece::Vote(_)) = m {
-                true
-            } else {
-                false
-            }
-        });
-        let stats = CommunicationStats {
-            all_count: self_msgs_inflight_all.clone().count(),
-            all_size: self_msgs_inflight_all.clone().map(|m| m.size()).sum(),
-            proposal_count: self_msgs_inflight_proposal.clone().count(),
-            proposal_size: self_msgs_inflight_proposal.clone().map(|m| m.size()).sum(),
-            piece_block_count: self_msgs_inflight_piece_block.clone().count(),
-            piece_block_size: self_msgs_inflight_piece_block
-                .clone()
-                .map(|m| m.size())
-                .sum(),
-            piece_vote_count: self_msgs_inflight_piece_vote.clone().count(),
-            piece_vote_size: self_msgs_inflight_piece_vote
-                .clone()
-                .map(|m| m.size())
-                .sum(),
-        };
+        let mut self_sync_requests = self.sync_requests.lock().unwrap();
+        let mut self_sync_requests_inflight = self.sync_requests_inflight.lock().unwrap();
+        let mut self_sync_responses_inflight = self.sync_responses_inflight.lock().unwrap();
+
+        let mut stats = CommunicationStats::default();
+        for msg in self_msgs_inflight.iter() {
+            stats.count(msg);
+        }
+        for (_, req) in self_sync_requests_inflight.iter() {
+            stats.count_sync_request(req);
+        }
+        for msg in self_sync_responses_inflight.iter() {
+            stats.count_sync_response(msg);
+        }
 
         self_stats.insert(r, stats);
         self_msgs.append(&mut self_msgs_inflight);
+        self_msgs.append(&mut self_sync_responses_inflight);
+        // requests not answered within the round they arrive in are dropped
+        *self_sync_requests = std::mem::take(&mut *self_sync_requests_inflight);
+        *self.r_delivered.lock().unwrap() = r;
+    }
+
+    pub fn collect_sync_requests(&mut self) -> Vec<(goldfish_type::Id, sync::SyncRequest)> {
+        let mut self_sync_requests = self.sync_requests.lock().unwrap();
+        self_sync_requests.drain(..).collect()
+    }
+
+    pub fn publish_block(&mut self, b: &goldfish_blockvote::Block) {
+        let mut self_published = self.published.lock().unwrap();
+        // only blocks of the current and previous slot can still be fetched
+        self_published.retain(|_, b_| b_.slot() + 1 >= b.slot());
+        self_published.insert(b.digest(), b.clone());
+    }
+
+    pub fn get_published_block(
+        &self,
+        h: &goldfish_type::Hash,
+    ) -> Option<goldfish_blockvote::Block> {
+        self.published.lock().unwrap().get(h).cloned()
+    }
+
+    pub fn publish_chunks(&mut self, b: &goldfish_blockvote::Block, samples: Vec<das::Sample>) {
+        let mut self_published_chunks = self.published_chunks.lock().unwrap();
+        self_published_chunks.retain(|_, (t, _)| *t + 1 >= b.slot());
+        self_published_chunks.insert(
+            b.digest(),
+            (
+                b.slot(),
+                samples.into_iter().map(|s| (s.index(), s)).collect(),
+            ),
+        );
+    }
+
+    pub fn get_published_chunk(
+        &self,
+        h: &goldfish_type::Hash,
+        index: usize,
+    ) -> Option<das::Sample> {
+        let self_published_chunks = self.published_chunks.lock().unwrap();
+        self_published_chunks.get(h)?.1.get(&index).cloned()
+    }
+
+    pub fn count_sample(&mut self, sample: Option<&das::Sample>) {
+        let r = *self.r_delivered.lock().unwrap();
+        let mut self_stats = self.stats.lock().unwrap();
+        self_stats.entry(r).or_default().count_sample(sample);
+    }
+
+    // account for a block fetched in the current round, and ignore it if it is also relayed later
+    pub fn receive_fetched_block(&mut self, b: &goldfish_blockvote::Block) {
+        let msg = goldfish_message::Message::Piece(goldfish_message::Piece::Block(b.clone()));
+        self.msgs_seen
+            .lock()
+            .unwrap()
+            .insert(msg.digest(), b.slot());
+        let r = *self.r_delivered.lock().unwrap();
+        let mut self_stats = self.stats.lock().unwrap();
+        self_stats.entry(r).or_default().count_fetch(b);
     }
 
     #[allow(dead_code)]
@@ -117,3 +314,79 @@ impl SimulationInbox {
         self_stats.clone()
     }
 }
+
+pub struct SimulationTransport<'a> {
+    inboxes: &'a mut Vec<SimulationInbox>,
+    my_inbox: usize,
+}
+
+impl<'a> SimulationTransport<'a> {
+    pub fn new(inboxes: &'a mut Vec<SimulationInbox>, my_inbox: usize) -> Self {
+        Self { inboxes, my_inbox }
+    }
+}
+
+impl<'a> Transport for SimulationTransport<'a> {
+    fn broadcast(&mut self, msg: &goldfish_message::Message) {
+        for inbox in self.inboxes.iter_mut() {
+            inbox.make_available(msg);
+        }
+    }
+
+    fn collect_inbox(&mut self) -> Vec<goldfish_message::Message> {
+        self.inboxes[self.my_inbox].collect_inbox()
+    }
+
+    fn publish_block(&mut self, b: &goldfish_blockvote::Block) {
+        self.inboxes[self.my_inbox].publish_block(b);
+    }
+
+    // fetches are idealized to complete within the round
+    fn fetch_block(&mut self, h: &goldfish_type::Hash) -> Option<goldfish_blockvote::Block> {
+        if let Some(b) = self.inboxes[self.my_inbox].get_published_block(h) {
+            return Some(b);
+        }
+        let b = self
+            .inboxes
+            .iter()
+            .find_map(|inbox| inbox.get_published_block(h))?;
+        self.inboxes[self.my_inbox].receive_fetched_block(&b);
+        Some(b)
+    }
+
+    fn publish_chunks(&mut self, b: &goldfish_blockvote::Block, samples: Vec<das::Sample>) {
+        self.inboxes[self.my_inbox].publish_chunks(b, samples);
+    }
+
+    fn sample_chunk(&mut self, h: &goldfish_type::Hash, index: usize) -> Option<das::Sample> {
+        if let Some(sample) = self.inboxes[self.my_inbox].get_published_chunk(h, index) {
+            return Some(sample);
+        }
+        let sample = self
+            .inboxes
+            .iter()
+            .find_map(|inbox| inbox.get_published_chunk(h, index));
+        self.inboxes[self.my_inbox].count_sample(sample.as_ref());
+        sample
+    }
+
+    fn sync_request(&mut self, req: &sync::SyncRequest) {
+        let my_inbox = self.my_inbox;
+        let peers = (0..self.inboxes.len())
+            .filter(|i| *i != my_inbox)
+            .choose_multiple(&mut rand::thread_rng(), sync::SYNC_FANOUT);
+        for peer in peers {
+            self.inboxes[peer].make_sync_request_available(my_inbox as goldfish_type::Id, req);
+        }
+    }
+
+    fn collect_sync_requests(&mut self) -> Vec<(goldfish_type::Id, sync::SyncRequest)> {
+        self.inboxes[self.my_inbox].collect_sync_requests()
+    }
+
+    fn sync_respond(&mut self, peer: goldfish_type::Id, msgs: Vec<goldfish_message::Message>) {
+        if let Some(inbox) = self.inboxes.get_mut(peer as usize) {
+            inbox.make_sync_response_available(msgs);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use bincode::Options as _;
+use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::goldfish_message;
+use crate::goldfish_type;
+use crate::network;
+use crate::sync;
+
+const HANDSHAKE_MAGIC: [u8; 8] = *b"goldfish";
+const PROTOCOL_VERSION: u32 = 2;
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+// deserializing blocks with large fixed-size payloads needs more than the default stack
+const READER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Frame {
+    Handshake {
+        magic: [u8; 8],
+        version: u32,
+        id: goldfish_type::Id,
+    },
+    Message(goldfish_message::Message),
+    SyncRequest(sync::SyncRequest),
+    SyncResponse(Vec<goldfish_message::Message>),
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// frames are a big-endian u32 length followed by the bincode-serialized `Frame`
+fn write_frame_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    if bytes.len() > MAX_FRAME_SIZE {
+        return Err(invalid_data("frame too large"));
+    }
+    w.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    w.write_all(bytes)?;
+    w.flush()
+}
+
+fn write_frame<W: Write>(w: &mut W, frame: &Frame) -> io::Result<()> {
+    let bytes = bincode::serialize(frame).map_err(invalid_data)?;
+    write_frame_bytes(w, &bytes)
+}
+
+fn read_frame<R: Read>(r: &mut R) -> io::Result<Frame> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(invalid_data("frame too large"));
+    }
+    let mut bytes = vec![0u8; len];
+    r.read_exact(&mut bytes)?;
+    // same encoding as `bincode::serialize`, but bounded for untrusted input
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_FRAME_SIZE as u64)
+        .deserialize(&bytes)
+        .map_err(invalid_data)
+}
+
+fn check_handshake(frame: Frame) -> io::Result<goldfish_type::Id> {
+    match frame {
+        Frame::Handshake { magic, version, id } => {
+            if magic != HANDSHAKE_MAGIC {
+                return Err(invalid_data("handshake: bad magic"));
+            }
+            if version != PROTOCOL_VERSION {
+                return Err(invalid_data(format!(
+                    "handshake: unsupported version {}",
+                    version
+                )));
+            }
+            Ok(id)
+        }
+        _ => Err(invalid_data("handshake: expected handshake frame")),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    id: goldfish_type::Id,
+    local_addr: SocketAddr,
+    peers: Arc<Mutex<HashMap<goldfish_type::Id, mpsc::Sender<Arc<Vec<u8>>>>>>,
+    msgs: Arc<Mutex<Vec<goldfish_message::Message>>>,
+    // with the slot of every message, to forget them once pruned
+    msgs_seen: Arc<Mutex<HashMap<goldfish_type::Hash, goldfish_type::Slot>>>,
+    sync_requests: Arc<Mutex<Vec<(goldfish_type::Id, sync::SyncRequest)>>>,
+    stats_pending: Arc<Mutex<network::CommunicationStats>>,
+    stats_total: Arc<Mutex<network::CommunicationStats>>,
+    stats: Arc<Mutex<HashMap<usize, network::CommunicationStats>>>,
+}
+
+#[allow(dead_code)]
+impl TcpTransport {
+    pub fn bind(id: goldfish_type::Id, addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let transport = Self {
+            id,
+            local_addr: listener.local_addr()?,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            msgs: Arc::new(Mutex::new(Vec::new())),
+            msgs_seen: Arc::new(Mutex::new(HashMap::new())),
+            sync_requests: Arc::new(Mutex::new(Vec::new())),
+            stats_pending: Arc::new(Mutex::new(network::CommunicationStats::default())),
+            stats_total: Arc::new(Mutex::new(network::CommunicationStats::default())),
+            stats: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        let this = transport.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let myid = this.id;
+                        let this = this.clone();
+                        let spawned =
+                            thread::Builder::new()
+                                .stack_size(READER_STACK_SIZE)
+                                .spawn(move || {
+                                    if let Err(e) = this.serve_inbound(stream) {
+                                        log::warn!(
+                                            "id={} inbound connection failed: {}",
+                                            this.id,
+                                            e
+                                        );
+                                    }
+                                });
+                        if let Err(e) = spawned {
+                            log::warn!("id={} cannot spawn connection reader: {}", myid, e);
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("id={} accept failed: {}", this.id, e);
+                    }
+                }
+            }
+        });
+
+        Ok(transport)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn handshake(&self) -> Frame {
+        Frame::Handshake {
+            magic: HANDSHAKE_MAGIC,
+            version: PROTOCOL_VERSION,
+            id: self.id,
+        }
+    }
+
+    fn serve_inbound(&self, mut stream: TcpStream) -> io::Result<()> {
+        let peer_id = check_handshake(read_frame(&mut stream)?)?;
+        write_frame(&mut stream, &self.handshake())?;
+        log::info!("id={} accepted connection from id={}", self.id, peer_id);
+
+        loop {
+            match read_frame(&mut stream) {
+                Ok(Frame::Message(msg)) => self.receive(msg),
+                Ok(Frame::SyncRequest(req)) => self.receive_sync_request(peer_id, req),
+                Ok(Frame::SyncResponse(msgs)) => self.receive_sync_response(msgs),
+                Ok(Frame::Handshake { .. }) => {
+                    return Err(invalid_data("unexpected handshake frame"));
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    log::info!("id={} connection from id={} closed", self.id, peer_id);
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn connect(&mut self, addr: SocketAddr) -> io::Result<goldfish_type::Id> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        write_frame(&mut stream, &self.handshake())?;
+        let peer_id = check_handshake(read_frame(&mut stream)?)?;
+        log::info!("id={} connected to id={} at {}", self.id, peer_id, addr);
+
+        // one writer thread per peer, so that a slow or stopped peer does not block broadcasts
+        let (tx, rx) = mpsc::channel::<Arc<Vec<u8>>>();
+        let myid = self.id;
+        thread::spawn(move || {
+            for bytes in rx {
+                if let Err(e) = write_frame_bytes(&mut stream, &bytes) {
+                    log::warn!("id={} dropping connection to id={}: {}", myid, peer_id, e);
+                    break;
+                }
+            }
+        });
+        self.peers.lock().unwrap().insert(peer_id, tx);
+
+        Ok(peer_id)
+    }
+
+    pub fn connect_with_retry(
+        &mut self,
+        addr: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<goldfish_type::Id> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.connect(addr) {
+                Ok(peer_id) => return Ok(peer_id),
+                Err(e) if Instant::now() < deadline => {
+                    log::debug!(
+                        "id={} connecting to {} failed, retrying: {}",
+                        self.id,
+                        addr,
+                        e
+                    );
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn receive(&self, msg: goldfish_message::Message) {
+        let mut self_msgs_seen = self.msgs_seen.lock().unwrap();
+        if self_msgs_seen.insert(msg.digest(), msg.slot()).is_none() {
+            self.stats_pending.lock().unwrap().count(&msg);
+            self.stats_total.lock().unwrap().count(&msg);
+            self.msgs.lock().unwrap().push(msg);
+        }
+    }
+
+    // own messages are delivered like those of peers, but not counted as received
+    fn receive_own(&self, msg: goldfish_message::Message) {
+        let mut self_msgs_seen = self.msgs_seen.lock().unwrap();
+        if self_msgs_seen.insert(msg.digest(), msg.slot()).is_none() {
+            self.msgs.lock().unwrap().push(msg);
+        }
+    }
+
+    fn receive_sync_request(&self, peer_id: goldfish_type::Id, req: sync::SyncRequest) {
+        self.stats_pending.lock().unwrap().count_sync_request(&req);
+        self.stats_total.lock().unwrap().count_sync_request(&req);
+        self.sync_requests.lock().unwrap().push((peer_id, req));
+    }
+
+    // responses are delivered even if their messages were seen before
+    fn receive_sync_response(&self, msgs: Vec<goldfish_message::Message>) {
+        let mut self_msgs_seen = self.msgs_seen.lock().unwrap();
+        for msg in msgs {
+            self_msgs_seen.insert(msg.digest(), msg.slot());
+            self.stats_pending.lock().unwrap().count_sync_response(&msg);
+            self.stats_total.lock().unwrap().count_sync_response(&msg);
+            self.msgs.lock().unwrap().push(msg);
+        }
+    }
+
+    // messages from before slot t are no longer deduplicated
+    pub fn forget_seen_before(&mut self, t: goldfish_type::Slot) {
+        self.msgs_seen.lock().unwrap().retain(|_, t_| *t_ >= t);
+    }
+
+    fn send_to(&mut self, peer_id: goldfish_type::Id, frame: &Frame) {
+        let bytes = Arc::new(bincode::serialize(frame).unwrap());
+        let mut self_peers = self.peers.lock().unwrap();
+        if let Some(tx) = self_peers.get(&peer_id) {
+            if tx.send(bytes).is_err() {
+                self_peers.remove(&peer_id);
+            }
+        }
+    }
+
+    pub fn record_stats(&mut self, r: usize) {
+        let stats = std::mem::take(&mut *self.stats_pending.lock().unwrap());
+        self.stats.lock().unwrap().insert(r, stats);
+    }
+
+    pub fn stats(&self) -> HashMap<usize, network::CommunicationStats> {
+        let self_stats = self.stats.lock().unwrap();
+        self_stats.clone()
+    }
+
+    pub fn stats_total(&self) -> network::CommunicationStats {
+        *self.stats_total.lock().unwrap()
+    }
+}
+
+impl network::Transport for TcpTransport {
+    fn broadcast(&mut self, msg: &goldfish_message::Message) {
+        let bytes = Arc::new(bincode::serialize(&Frame::Message(msg.clone())).unwrap());
+
+        // deliver to ourselves, as the simulated network does
+        self.receive_own(msg.clone());
+
+        let mut self_peers = self.peers.lock().unwrap();
+        self_peers.retain(|_peer_id, tx| tx.send(bytes.clone()).is_ok());
+    }
+
+    fn collect_inbox(&mut self) -> Vec<goldfish_message::Message> {
+        let mut self_msgs = self.msgs.lock().unwrap();
+        self_msgs.drain(..).collect()
+    }
+
+    fn sync_request(&mut self, req: &sync::SyncRequest) {
+        let peers = self
+            .peers
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .choose_multiple(&mut rand::thread_rng(), sync::SYNC_FANOUT);
+        for peer_id in peers {
+            self.send_to(peer_id, &Frame::SyncRequest(req.clone()));
+        }
+    }
+
+    fn collect_sync_requests(&mut self) -> Vec<(goldfish_type::Id, sync::SyncRequest)> {
+        let mut self_sync_requests = self.sync_requests.lock().unwrap();
+        self_sync_requests.drain(..).collect()
+    }
+
+    fn sync_respond(&mut self, peer: goldfish_type::Id, msgs: Vec<goldfish_message::Message>) {
+        self.send_to(peer, &Frame::SyncResponse(msgs));
+    }
+}
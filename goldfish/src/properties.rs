@@ -0,0 +1,188 @@
+use proptest::prelude::*;
+
+use crate::goldfish_blockvote::BvSet as _;
+use crate::goldfish_type;
+use crate::goldfish_validator;
+use crate::goldfish_validator::DaScheduleStatus;
+use crate::testing;
+
+// slots with randomized participation, followed by slots with everyone awake
+const T_HORIZON: usize = 12;
+const T_TAIL: usize = 6;
+const KAPPA: usize = 4;
+
+fn configs() -> testing::Configs {
+    testing::Configs::new(&["--confirm-slow-kappa", &KAPPA.to_string()])
+}
+
+// per validator and slot of the horizon, whether it is awake
+fn participation() -> impl Strategy<Value = Vec<Vec<bool>>> {
+    (4..=20usize).prop_flat_map(|n| {
+        prop::collection::vec(
+            prop_oneof![
+                Just(vec![true; T_HORIZON]),
+                prop::collection::vec(any::<bool>(), T_HORIZON),
+            ],
+            n,
+        )
+    })
+}
+
+fn always_awake(awake: &[Vec<bool>]) -> Vec<usize> {
+    (0..awake.len())
+        .filter(|i| awake[*i].iter().all(|a| *a))
+        .collect()
+}
+
+fn network(configs: testing::Configs, awake: &[Vec<bool>]) -> testing::Network {
+    let rounds_per_slot = configs.validator.slot_schedule.rounds_per_slot();
+    let rounds = configs
+        .validator
+        .slot_schedule
+        .horizon((T_HORIZON + T_TAIL) as u64)
+        .end;
+    let da_schedules = awake
+        .iter()
+        .map(|awake| {
+            (0..rounds)
+                .map(|r| {
+                    let t = r / rounds_per_slot;
+                    match t.checked_sub(1).and_then(|i| awake.get(i)) {
+                        Some(false) => DaScheduleStatus::Asleep,
+                        _ => DaScheduleStatus::Awake,
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    testing::Network::new(configs, da_schedules)
+}
+
+// blocks from genesis (excluded) to h
+fn chain(v: &goldfish_validator::Validator, h: &goldfish_type::Hash) -> Vec<goldfish_type::Hash> {
+    let mut chain: Vec<goldfish_type::Hash> = v.bvtree().ancestors(h).map(|b| b.digest()).collect();
+    chain.reverse();
+    chain
+}
+
+fn consistent(a: &[goldfish_type::Hash], b: &[goldfish_type::Hash]) -> bool {
+    let len = a.len().min(b.len());
+    a[..len] == b[..len]
+}
+
+// runs the network, calling check at the end of every slot
+fn run<F: FnMut(&testing::Network, goldfish_type::Slot) -> Result<(), TestCaseError>>(
+    net: &mut testing::Network,
+    mut check: F,
+) -> Result<(), TestCaseError> {
+    for r in net.rounds((T_HORIZON + T_TAIL) as u64) {
+        net.step(r);
+        let t = net.configs.validator.slot_schedule.slot(r);
+        if r == net.configs.validator.slot_schedule.last_round(t) {
+            check(net, t)?;
+        }
+    }
+    for v in net.validators.iter() {
+        let res = v.validator().bvtree().check_tree();
+        prop_assert!(res.is_ok(), "validator {}: {:?}", v.validator().id(), res);
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn always_awake_validators_agree_on_slow_prefix(awake in participation()) {
+        let honest = always_awake(&awake);
+        prop_assume!(honest.len() >= 2);
+        let mut net = network(configs(), &awake);
+        run(&mut net, |net, t| {
+            let chains: Vec<Vec<goldfish_type::Hash>> = honest
+                .iter()
+                .map(|i| {
+                    let v = net.validators[*i].validator();
+                    chain(v, &v.tip_slow())
+                })
+                .collect();
+            for (a, chain_a) in honest.iter().zip(chains.iter()) {
+                for (b, chain_b) in honest.iter().zip(chains.iter()) {
+                    prop_assert!(
+                        consistent(chain_a, chain_b),
+                        "t={}: slow ledgers of {} and {} conflict",
+                        t,
+                        a,
+                        b
+                    );
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    #[test]
+    fn ledgers_grow_under_full_participation(
+        n in 4..=20usize,
+        protocol in prop_oneof![
+            Just(goldfish_validator::Protocol::Goldfish),
+            Just(goldfish_validator::Protocol::LmdGhost),
+            Just(goldfish_validator::Protocol::LongestChain),
+        ]
+    ) {
+        let mut configs = configs();
+        configs.validator.protocol = protocol;
+        let mut net = network(configs, &vec![vec![true; T_HORIZON]; n]);
+        let mut heights = vec![Vec::new(); n];
+        run(&mut net, |net, _t| {
+            for (v, heights) in net.validators.iter().zip(heights.iter_mut()) {
+                let v = v.validator();
+                heights.push(v.bvtree().get_block_height(&v.tip_slow()));
+            }
+            Ok(())
+        })?;
+
+        for (i, heights) in heights.iter().enumerate() {
+            prop_assert!(
+                heights.windows(2).all(|w| w[0] <= w[1]),
+                "slow ledger of {} shrinks: {:?}",
+                i,
+                heights
+            );
+            prop_assert!(
+                heights[heights.len() / 2] < heights[heights.len() - 1],
+                "slow ledger of {} does not grow: {:?}",
+                i,
+                heights
+            );
+        }
+    }
+
+    #[test]
+    fn fast_confirmation_implies_later_slow_confirmation(awake in participation()) {
+        let honest = always_awake(&awake);
+        prop_assume!(!honest.is_empty());
+        let mut net = network(configs(), &awake);
+        let mut fast = Vec::new();
+        run(&mut net, |net, _t| {
+            for i in honest.iter() {
+                fast.push((*i, net.validators[*i].validator().tip_fast()));
+            }
+            Ok(())
+        })?;
+
+        for i in honest.iter() {
+            let v = net.validators[*i].validator();
+            let slow = chain(v, &v.tip_slow());
+            let t_slow = v.bvtree().get_block(v.tip_slow()).unwrap().slot();
+            for (_, h) in fast.iter().filter(|(j, _)| j == i) {
+                let b = v.bvtree().get_block(h.clone()).unwrap();
+                prop_assert!(
+                    b.slot() > t_slow || b.slot() == 0 || slow.contains(h),
+                    "fast-confirmed block of slot {} not slow-confirmed by {}",
+                    b.slot(),
+                    i
+                );
+            }
+        }
+    }
+}
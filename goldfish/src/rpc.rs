@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::goldfish_blockvote;
+use crate::goldfish_blockvote::BvSet as _;
+use crate::goldfish_type;
+use crate::goldfish_validator;
+use crate::http;
+use crate::light_client;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Read-only view of a validator, as exposed over RPC
+pub trait Inspect {
+    fn id(&self) -> goldfish_type::Id;
+    fn validator(&self) -> &goldfish_validator::Validator;
+    fn sleep_status(&self) -> Option<goldfish_validator::DaValidatorSleepStatus>;
+}
+
+impl Inspect for goldfish_validator::DaSimulationValidator {
+    fn id(&self) -> goldfish_type::Id {
+        self.validator().id()
+    }
+
+    fn validator(&self) -> &goldfish_validator::Validator {
+        self.validator()
+    }
+
+    fn sleep_status(&self) -> Option<goldfish_validator::DaValidatorSleepStatus> {
+        Some(self.sleep_status())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TipJson {
+    hash: String,
+    height: usize,
+    slot: goldfish_type::Slot,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct HeadJson {
+    tip_best: TipJson,
+    tip_fast: TipJson,
+    tip_slow: TipJson,
+    tip_final: TipJson,
+    state_root: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BlockJson {
+    hash: String,
+    parent: String,
+    height: usize,
+    slot: goldfish_type::Slot,
+    proposer: goldfish_type::Id,
+    prio: u64,
+    graffiti: String,
+    txs: usize,
+    votes: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct VoteJson {
+    hash: String,
+    target: String,
+    slot: goldfish_type::Slot,
+    voter: goldfish_type::Id,
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: Option<String>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+}
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+fn tip_json(bvtree: &goldfish_blockvote::BvTree, h: goldfish_type::Hash) -> TipJson {
+    TipJson {
+        hash: h.to_base64(),
+        height: bvtree.get_block_height(&h),
+        slot: bvtree.get_block(h).unwrap().slot(),
+    }
+}
+
+fn block_json(bvtree: &goldfish_blockvote::BvTree, b: &goldfish_blockvote::Block) -> BlockJson {
+    BlockJson {
+        hash: b.digest().to_base64(),
+        parent: b.parent().to_base64(),
+        height: bvtree.get_block_height(&b.digest()),
+        slot: b.slot(),
+        proposer: b.id(),
+        prio: b.prio(),
+        graffiti: b.graffiti().to_string(),
+        txs: b.txs().len(),
+        votes: bvtree.get_block_votecount(&b.digest()),
+    }
+}
+
+fn vote_json(v: &goldfish_blockvote::Vote) -> VoteJson {
+    VoteJson {
+        hash: v.digest().to_base64(),
+        target: v.target().to_base64(),
+        slot: v.slot(),
+        voter: v.id(),
+    }
+}
+
+fn param<'a>(params: &'a Value, name: &str) -> Option<&'a Value> {
+    params.get(name).filter(|v| !v.is_null())
+}
+
+fn select_target<'a>(
+    targets: &[&'a dyn Inspect],
+    params: &Value,
+) -> Result<&'a dyn Inspect, RpcError> {
+    match param(params, "id") {
+        None => targets
+            .first()
+            .copied()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "no validators")),
+        Some(id) => {
+            let id = id
+                .as_u64()
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "id must be an integer"))?;
+            targets
+                .iter()
+                .find(|v| v.id() == id)
+                .copied()
+                .ok_or_else(|| {
+                    RpcError::new(INVALID_PARAMS, format!("no honest validator id={}", id))
+                })
+        }
+    }
+}
+
+fn call(targets: &[&dyn Inspect], method: &str, params: &Value) -> Result<Value, RpcError> {
+    if method == "list_validators" {
+        return Ok(json!(targets.iter().map(|v| v.id()).collect::<Vec<_>>()));
+    }
+
+    let target = select_target(targets, params)?;
+    let validator = target.validator();
+    let bvtree = validator.bvtree();
+
+    let result = match method {
+        "get_head" => json!(HeadJson {
+            tip_best: tip_json(bvtree, validator.tip_best()),
+            tip_fast: tip_json(bvtree, validator.tip_fast()),
+            tip_slow: tip_json(bvtree, validator.tip_slow()),
+            tip_final: tip_json(bvtree, validator.tip_final()),
+            state_root: validator.state_root().to_base64(),
+        }),
+        "get_block" => {
+            let h = param(params, "hash")
+                .and_then(|h| h.as_str())
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing hash"))?;
+            let h = goldfish_type::Hash::from_base64(h)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "malformed hash"))?;
+            match bvtree.get_block(h) {
+                Some(b) => json!(block_json(bvtree, &b)),
+                None => Value::Null,
+            }
+        }
+        "get_votes" => {
+            let t = param(params, "slot")
+                .and_then(|t| t.as_u64())
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing slot"))?;
+            json!(bvtree
+                .get_votes_in_slot(t as goldfish_type::Slot)
+                .iter()
+                .map(vote_json)
+                .collect::<Vec<_>>())
+        }
+        "get_account" => {
+            let account = param(params, "account")
+                .and_then(|a| a.as_u64())
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing account"))?;
+            json!(validator.executor().state().get(account))
+        }
+        "get_inclusion_proof" => {
+            let h_tx = param(params, "tx")
+                .and_then(|h| h.as_str())
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing tx"))?;
+            let h_tx = goldfish_type::Hash::from_base64(h_tx)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "malformed tx"))?;
+            let tip = match param(params, "tip").and_then(|t| t.as_str()) {
+                None | Some("slow") => validator.tip_slow(),
+                Some("fast") => validator.tip_fast(),
+                Some("final") => validator.tip_final(),
+                Some(_) => {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        "tip must be slow, fast or final",
+                    ))
+                }
+            };
+            match light_client::prove(bvtree, &tip, &h_tx) {
+                Some(proof) => json!({"tip": tip.to_base64(), "proof": proof}),
+                None => Value::Null,
+            }
+        }
+        "get_tree_dot" => json!(validator.dump_dotfile()),
+        "get_buffer_sizes" => json!(validator.buffer_sizes()),
+        "get_sleep_status" => json!(target.sleep_status()),
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            ))
+        }
+    };
+    Ok(result)
+}
+
+fn error_response(id: Value, e: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": e.code, "message": e.message},
+    })
+}
+
+// returns the response and whether the client asked to resume
+fn handle(targets: &[&dyn Inspect], body: &[u8], can_resume: bool) -> (Value, bool) {
+    let req: Request = match serde_json::from_slice::<Value>(body) {
+        Err(e) => {
+            return (
+                error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())),
+                false,
+            )
+        }
+        Ok(v) => match serde_json::from_value(v) {
+            Ok(req) => req,
+            Err(e) => {
+                return (
+                    error_response(Value::Null, RpcError::new(INVALID_REQUEST, e.to_string())),
+                    false,
+                )
+            }
+        },
+    };
+    if req.jsonrpc.as_deref().map_or(false, |v| v != "2.0") {
+        return (
+            error_response(
+                req.id,
+                RpcError::new(INVALID_REQUEST, "jsonrpc must be 2.0"),
+            ),
+            false,
+        );
+    }
+
+    if can_resume && req.method == "resume" {
+        return (
+            json!({"jsonrpc": "2.0", "id": req.id, "result": true}),
+            true,
+        );
+    }
+
+    match call(targets, &req.method, &req.params) {
+        Ok(result) => (
+            json!({"jsonrpc": "2.0", "id": req.id, "result": result}),
+            false,
+        ),
+        Err(e) => (error_response(req.id, e), false),
+    }
+}
+
+// returns the HTTP response and whether the client asked to resume
+fn handle_http(
+    targets: &[&dyn Inspect],
+    request: &http::Request,
+    can_resume: bool,
+) -> (http::Response, bool) {
+    if request.method != "POST" {
+        return (http::Response::error("405 Method Not Allowed"), false);
+    }
+    let (response, resume) = handle(targets, &request.body, can_resume);
+    (
+        http::Response::ok("application/json", response.to_string().into_bytes()),
+        resume,
+    )
+}
+
+/// JSON-RPC 2.0 over HTTP POST. Methods: `list_validators`, `get_head`,
+/// `get_block {hash}`, `get_votes {slot}`, `get_account {account}`,
+/// `get_inclusion_proof {tx, tip: slow|fast|final}`, `get_tree_dot`, `get_buffer_sizes`,
+/// `get_sleep_status`, and `resume` while paused.
+/// All methods but `list_validators` take an optional `id` to pick the
+/// validator (default: the first one).
+pub struct RpcServer {
+    listener: TcpListener,
+}
+
+impl RpcServer {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        log::warn!("RPC listening on {}", listener.local_addr()?);
+        Ok(Self { listener })
+    }
+
+    /// Serve requests on the calling thread until a client calls `resume`
+    pub fn serve_until_resume(&self, targets: &[&dyn Inspect]) {
+        for stream in self.listener.incoming() {
+            let mut resume = false;
+            match stream {
+                Ok(stream) => {
+                    let served = http::serve_connection(stream, |request| {
+                        let (response, resume_) = handle_http(targets, request, true);
+                        resume = resume_;
+                        response
+                    });
+                    if let Err(e) = served {
+                        log::warn!("RPC connection failed: {}", e);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("RPC accept failed: {}", e);
+                }
+            }
+            if resume {
+                return;
+            }
+        }
+    }
+
+    /// Serve requests in the background, locking the validator for each of them
+    pub fn spawn<V: Inspect + Send + 'static>(self, validator: Arc<Mutex<V>>) {
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let served = http::serve_connection(stream, |request| {
+                            let validator = validator.lock().unwrap();
+                            handle_http(&[&*validator], request, false).0
+                        });
+                        if let Err(e) = served {
+                            log::warn!("RPC connection failed: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("RPC accept failed: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
@@ -14,6 +14,16 @@ pub trait Scheme {
     fn gen(&mut self) -> (Self::Sk, Self::Pk);
     fn sign(sk: &Self::Sk, m: &[u8]) -> Self::Sig;
     fn verify(pk: &Self::Pk, m: &[u8], sigma: &Self::Sig) -> bool;
+
+    fn sk_to_bytes(sk: &Self::Sk) -> Vec<u8>;
+    fn sk_from_bytes(bytes: &[u8]) -> Option<Self::Sk>;
+    fn pk_to_bytes(pk: &Self::Pk) -> Vec<u8>;
+    fn pk_from_bytes(bytes: &[u8]) -> Option<Self::Pk>;
+}
+
+// bytes of a signature (or VRF proof) as it enters digests
+pub trait HashingBytes {
+    fn as_bytes_for_hashing(&self) -> [u8; 96];
 }
 
 pub struct MockScheme {
@@ -41,6 +51,32 @@ impl Scheme for MockScheme {
     fn verify(pk: &Self::Pk, m: &[u8], sigma: &Self::Sig) -> bool {
         sigma.0 == ghash::Ghash::new(m) && sigma.1 == *pk
     }
+
+    fn sk_to_bytes(sk: &Self::Sk) -> Vec<u8> {
+        sk.to_le_bytes().to_vec()
+    }
+
+    fn sk_from_bytes(bytes: &[u8]) -> Option<Self::Sk> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn pk_to_bytes(pk: &Self::Pk) -> Vec<u8> {
+        pk.to_le_bytes().to_vec()
+    }
+
+    fn pk_from_bytes(bytes: &[u8]) -> Option<Self::Pk> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+// signatures of the mock scheme, padded like BLS signatures
+impl HashingBytes for (ghash::Ghash, u64) {
+    fn as_bytes_for_hashing(&self) -> [u8; 96] {
+        let mut bytes = [0; 96];
+        bytes[..32].copy_from_slice(self.0.as_bytes());
+        bytes[32..40].copy_from_slice(&self.1.to_le_bytes());
+        bytes
+    }
 }
 
 pub struct MilagroBlsScheme {}
@@ -82,6 +118,24 @@ impl Scheme for MilagroBlsScheme {
         // sigma.0.clone().unwrap().verify(m, &pk.0)
         milagro_bls_scheme_verify(pk.clone(), m.to_vec(), sigma.clone())
     }
+
+    fn sk_to_bytes(sk: &Self::Sk) -> Vec<u8> {
+        sk.as_bytes().to_vec()
+    }
+
+    fn sk_from_bytes(bytes: &[u8]) -> Option<Self::Sk> {
+        milagro_bls::SecretKey::from_bytes(bytes).ok()
+    }
+
+    fn pk_to_bytes(pk: &Self::Pk) -> Vec<u8> {
+        pk.0.clone().as_bytes().to_vec()
+    }
+
+    fn pk_from_bytes(bytes: &[u8]) -> Option<Self::Pk> {
+        Some(MilagroBlsSchemePk(
+            milagro_bls::PublicKey::from_bytes(bytes).ok()?,
+        ))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -96,7 +150,7 @@ impl std::hash::Hash for MilagroBlsSchemePk {
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(
     into = "MilagroBlsSchemeSigSerdeWrapper",
-    from = "MilagroBlsSchemeSigSerdeWrapper"
+    try_from = "MilagroBlsSchemeSigSerdeWrapper"
 )]
 pub struct MilagroBlsSchemeSig(Option<milagro_bls::Signature>);
 
@@ -108,12 +162,12 @@ pub struct MilagroBlsSchemeSig(Option<milagro_bls::Signature>);
 //     }
 // }
 
-impl MilagroBlsSchemeSig {
+impl HashingBytes for MilagroBlsSchemeSig {
     // pub fn as_bytes_for_hashing(&self) -> [u8; 96] {
     //     milagro_bls_scheme_sig_as_bytes_for_hashing(self.clone())
     // }
 
-    pub fn as_bytes_for_hashing(&self) -> [u8; 96] {
+    fn as_bytes_for_hashing(&self) -> [u8; 96] {
         match self.0 {
             Some(ref sig) => sig.clone().as_bytes(),
             None => [0; 96],
@@ -238,15 +292,18 @@ impl std::convert::From<MilagroBlsSchemeSig> for MilagroBlsSchemeSigSerdeWrapper
     }
 }
 
-impl std::convert::From<MilagroBlsSchemeSigSerdeWrapper> for MilagroBlsSchemeSig {
-    fn from(value: MilagroBlsSchemeSigSerdeWrapper) -> Self {
+// malformed signatures from peers fail to deserialize instead of panicking
+impl std::convert::TryFrom<MilagroBlsSchemeSigSerdeWrapper> for MilagroBlsSchemeSig {
+    type Error = String;
+
+    fn try_from(value: MilagroBlsSchemeSigSerdeWrapper) -> Result<Self, Self::Error> {
         // if value.default {
         if value.bytes == [0; 96] {
-            Self(None)
+            Ok(Self(None))
         } else {
-            Self(Some(
-                milagro_bls::Signature::from_bytes(&value.bytes).unwrap(),
-            ))
+            let sig = milagro_bls::Signature::from_bytes(&value.bytes)
+                .map_err(|e| format!("invalid signature: {:?}", e))?;
+            Ok(Self(Some(sig)))
         }
     }
 }
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+use crate::goldfish_type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Phase {
+    Propose,
+    Vote,
+    FastConfirm,
+    SlowConfirm,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [
+        Phase::Propose,
+        Phase::Vote,
+        Phase::FastConfirm,
+        Phase::SlowConfirm,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Propose => "propose",
+            Phase::Vote => "vote",
+            Phase::FastConfirm => "fast-confirm",
+            Phase::SlowConfirm => "slow-confirm",
+        }
+    }
+}
+
+/// Rounds of a slot and the phases run in them: `PHASE[:ROUNDS],...` in order,
+/// where a phase runs in the first of its rounds (default 1), and a phase of 0
+/// rounds runs in the same round as the next phase. For example,
+/// `propose:2,vote,fast-confirm:0,slow-confirm` gives proposals an extra round
+/// to propagate and runs both confirmations in the same round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotSchedule {
+    spec: Vec<(Phase, usize)>,
+    rounds: Vec<Vec<Phase>>,
+}
+
+impl SlotSchedule {
+    pub fn new(spec: Vec<(Phase, usize)>) -> Result<Self, String> {
+        for (i, (phase, _)) in spec.iter().enumerate() {
+            if spec[..i].iter().any(|(p, _)| p == phase) {
+                return Err(format!("phase {} appears more than once", phase.name()));
+            }
+        }
+        for phase in [Phase::Propose, Phase::Vote] {
+            if !spec.iter().any(|(p, _)| *p == phase) {
+                return Err(format!("phase {} is missing", phase.name()));
+            }
+        }
+
+        let mut rounds: Vec<Vec<Phase>> = Vec::new();
+        let mut pending = Vec::new();
+        for (phase, duration) in spec.iter() {
+            pending.push(*phase);
+            if *duration > 0 {
+                rounds.push(std::mem::take(&mut pending));
+                rounds.extend((1..*duration).map(|_| Vec::new()));
+            }
+        }
+        // trailing phases of 0 rounds share the last round
+        match rounds.last_mut() {
+            Some(last) => last.extend(pending),
+            None => rounds.push(pending),
+        }
+
+        Ok(Self { spec, rounds })
+    }
+
+    pub fn rounds_per_slot(&self) -> usize {
+        self.rounds.len()
+    }
+
+    pub fn slot(&self, r: usize) -> goldfish_type::Slot {
+        (r / self.rounds_per_slot()) as goldfish_type::Slot
+    }
+
+    /// Phases to run in round r, in order (none in rounds that only give messages time to propagate)
+    pub fn phases(&self, r: usize) -> &[Phase] {
+        &self.rounds[r % self.rounds_per_slot()]
+    }
+
+    pub fn first_round(&self, t: goldfish_type::Slot) -> usize {
+        t as usize * self.rounds_per_slot()
+    }
+
+    pub fn last_round(&self, t: goldfish_type::Slot) -> usize {
+        self.first_round(t + 1) - 1
+    }
+
+    /// Round in which phase runs in slot t (if the schedule has the phase)
+    pub fn round(&self, t: goldfish_type::Slot, phase: Phase) -> Option<usize> {
+        self.rounds
+            .iter()
+            .position(|phases| phases.contains(&phase))
+            .map(|i| self.first_round(t) + i)
+    }
+
+    /// Rounds of the first t_horizon slots after the genesis slot
+    pub fn horizon(&self, t_horizon: u64) -> std::ops::Range<usize> {
+        self.first_round(1)..self.first_round(1 + t_horizon)
+    }
+}
+
+impl Default for SlotSchedule {
+    fn default() -> Self {
+        Self::new(Phase::ALL.iter().map(|phase| (*phase, 1)).collect()).unwrap()
+    }
+}
+
+impl std::str::FromStr for SlotSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s
+            .split(',')
+            .map(|part| {
+                let (name, duration) = match part.split_once(':') {
+                    Some((name, duration)) => (
+                        name,
+                        duration
+                            .parse::<usize>()
+                            .map_err(|e| format!("invalid rounds {:?}: {}", duration, e))?,
+                    ),
+                    None => (part, 1),
+                };
+                let phase = Phase::ALL
+                    .iter()
+                    .find(|phase| phase.name() == name)
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid phase {:?} (expected propose, vote, fast-confirm or slow-confirm)",
+                            name
+                        )
+                    })?;
+                Ok((*phase, duration))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Self::new(spec)
+    }
+}
+
+impl std::fmt::Display for SlotSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = self
+            .spec
+            .iter()
+            .map(|(phase, duration)| match duration {
+                1 => phase.name().to_string(),
+                _ => format!("{}:{}", phase.name(), duration),
+            })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
@@ -0,0 +1,444 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::ffg;
+use crate::ghash;
+use crate::goldfish_blockvote;
+use crate::goldfish_type;
+use crate::goldfish_validator;
+use crate::slot;
+
+const MAX_RECORD_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct StoreConfig {
+    /// Persist the blocks, votes and confirmed tips of every validator to an append-only
+    /// log in this directory, to recover from after a restart
+    #[arg(long)]
+    pub store_dir: Option<PathBuf>,
+
+    /// Keep them in memory instead, which is enough to survive simulated crashes
+    #[arg(long, conflicts_with = "store_dir")]
+    pub store_in_memory: bool,
+}
+
+impl StoreConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(dir) = &self.store_dir {
+            args.push("--store-dir".to_string());
+            args.push(dir.display().to_string());
+        }
+        if self.store_in_memory {
+            args.push("--store-in-memory".to_string());
+        }
+        args
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.store_dir.is_some() || self.store_in_memory
+    }
+
+    // store of validator id, with whatever an earlier run left in it
+    pub fn open(&self, id: goldfish_type::Id) -> io::Result<Box<dyn Store>> {
+        match &self.store_dir {
+            Some(dir) => Ok(Box::new(LogStore::open(&log_path(dir, id))?)),
+            None if self.store_in_memory => Ok(Box::new(MemoryStore::default())),
+            None => Ok(Box::new(NullStore)),
+        }
+    }
+
+    // empty store of validator id
+    pub fn create(&self, id: goldfish_type::Id) -> io::Result<Box<dyn Store>> {
+        if let Some(dir) = &self.store_dir {
+            let path = log_path(dir, id);
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        self.open(id)
+    }
+}
+
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct CrashConfig {
+    /// Probability that an awake honest validator crashes in a slot, losing everything
+    /// not in its store and the messages sent while it is down
+    #[arg(long, default_value_t = 0.0)]
+    pub crash_rate: f64,
+
+    /// Slots until a crashed validator restarts from its store
+    #[arg(long, default_value_t = 10)]
+    pub crash_slots: u64,
+}
+
+impl CrashConfig {
+    // turns slots of the schedules into crashes (except for the first always-awake
+    // validator, whose ledger is reported); returns the number of crashes
+    pub fn apply(
+        &self,
+        da_schedules: &mut [Vec<goldfish_validator::DaScheduleStatus>],
+        slot_schedule: &slot::SlotSchedule,
+        sim_horizon: std::ops::Range<usize>,
+    ) -> usize {
+        if self.crash_rate <= 0.0 {
+            return 0;
+        }
+        let idx_always_awake = da_schedules.iter().position(|da_schedule| {
+            da_schedule
+                .iter()
+                .all(|status| *status == goldfish_validator::DaScheduleStatus::Awake)
+        });
+
+        let mut rng = rand::thread_rng();
+        let mut n_crashes = 0;
+        for (idx, da_schedule) in da_schedules.iter_mut().enumerate() {
+            if Some(idx) == idx_always_awake {
+                continue;
+            }
+            let mut t = slot_schedule.slot(sim_horizon.start);
+            while slot_schedule.first_round(t) < sim_horizon.end {
+                let r = slot_schedule.first_round(t);
+                if da_schedule[r] == goldfish_validator::DaScheduleStatus::Awake
+                    && rng.gen_bool(self.crash_rate)
+                {
+                    let r_restart = slot_schedule
+                        .first_round(t + self.crash_slots)
+                        .min(sim_horizon.end);
+                    for status in da_schedule[r..r_restart].iter_mut() {
+                        *status = goldfish_validator::DaScheduleStatus::Crashed;
+                    }
+                    n_crashes += 1;
+                    t += self.crash_slots;
+                } else {
+                    t += 1;
+                }
+            }
+        }
+        n_crashes
+    }
+}
+
+pub fn log_path(dir: &Path, id: goldfish_type::Id) -> PathBuf {
+    dir.join(format!("store-{}.log", id))
+}
+
+/// Confirmed tips of a validator as of the end of round r
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tips {
+    pub r: usize,
+    pub fast: goldfish_type::Hash,
+    pub slow: goldfish_type::Hash,
+    pub best: goldfish_type::Hash,
+}
+
+// blocks come before the blocks and votes that refer to them, in the order they were merged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Record {
+    Block(goldfish_blockvote::Block),
+    Vote(goldfish_blockvote::Vote),
+    FfgVote(ffg::FfgVote),
+    Tips(Tips),
+}
+
+impl Record {
+    // tips are superseded by later tips, so only the others are looked up
+    fn digest(&self) -> Option<goldfish_type::Hash> {
+        match self {
+            Record::Block(b) => {
+                // so that a block is not taken for a duplicate of its header
+                let mut hasher = ghash::Ghasher::new();
+                b.digest_hasher_update(&mut hasher);
+                hasher.update(&[b.payload().is_some() as u8]);
+                Some(hasher.into())
+            }
+            Record::Vote(v) => Some(v.digest()),
+            Record::FfgVote(v) => Some(v.digest()),
+            Record::Tips(_) => None,
+        }
+    }
+}
+
+/// Storage backend for the block tree, FFG votes and confirmed tips of a validator
+pub trait Store: Send + Sync + std::fmt::Debug {
+    // appending a block, vote or FFG vote that is already stored does nothing
+    fn append(&mut self, record: &Record) -> io::Result<()>;
+
+    // makes everything appended so far durable
+    fn sync(&mut self) -> io::Result<()>;
+
+    // everything appended so far, in order
+    fn load(&mut self) -> io::Result<Vec<Record>>;
+}
+
+/// Keeps nothing, for validators that cannot crash
+#[derive(Debug, Default)]
+pub struct NullStore;
+
+impl Store for NullStore {
+    fn append(&mut self, _record: &Record) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Vec<Record>> {
+        Ok(Vec::new())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    records: Vec<Record>,
+    digests: HashSet<goldfish_type::Hash>,
+}
+
+impl Store for MemoryStore {
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        if let Some(h) = record.digest() {
+            if !self.digests.insert(h) {
+                return Ok(());
+            }
+        }
+        self.records.push(record.clone());
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Vec<Record>> {
+        Ok(self.records.clone())
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// records are a big-endian u32 length followed by the bincode-serialized `Record`
+fn read_record<R: Read>(r: &mut R) -> io::Result<(Record, u64)> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_RECORD_SIZE {
+        return Err(invalid_data("record too large"));
+    }
+    let mut bytes = vec![0u8; len];
+    r.read_exact(&mut bytes)?;
+    let record = bincode::deserialize(&bytes).map_err(invalid_data)?;
+    Ok((record, 4 + len as u64))
+}
+
+/// Append-only log of records on disk, with the digests of those it holds
+#[derive(Debug)]
+pub struct LogStore {
+    path: PathBuf,
+    writer: BufWriter<fs::File>,
+    digests: HashSet<goldfish_type::Hash>,
+    len: u64,
+}
+
+impl LogStore {
+    // a record cut short by a crash while writing it is dropped
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut digests = HashSet::new();
+        let mut len = 0;
+        let mut reader = BufReader::new(&file);
+        loop {
+            match read_record(&mut reader) {
+                Ok((record, size)) => {
+                    if let Some(h) = record.digest() {
+                        digests.insert(h);
+                    }
+                    len += size;
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    log::warn!("{}: dropping torn record at {}: {}", path.display(), len, e);
+                    break;
+                }
+            }
+        }
+        drop(reader);
+        if file.metadata()?.len() > len {
+            log::warn!("{}: truncating to {} bytes", path.display(), len);
+            file.set_len(len)?;
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            digests,
+            len,
+        })
+    }
+}
+
+impl Store for LogStore {
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let h = record.digest();
+        if let Some(h) = &h {
+            if self.digests.contains(h) {
+                return Ok(());
+            }
+        }
+        let bytes = bincode::serialize(record).map_err(invalid_data)?;
+        if bytes.len() > MAX_RECORD_SIZE {
+            return Err(invalid_data("record too large"));
+        }
+        self.writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&bytes)?;
+        if let Some(h) = h {
+            self.digests.insert(h);
+        }
+        self.len += 4 + bytes.len() as u64;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+
+    fn load(&mut self) -> io::Result<Vec<Record>> {
+        self.writer.flush()?;
+        let mut reader = BufReader::new(fs::File::open(&self.path)?);
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < self.len {
+            let (record, size) = read_record(&mut reader)?;
+            records.push(record);
+            offset += size;
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::goldfish_blockvote::BvSet as _;
+    use crate::goldfish_message;
+
+    fn tips(r: usize) -> Record {
+        let h = |name: &str| goldfish_type::Hash::new(format!("{}-{}", name, r).as_bytes());
+        Record::Tips(Tips {
+            r,
+            fast: h("fast"),
+            slow: h("slow"),
+            best: h("best"),
+        })
+    }
+
+    #[test]
+    fn log_store_drops_torn_record() {
+        let dir = std::env::temp_dir().join(format!("goldfish-store-{}", std::process::id()));
+        let path = log_path(&dir, 0);
+        let _ = fs::remove_file(&path);
+
+        let records: Vec<Record> = (0..5).map(tips).collect();
+        let mut store = LogStore::open(&path).unwrap();
+        for record in records.iter() {
+            store.append(record).unwrap();
+        }
+        store.sync().unwrap();
+        drop(store);
+
+        // a crash halfway through writing the last record
+        let len = fs::metadata(&path).unwrap().len();
+        let size_last = 4 + bincode::serialize(&records[4]).unwrap().len() as u64;
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - size_last / 2)
+            .unwrap();
+
+        let mut store = LogStore::open(&path).unwrap();
+        assert_eq!(store.load().unwrap(), records[..4]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len - size_last);
+
+        // appending goes on after the intact prefix
+        store.append(&tips(5)).unwrap();
+        store.sync().unwrap();
+        drop(store);
+        let mut expected = records[..4].to_vec();
+        expected.push(tips(5));
+        assert_eq!(LogStore::open(&path).unwrap().load().unwrap(), expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restart_keeps_payload_of_upgraded_block() {
+        // a block merged with only its header (compact proposal, DAS), then its payload
+        let (_, sks) = crate::generate_keys(1);
+        let b = goldfish_blockvote::Block::create(
+            &sks[0].0,
+            (0, 1),
+            Default::default(),
+            goldfish_blockvote::Block::default().digest(),
+            goldfish_blockvote::Payload::new("payload".to_string(), Vec::new(), 0),
+            goldfish_type::Hash::new(b"data root"),
+        );
+        let mut bvtree = goldfish_blockvote::BvTree::default();
+        bvtree.insert_block(&goldfish_blockvote::Block::from_header(b.header()));
+        bvtree.insert_block(&b);
+        let records: Vec<Record> = bvtree
+            .take_journal()
+            .into_iter()
+            .map(|x| match x {
+                goldfish_message::Piece::Block(b) => Record::Block(b),
+                goldfish_message::Piece::Vote(v) => Record::Vote(v),
+            })
+            .collect();
+
+        let dir = std::env::temp_dir().join(format!("goldfish-store-up-{}", std::process::id()));
+        let path = log_path(&dir, 0);
+        let _ = fs::remove_file(&path);
+        let stores: Vec<Box<dyn Store>> = vec![
+            Box::new(MemoryStore::default()),
+            Box::new(LogStore::open(&path).unwrap()),
+        ];
+        for mut store in stores {
+            for record in records.iter() {
+                store.append(record).unwrap();
+            }
+            store.sync().unwrap();
+
+            let pieces = store
+                .load()
+                .unwrap()
+                .into_iter()
+                .filter_map(|record| match record {
+                    Record::Block(b) => Some(goldfish_message::Piece::Block(b)),
+                    _ => None,
+                })
+                .collect();
+            let mut bvtree = goldfish_blockvote::BvTree::default();
+            bvtree.restore(pieces);
+            let b_restored = bvtree.get_block(b.digest()).unwrap();
+            assert_eq!(b_restored.payload(), b.payload());
+            assert_eq!(bvtree.header_only().count(), 0);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::goldfish_blockvote;
+use crate::goldfish_blockvote::BvSet as _;
+use crate::goldfish_message;
+use crate::goldfish_type;
+
+// number of peers asked for every request
+pub const SYNC_FANOUT: usize = 3;
+// rounds after which an unanswered request is sent again
+pub const SYNC_TIMEOUT: usize = 2;
+// requests for a block after which it no longer holds up rejoining
+pub const SYNC_MAX_ATTEMPTS: usize = 3;
+// responses stay well below the maximum frame size of the TCP transport
+const MAX_RESPONSE_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(clap::Args, Debug, Clone, Copy, Default)]
+pub struct SyncConfig {
+    /// After waking up, request missed blocks and votes from awake peers and only
+    /// rejoin once caught up
+    #[arg(long)]
+    pub sync: bool,
+}
+
+impl SyncConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.sync {
+            args.push("--sync".to_string());
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncRequest {
+    // blocks with these digests
+    BlocksByRoot(Vec<goldfish_type::Hash>),
+    // blocks of slots from..=to on the canonical chain of the peer
+    BlocksByRange {
+        from: goldfish_type::Slot,
+        to: goldfish_type::Slot,
+    },
+    // unexpired votes of the peer from slot `from` on
+    CurrentVotes {
+        from: goldfish_type::Slot,
+    },
+}
+
+impl SyncRequest {
+    pub fn size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize
+    }
+}
+
+// answer of a peer with block tree bvtree and canonical tip, oldest blocks first
+pub fn serve(
+    bvtree: &goldfish_blockvote::BvTree,
+    tip: &goldfish_type::Hash,
+    req: &SyncRequest,
+) -> Vec<goldfish_message::Message> {
+    let pieces: Vec<goldfish_message::Piece> = match req {
+        SyncRequest::BlocksByRoot(hs) => hs
+            .iter()
+            .filter_map(|h| bvtree.get_block(h.clone()))
+            .map(goldfish_message::Piece::Block)
+            .collect(),
+        SyncRequest::BlocksByRange { from, to } => {
+            let mut blocks: Vec<goldfish_blockvote::Block> = bvtree
+                .ancestors(tip)
+                .skip_while(|b| b.slot() > *to)
+                .take_while(|b| b.slot() >= *from)
+                .cloned()
+                .collect();
+            blocks.reverse();
+            blocks
+                .into_iter()
+                .map(goldfish_message::Piece::Block)
+                .collect()
+        }
+        SyncRequest::CurrentVotes { from } => bvtree
+            .get_votes_since(*from)
+            .into_iter()
+            .map(goldfish_message::Piece::Vote)
+            .collect(),
+    };
+
+    let mut size = 0;
+    pieces
+        .into_iter()
+        .map(goldfish_message::Message::Piece)
+        .take_while(|msg| {
+            size += msg.size();
+            size <= MAX_RESPONSE_SIZE
+        })
+        .collect()
+}
@@ -0,0 +1,121 @@
+use clap::Parser as _;
+
+use crate::goldfish_message;
+use crate::goldfish_type;
+use crate::goldfish_validator;
+use crate::goldfish_validator::HonestValidator as _;
+use crate::network;
+use crate::store;
+
+// the honest validator options of the command line, for tests to override
+#[derive(clap::Parser, Debug, Clone)]
+pub struct Configs {
+    #[arg(long, default_value_t = 1.0)]
+    pub probability_lottery_block: f64,
+
+    #[arg(long, default_value_t = 1.0)]
+    pub probability_lottery_vote: f64,
+
+    #[command(flatten)]
+    pub validator: goldfish_validator::ValidatorConfig,
+}
+
+impl Configs {
+    // defaults, except that empty blocks are not padded
+    pub fn new(args: &[&str]) -> Self {
+        Self::parse_from(["goldfish", "--no-block-padding"].iter().chain(args.iter()))
+    }
+}
+
+// honest validators on a simulated network with synchronous delivery, plus an
+// inbox nobody reads that sees every broadcast message
+pub struct Network {
+    pub configs: Configs,
+    pub lotteries: goldfish_type::Lotteries,
+    pub pki: goldfish_type::Pki,
+    pub validators: Vec<goldfish_validator::DaSimulationValidator>,
+    pub inboxes: Vec<network::SimulationInbox>,
+}
+
+impl Network {
+    // da_schedules has one schedule per validator, covering all rounds to be run
+    pub fn new(
+        configs: Configs,
+        da_schedules: Vec<Vec<goldfish_validator::DaScheduleStatus>>,
+    ) -> Self {
+        let n = da_schedules.len();
+        let lotteries = crate::instantiate_lotteries(
+            configs.probability_lottery_block,
+            configs.probability_lottery_vote,
+        );
+        let (pki, sks) = crate::generate_keys(n as u64);
+        let validators = sks
+            .into_iter()
+            .zip(da_schedules)
+            .enumerate()
+            .map(|(id, ((sk_sig, sk_vrf), da_schedule))| {
+                goldfish_validator::DaSimulationValidator::new(
+                    goldfish_validator::Validator::new(
+                        id as goldfish_type::Id,
+                        sk_sig,
+                        sk_vrf,
+                        pki.clone(),
+                        configs.validator.clone(),
+                        Box::new(store::NullStore),
+                    ),
+                    da_schedule,
+                    configs.validator.sleep,
+                    configs.validator.sync,
+                )
+            })
+            .collect();
+        let inboxes = (0..=n).map(|_| network::SimulationInbox::new()).collect();
+        Self {
+            configs,
+            lotteries,
+            pki,
+            validators,
+            inboxes,
+        }
+    }
+
+    pub fn always_awake(configs: Configs, n: usize, t_horizon: u64) -> Self {
+        let rounds = configs.validator.slot_schedule.horizon(t_horizon).end;
+        Self::new(
+            configs,
+            vec![vec![goldfish_validator::DaScheduleStatus::Awake; rounds]; n],
+        )
+    }
+
+    pub fn rounds(&self, t_horizon: u64) -> std::ops::Range<usize> {
+        self.configs.validator.slot_schedule.horizon(t_horizon)
+    }
+
+    pub fn run(&mut self, rounds: std::ops::Range<usize>) {
+        for r in rounds {
+            self.step(r);
+        }
+    }
+
+    pub fn step(&mut self, r: usize) {
+        for inbox in self.inboxes.iter_mut() {
+            inbox.deliver_msgs_inflight(r);
+        }
+        for (i, v) in self.validators.iter_mut().enumerate() {
+            let mut transport = network::SimulationTransport::new(&mut self.inboxes, i);
+            v.step(&self.lotteries, r, &mut transport);
+        }
+    }
+
+    // delivered to validator i in the next round, as if from the network
+    pub fn inject(&mut self, i: usize, msg: &goldfish_message::Message) {
+        self.inboxes[i].make_available(msg);
+    }
+
+    // all messages broadcast so far
+    pub fn observed(&mut self) -> Vec<goldfish_message::Message> {
+        let (mut msgs, msgs_inflight) = self.inboxes.last_mut().unwrap().adversary_peek();
+        msgs.extend(msgs_inflight);
+        msgs
+    }
+}
@@ -0,0 +1,422 @@
+use serde::{Deserialize, Serialize};
+use serde_with::with_prefix;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::goldfish_blockvote::BvSet as _;
+use crate::goldfish_type;
+use crate::goldfish_validator;
+use crate::goldfish_validator::HonestValidator as _;
+use crate::keystore;
+use crate::metrics;
+use crate::network;
+use crate::network_tcp;
+use crate::rpc;
+use crate::slot;
+use crate::store;
+use crate::transaction;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParticipationControl {
+    /// Suspend and resume node processes with SIGSTOP/SIGCONT
+    Signal,
+    /// Hand each node its schedule, and let it sleep by itself
+    Sleep,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub id: goldfish_type::Id,
+    pub pki: PathBuf,
+    pub keystore: PathBuf,
+    pub password_file: Option<PathBuf>,
+    pub listen: SocketAddr,
+    pub peers: Vec<SocketAddr>,
+    pub genesis_time_ms: u64,
+    pub round_duration_ms: u64,
+    pub connect_timeout_ms: u64,
+    pub t_horizon: u64,
+    pub probability_lottery_block: f64,
+    pub probability_lottery_vote: f64,
+    pub workload: transaction::WorkloadConfig,
+    pub validator: goldfish_validator::ValidatorConfig,
+    pub store: store::StoreConfig,
+    pub schedule: Option<PathBuf>,
+    pub rpc_listen: Option<SocketAddr>,
+    pub metrics_listen: Option<SocketAddr>,
+    pub metrics_out: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestnetConfig {
+    pub verbose: u8,
+    pub t_horizon: u64,
+    pub n: u64,
+    pub probability_lottery_block: f64,
+    pub probability_lottery_vote: f64,
+    pub workload: transaction::WorkloadConfig,
+    pub validator: goldfish_validator::ValidatorConfig,
+    pub store: store::StoreConfig,
+    pub dir: PathBuf,
+    pub base_port: u16,
+    pub round_duration_ms: u64,
+    pub rpc_base_port: Option<u16>,
+    pub metrics_base_port: Option<u16>,
+    pub startup_delay_ms: u64,
+    pub participation_control: ParticipationControl,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct NodeStats {
+    r: usize,
+    id: goldfish_type::Id,
+    status: goldfish_validator::DaValidatorSleepStatus,
+    #[serde(flatten)]
+    ledger: goldfish_validator::ValidatorLedgerStats,
+    #[serde(flatten, with = "prefix_comms")]
+    comms: network::CommunicationStats,
+}
+
+with_prefix!(prefix_comms "comms_");
+
+// the first round after the genesis slot starts at genesis time
+fn round_start(
+    genesis_time_ms: u64,
+    round_duration_ms: u64,
+    slot_schedule: &slot::SlotSchedule,
+    r: usize,
+) -> SystemTime {
+    let r_first = slot_schedule.first_round(1);
+    UNIX_EPOCH + Duration::from_millis(genesis_time_ms + (r - r_first) as u64 * round_duration_ms)
+}
+
+fn sleep_until(t: SystemTime) {
+    if let Ok(d) = t.duration_since(SystemTime::now()) {
+        thread::sleep(d);
+    }
+}
+
+// sampling needs a transport that serves chunks, which only the simulation has
+const NO_DAS: &str = "--das is only supported in simulations";
+
+pub fn run_node(cfg: NodeConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let pki = keystore::read_pki(&cfg.pki)?;
+    let password = match &cfg.password_file {
+        Some(path) => Some(keystore::read_password_file(path)?),
+        None => None,
+    };
+    if cfg.validator.das.das {
+        return Err(NO_DAS.into());
+    }
+    let (id, (sk_sig, sk_vrf)) = keystore::read_keystore(&cfg.keystore, &pki, password.as_deref())?;
+    if id != cfg.id {
+        return Err(format!("keystore is for id={}, not id={}", id, cfg.id).into());
+    }
+    let lotteries =
+        crate::instantiate_lotteries(cfg.probability_lottery_block, cfg.probability_lottery_vote);
+    let schedule: Option<Vec<goldfish_validator::DaScheduleStatus>> = match &cfg.schedule {
+        Some(path) => Some(bincode::deserialize(&fs::read(path)?)?),
+        None => None,
+    };
+    let sim_horizon = cfg.validator.slot_schedule.horizon(cfg.t_horizon);
+    if let Some(schedule) = &schedule {
+        if schedule.len() < sim_horizon.end {
+            return Err(format!(
+                "schedule covers {} rounds, but the horizon has {}",
+                schedule.len(),
+                sim_horizon.end
+            )
+            .into());
+        }
+    }
+
+    let mut transport = network_tcp::TcpTransport::bind(id, cfg.listen)?;
+    for peer in cfg.peers.iter() {
+        transport.connect_with_retry(*peer, Duration::from_millis(cfg.connect_timeout_ms))?;
+    }
+
+    let validator = Arc::new(Mutex::new(goldfish_validator::DaSimulationValidator::new(
+        goldfish_validator::Validator::new(
+            id,
+            sk_sig,
+            sk_vrf,
+            pki,
+            cfg.validator.clone(),
+            cfg.store.open(id)?,
+        ),
+        vec![
+            goldfish_validator::DaScheduleStatus::Awake;
+            cfg.validator.slot_schedule.rounds_per_slot()
+        ],
+        cfg.validator.sleep,
+        cfg.validator.sync,
+    )));
+    // a node restarted after a crash picks up where its store left off
+    if let Some(r) = validator.lock().unwrap().restart()? {
+        log::warn!("id={} Recovered from store up to r={}", id, r);
+    }
+    if let Some(addr) = cfg.rpc_listen {
+        rpc::RpcServer::bind(addr)?.spawn(validator.clone());
+    }
+    if let Some(addr) = cfg.metrics_listen {
+        metrics::MetricsServer::bind(addr)?.spawn(validator.clone(), transport.clone());
+    }
+
+    let workload = transaction::Workload::new(cfg.workload.clone());
+
+    log::warn!("id={} Main loop", id);
+
+    for r in sim_horizon.clone() {
+        sleep_until(round_start(
+            cfg.genesis_time_ms,
+            cfg.round_duration_ms,
+            &cfg.validator.slot_schedule,
+            r,
+        ));
+
+        // a round that is already over when we get to it was slept through
+        // (e.g., because the process was suspended)
+        let missed = SystemTime::now()
+            >= round_start(
+                cfg.genesis_time_ms,
+                cfg.round_duration_ms,
+                &cfg.validator.slot_schedule,
+                r + 1,
+            );
+        let status = if missed {
+            log::warn!("r={} id={} MISSED", r, id);
+            goldfish_validator::DaScheduleStatus::Asleep
+        } else {
+            match &schedule {
+                Some(schedule) => schedule[r],
+                None => goldfish_validator::DaScheduleStatus::Awake,
+            }
+        };
+
+        let mut validator = validator.lock().unwrap();
+        validator.submit_txs(&workload.generate(r));
+        validator.set_schedule_status(r, status);
+        validator.step(&lotteries, r, &mut transport);
+        // messages from before the anchor are not needed anymore
+        if cfg.validator.prune.prune_depth.is_some() {
+            let bvtree = validator.validator().bvtree();
+            transport.forget_seen_before(bvtree.get_block(bvtree.anchor()).unwrap().slot());
+        }
+        drop(validator);
+        transport.record_stats(r);
+    }
+
+    if let Some(path) = &cfg.metrics_out {
+        let stats = validator.lock().unwrap().stats();
+        let comms = transport.stats();
+        let mut wtr = csv::Writer::from_path(path)?;
+        for r in sim_horizon {
+            wtr.serialize(NodeStats {
+                r,
+                id,
+                status: stats[&r].0.status,
+                ledger: stats[&r].1,
+                comms: comms[&r],
+            })?;
+        }
+        wtr.flush()?;
+    }
+
+    Ok(())
+}
+
+fn signal(child: &Child, sig: &str) -> std::io::Result<()> {
+    let status = Command::new("kill")
+        .arg(format!("-{}", sig))
+        .arg(child.id().to_string())
+        .status()?;
+    if !status.success() {
+        log::warn!("kill -{} {} failed: {}", sig, child.id(), status);
+    }
+    Ok(())
+}
+
+pub fn run_testnet(
+    cfg: TestnetConfig,
+    scenario: crate::SimulationCommands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if cfg.validator.das.das {
+        return Err(NO_DAS.into());
+    }
+    let sim_horizon = cfg.validator.slot_schedule.horizon(cfg.t_horizon);
+
+    // SETUP
+
+    let (pki, sks) = crate::generate_keys(cfg.n);
+    let pki_path = keystore::write_dir(&cfg.dir, &pki, &sks, keystore::Format::Bincode, None)?;
+
+    let (_, da_schedules) = crate::instantiate_validators(
+        scenario,
+        &pki,
+        sks,
+        sim_horizon.clone(),
+        cfg.validator.clone(),
+        // only the schedules are needed here, the nodes open their own stores
+        store::StoreConfig::default(),
+    );
+
+    // LAUNCH
+
+    let addrs: Vec<SocketAddr> = (0..cfg.n)
+        .map(|id| SocketAddr::from(([127, 0, 0, 1], cfg.base_port + id as u16)))
+        .collect();
+    let genesis_time_ms =
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64 + cfg.startup_delay_ms;
+
+    let mut children = Vec::new();
+    for id in 0..(cfg.n as usize) {
+        let mut cmd = Command::new(std::env::current_exe()?);
+        if cfg.verbose > 0 {
+            cmd.arg(format!("-{}", "v".repeat(cfg.verbose as usize)));
+        }
+        cmd.arg("node")
+            .arg("--id")
+            .arg(id.to_string())
+            .arg("--pki")
+            .arg(&pki_path)
+            .arg("--keystore")
+            .arg(keystore::keystore_path(
+                &cfg.dir,
+                id as goldfish_type::Id,
+                keystore::Format::Bincode,
+            ))
+            .arg("--listen")
+            .arg(addrs[id].to_string())
+            .arg("--genesis-time-ms")
+            .arg(genesis_time_ms.to_string())
+            .arg("--round-duration-ms")
+            .arg(cfg.round_duration_ms.to_string())
+            .arg("--connect-timeout-ms")
+            .arg(cfg.startup_delay_ms.to_string())
+            .arg("--t-horizon")
+            .arg(cfg.t_horizon.to_string())
+            .arg("--probability-lottery-block")
+            .arg(cfg.probability_lottery_block.to_string())
+            .arg("--probability-lottery-vote")
+            .arg(cfg.probability_lottery_vote.to_string())
+            .args(cfg.workload.to_args())
+            .args(cfg.validator.to_args())
+            .args(cfg.store.to_args())
+            .arg("--metrics-out")
+            .arg(cfg.dir.join(format!("metrics-{}.csv", id)));
+        for (peer_id, addr) in addrs.iter().enumerate() {
+            if peer_id != id {
+                cmd.arg("--peer").arg(addr.to_string());
+            }
+        }
+        if let Some(rpc_base_port) = cfg.rpc_base_port {
+            cmd.arg("--rpc-listen")
+                .arg(SocketAddr::from(([127, 0, 0, 1], rpc_base_port + id as u16)).to_string());
+        }
+        if let Some(metrics_base_port) = cfg.metrics_base_port {
+            cmd.arg("--metrics-listen")
+                .arg(SocketAddr::from(([127, 0, 0, 1], metrics_base_port + id as u16)).to_string());
+        }
+        if cfg.participation_control == ParticipationControl::Sleep {
+            let schedule_path = cfg.dir.join(format!("schedule-{}.bin", id));
+            fs::write(&schedule_path, bincode::serialize(&da_schedules[id])?)?;
+            cmd.arg("--schedule").arg(schedule_path);
+        }
+        cmd.stdout(Stdio::null())
+            .stderr(fs::File::create(cfg.dir.join(format!("node-{}.log", id)))?);
+
+        log::info!("Launching node id={}: {:?}", id, cmd);
+        children.push(cmd.spawn()?);
+    }
+
+    // PARTICIPATION
+
+    if cfg.participation_control == ParticipationControl::Signal {
+        for r in sim_horizon.clone() {
+            sleep_until(round_start(
+                genesis_time_ms,
+                cfg.round_duration_ms,
+                &cfg.validator.slot_schedule,
+                r,
+            ));
+            for (id, child) in children.iter().enumerate() {
+                match (da_schedules[id][r - 1], da_schedules[id][r]) {
+                    (
+                        goldfish_validator::DaScheduleStatus::Awake,
+                        goldfish_validator::DaScheduleStatus::Asleep,
+                    ) => {
+                        log::info!("r={} id={} STOP", r, id);
+                        signal(child, "STOP")?;
+                    }
+                    (
+                        goldfish_validator::DaScheduleStatus::Asleep,
+                        goldfish_validator::DaScheduleStatus::Awake,
+                    ) => {
+                        log::info!("r={} id={} CONT", r, id);
+                        signal(child, "CONT")?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // let nodes that are still suspended run to completion
+        for (id, child) in children.iter().enumerate() {
+            if *da_schedules[id].last().unwrap() == goldfish_validator::DaScheduleStatus::Asleep {
+                signal(child, "CONT")?;
+            }
+        }
+    }
+
+    for (id, child) in children.iter_mut().enumerate() {
+        let status = child.wait()?;
+        if !status.success() {
+            log::error!("Node id={} exited with {}", id, status);
+        }
+    }
+
+    // METRICS
+    log::warn!("Metrics");
+
+    let mut header = None;
+    let mut rows = Vec::new();
+    for id in 0..(cfg.n as usize) {
+        let path = cfg.dir.join(format!("metrics-{}.csv", id));
+        let mut rdr = match csv::Reader::from_path(&path) {
+            Ok(rdr) => rdr,
+            Err(e) => {
+                log::error!("No metrics from node id={}: {}", id, e);
+                continue;
+            }
+        };
+        if header.is_none() {
+            header = Some(rdr.headers()?.clone());
+        }
+        for record in rdr.records() {
+            let record = record?;
+            let r: usize = record[0].parse()?;
+            rows.push((r, id, record));
+        }
+    }
+    rows.sort_by_key(|(r, id, _)| (*r, *id));
+
+    let mut wtr = csv::Writer::from_writer(std::io::stdout());
+    if let Some(header) = header {
+        let mut h = csv::StringRecord::from(vec!["scheduled"]);
+        h.extend(header.iter());
+        wtr.write_record(&h)?;
+    }
+    for (r, id, record) in rows {
+        let mut row = csv::StringRecord::from(vec![format!("{:?}", da_schedules[id][r])]);
+        row.extend(record.iter());
+        wtr.write_record(&row)?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
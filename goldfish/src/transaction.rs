@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::ghash;
+use crate::goldfish_type;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Transaction {
+    sender: u64,
+    nonce: u64,
+    gas: u64,
+    // round in which the transaction was submitted (for latency stats)
+    created: usize,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+}
+
+impl Transaction {
+    pub fn new(sender: u64, nonce: u64, gas: u64, created: usize, data: Vec<u8>) -> Self {
+        Self {
+            sender,
+            nonce,
+            gas,
+            created,
+            data,
+        }
+    }
+
+    pub fn digest(&self) -> goldfish_type::Hash {
+        let mut hasher = ghash::Ghasher::new();
+        self.digest_hasher_update(&mut hasher);
+        hasher.into()
+    }
+
+    pub fn digest_hasher_update(&self, hasher: &mut ghash::Ghasher) {
+        hasher.update(b"tx");
+        hasher.update(&self.sender.to_ne_bytes());
+        hasher.update(&self.nonce.to_ne_bytes());
+        hasher.update(&self.gas.to_ne_bytes());
+        hasher.update(&self.created.to_ne_bytes());
+        hasher.update(&self.data);
+    }
+
+    pub fn size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize
+    }
+
+    pub fn sender(&self) -> u64 {
+        self.sender
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn gas(&self) -> u64 {
+        self.gas
+    }
+
+    pub fn created(&self) -> usize {
+        self.created
+    }
+
+    // (recipient, amount) from the first 16 bytes of the payload, if any
+    pub fn transfer(&self) -> Option<(u64, u64)> {
+        if self.data.len() < 16 {
+            return None;
+        }
+        Some((
+            u64::from_le_bytes(self.data[..8].try_into().unwrap()),
+            u64::from_le_bytes(self.data[8..16].try_into().unwrap()),
+        ))
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct WorkloadConfig {
+    /// Transactions submitted per round
+    #[arg(long, default_value_t = 80)]
+    pub tx_per_round: usize,
+
+    /// Transaction payload size (bytes)
+    #[arg(long, default_value_t = 210)]
+    pub tx_size: usize,
+
+    /// Gas used per transaction
+    #[arg(long, default_value_t = 21_000)]
+    pub tx_gas: u64,
+
+    /// Number of distinct transaction senders
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 100)]
+    pub tx_senders: u64,
+}
+
+impl WorkloadConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        vec![
+            "--tx-per-round".to_string(),
+            self.tx_per_round.to_string(),
+            "--tx-size".to_string(),
+            self.tx_size.to_string(),
+            "--tx-gas".to_string(),
+            self.tx_gas.to_string(),
+            "--tx-senders".to_string(),
+            self.tx_senders.to_string(),
+        ]
+    }
+}
+
+// Transactions are assumed to reach all mempools instantly, so every validator
+// (and every node of a testnet) generates the same transactions for a round.
+#[derive(Debug, Clone)]
+pub struct Workload {
+    config: WorkloadConfig,
+}
+
+impl Workload {
+    pub fn new(config: WorkloadConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn generate(&self, r: usize) -> Vec<Arc<Transaction>> {
+        (0..self.config.tx_per_round)
+            .map(|i| {
+                let k = (r * self.config.tx_per_round + i) as u64;
+                let sender = k % self.config.tx_senders;
+                // transfer 1 unit to the next sender, if the payload has room for it
+                let mut data = vec![0; self.config.tx_size];
+                if data.len() >= 16 {
+                    let recipient = (sender + 1) % self.config.tx_senders;
+                    data[..8].copy_from_slice(&recipient.to_le_bytes());
+                    data[8..16].copy_from_slice(&1u64.to_le_bytes());
+                }
+                Arc::new(Transaction::new(
+                    sender,
+                    k / self.config.tx_senders,
+                    self.config.tx_gas,
+                    r,
+                    data,
+                ))
+            })
+            .collect()
+    }
+}
@@ -10,8 +10,14 @@ pub trait Scheme {
     fn gen(&mut self) -> (Self::Sk, Self::Pk);
     fn eval(sk: &Self::Sk, x: &[u8]) -> (u64, Self::Pf);
     fn verify(pk: &Self::Pk, x: &[u8], y: u64, pf: &Self::Pf) -> bool;
+
+    fn sk_to_bytes(sk: &Self::Sk) -> Vec<u8>;
+    fn sk_from_bytes(bytes: &[u8]) -> Option<Self::Sk>;
+    fn pk_to_bytes(pk: &Self::Pk) -> Vec<u8>;
+    fn pk_from_bytes(bytes: &[u8]) -> Option<Self::Pk>;
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct MockScheme {
     last_id: u64,
 }
@@ -45,6 +51,32 @@ impl Scheme for MockScheme {
         let y_ = y_.extract_first_u64();
         y == y_ && pf.0 == y_ && pf.1 == *pk
     }
+
+    fn sk_to_bytes(sk: &Self::Sk) -> Vec<u8> {
+        sk.to_le_bytes().to_vec()
+    }
+
+    fn sk_from_bytes(bytes: &[u8]) -> Option<Self::Sk> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn pk_to_bytes(pk: &Self::Pk) -> Vec<u8> {
+        pk.to_le_bytes().to_vec()
+    }
+
+    fn pk_from_bytes(bytes: &[u8]) -> Option<Self::Pk> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+// proofs of the mock scheme
+impl sig::HashingBytes for (u64, u64) {
+    fn as_bytes_for_hashing(&self) -> [u8; 96] {
+        let mut bytes = [0; 96];
+        bytes[..8].copy_from_slice(&self.0.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.1.to_le_bytes());
+        bytes
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -75,4 +107,20 @@ impl Scheme for MilagroBlsVrfScheme {
         let y_ = hash.extract_first_u64();
         y == y_ && sig::MilagroBlsScheme::verify(pk, x, pf)
     }
+
+    fn sk_to_bytes(sk: &Self::Sk) -> Vec<u8> {
+        sig::MilagroBlsScheme::sk_to_bytes(sk)
+    }
+
+    fn sk_from_bytes(bytes: &[u8]) -> Option<Self::Sk> {
+        sig::MilagroBlsScheme::sk_from_bytes(bytes)
+    }
+
+    fn pk_to_bytes(pk: &Self::Pk) -> Vec<u8> {
+        sig::MilagroBlsScheme::pk_to_bytes(pk)
+    }
+
+    fn pk_from_bytes(bytes: &[u8]) -> Option<Self::Pk> {
+        sig::MilagroBlsScheme::pk_from_bytes(bytes)
+    }
 }